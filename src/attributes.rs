@@ -0,0 +1,449 @@
+//! `.gitattributes` support.
+//!
+//! - A git-se managed block inside the repo's `.gitattributes`. Only the lines
+//!   between the `BLOCK_BEGIN` and `BLOCK_END` markers are touched; anything
+//!   the user wrote outside the block is preserved verbatim.
+//! - The `crypt` and `crypt-zstd` attributes, which select files for
+//!   encryption next to `crypt_list`, resolved with `git check-attr`.
+
+use std::{collections::HashMap, path::Path, process::Command};
+
+use log::{debug, info, warn};
+
+use crate::{
+    config::{EncryptSettings, is_glob, literal_path},
+    error::Result,
+    git::run_with_input,
+    repo::Repo,
+    utils::atomic_write,
+};
+
+/// Name of the attributes file at the repo root.
+pub const ATTRIBUTES_FILE: &str = ".gitattributes";
+/// Name of the custom diff/merge drivers registered in git config.
+pub const DRIVER_NAME: &str = "git-se";
+
+/// Attribute that selects a file for encryption, e.g. `secrets/** crypt`.
+/// `-crypt` (or `crypt=false`) keeps a file in plaintext even if `crypt_list`
+/// covers it.
+pub const CRYPT_ATTR: &str = "crypt";
+/// Attribute that overrides the compression of a file: `crypt-zstd=19` sets
+/// the level, `-crypt-zstd` stores it uncompressed.
+pub const CRYPT_ZSTD_ATTR: &str = "crypt-zstd";
+
+const BLOCK_BEGIN: &str = "# >>> git-se managed, do not edit >>>";
+const BLOCK_END: &str = "# <<< git-se managed <<<";
+
+/// Convert a `crypt_list` entry into a `.gitattributes` pattern.
+///
+/// Literal entries are anchored and directories are expanded to `dir/**` so
+/// the attributes apply to every file inside. Globs are used as-is, since
+/// `.gitattributes` matches them like `.gitignore` does. Patterns containing
+/// whitespace are quoted.
+#[must_use]
+pub fn entry_to_pattern(entry: &str, repo_path: &Path) -> String {
+    let entry = entry.trim_start_matches("./").trim_end_matches('/');
+    let pattern = if is_glob(entry) {
+        entry.to_owned()
+    } else if repo_path.join(literal_path(entry)).is_dir() {
+        format!("/{entry}/**")
+    } else {
+        format!("/{entry}")
+    };
+    if pattern.contains(char::is_whitespace) {
+        format!("\"{}\"", pattern.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        pattern
+    }
+}
+
+/// The attribute lines git-se wants for the current config, one per
+/// `crypt_list` and `exclude` entry.
+///
+/// Listed paths are marked `-text`, so git never converts line endings inside
+/// ciphertext, and get the custom diff and merge drivers if they are enabled,
+/// `-diff -merge` otherwise. `.gitattributes` has no negated patterns, so
+/// negations and excludes reset these attributes (`!text`) on the paths they
+/// match instead.
+#[must_use]
+pub fn managed_lines(repo: &Repo) -> Vec<String> {
+    let managed = [
+        ("text", None),
+        ("diff", repo.conf.diff_driver.then_some(DRIVER_NAME)),
+        ("merge", repo.conf.merge_driver.then_some(DRIVER_NAME)),
+    ];
+    let attrs = managed
+        .iter()
+        .map(|(attr, driver)| driver.map_or_else(|| format!("-{attr}"), |d| format!("{attr}={d}")))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let unset = managed
+        .iter()
+        .map(|(attr, _)| format!("!{attr}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let excluded = repo.conf.exclude.iter().map(|e| format!("!{e}"));
+    repo.conf
+        .crypt_list
+        .iter()
+        .cloned()
+        .chain(excluded)
+        .map(|entry| {
+            let (pattern, attrs) = entry
+                .strip_prefix('!')
+                .map_or((entry.as_str(), &attrs), |negated| (negated, &unset));
+            format!("{} {attrs}", entry_to_pattern(pattern, repo.path()))
+        })
+        .collect()
+}
+
+/// Rewrite the managed block in `<repo>/.gitattributes` from the config.
+///
+/// The block is removed entirely when there is nothing to manage, and the file
+/// is left untouched if its content would not change.
+pub fn sync(repo: &Repo) -> Result<()> {
+    let path = repo.path().join(ATTRIBUTES_FILE);
+    let old = if path.exists() {
+        std::fs::read_to_string(&path)?
+    } else {
+        String::new()
+    };
+    let new = replace_block(&old, &managed_lines(repo));
+    if new == old {
+        debug!("{ATTRIBUTES_FILE} already up to date");
+        return Ok(());
+    }
+    atomic_write(&path, new.as_bytes())?;
+    info!("Updated {}", path.display());
+    Ok(())
+}
+
+/// The git-se attributes of one file. `None` fields are unspecified.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CryptAttrs {
+    /// `crypt`: whether the file is encrypted.
+    pub crypt: Option<bool>,
+    /// `crypt-zstd`: whether the file is compressed.
+    pub use_zstd: Option<bool>,
+    /// `crypt-zstd=<level>`: the zstd level.
+    pub zstd_level: Option<u8>,
+}
+
+impl CryptAttrs {
+    /// Whether the repo-relative `path` is encrypted, given whether
+    /// `crypt_list` covers it. A set or unset `crypt` attribute wins over the
+    /// list, except that it never selects an attributes file, which git has
+    /// to be able to read.
+    #[must_use]
+    pub fn selects(&self, path: &str, listed: bool) -> bool {
+        if path.rsplit('/').next() == Some(ATTRIBUTES_FILE) {
+            return listed;
+        }
+        self.crypt.unwrap_or(listed)
+    }
+
+    /// `settings` with the attribute overrides applied, which win over the
+    /// config's rules.
+    #[must_use]
+    pub fn apply(&self, mut settings: EncryptSettings) -> EncryptSettings {
+        settings.use_zstd = self.use_zstd.unwrap_or(settings.use_zstd);
+        settings.zstd_level = self.zstd_level.unwrap_or(settings.zstd_level);
+        settings
+    }
+
+    fn set(&mut self, path: &str, attr: &[u8], value: &[u8]) {
+        let value = String::from_utf8_lossy(value);
+        let enabled = match value.as_ref() {
+            "unspecified" => return,
+            "unset" | "false" => false,
+            _ => true,
+        };
+        match attr {
+            b"crypt" => self.crypt = Some(enabled),
+            b"crypt-zstd" => {
+                self.use_zstd = Some(enabled);
+                if !enabled || value == "set" || value == "true" {
+                    return;
+                }
+                match value.parse() {
+                    Ok(level @ 1..=22) => self.zstd_level = Some(level),
+                    _ => warn!(
+                        "{path}: ignoring {CRYPT_ZSTD_ATTR}={value}, the level should be 1-22"
+                    ),
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Resolve the git-se attributes of the repo-relative, `/`-separated `paths`
+/// with `git check-attr`, in the order of `paths`. With `cached`, only the
+/// attributes files in the index are read, as for staged content.
+///
+/// Outside a git repo there are no attributes, and every file gets the
+/// default.
+pub fn check_crypt_attrs(
+    repo_path: &Path,
+    paths: &[String],
+    cached: bool,
+) -> Result<Vec<CryptAttrs>> {
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut command = Command::new("git");
+    command
+        .current_dir(repo_path)
+        .args(["check-attr", "--stdin", "-z"]);
+    if cached {
+        command.arg("--cached");
+    }
+    command.args([CRYPT_ATTR, CRYPT_ZSTD_ATTR]);
+    let input = paths.iter().fold(Vec::new(), |mut input, p| {
+        input.extend_from_slice(p.as_bytes());
+        input.push(0);
+        input
+    });
+    let output = match run_with_input(command, &input) {
+        Ok(output) => output,
+        Err(e) => {
+            debug!("git check-attr failed, ignoring attributes: {e}");
+            return Ok(vec![CryptAttrs::default(); paths.len()]);
+        }
+    };
+
+    // `<path> NUL <attribute> NUL <value> NUL` for every path and attribute.
+    let mut attrs: HashMap<&[u8], CryptAttrs> = HashMap::new();
+    let fields = output.split(|&b| b == 0).collect::<Vec<_>>();
+    for record in fields.chunks_exact(3) {
+        let path = String::from_utf8_lossy(record[0]);
+        attrs
+            .entry(record[0])
+            .or_default()
+            .set(&path, record[1], record[2]);
+    }
+    Ok(paths
+        .iter()
+        .map(|p| attrs.get(p.as_bytes()).copied().unwrap_or_default())
+        .collect())
+}
+
+/// Whether an attributes file of the repo at `repo_path` mentions the `crypt`
+/// attribute: a `.gitattributes` anywhere in the working tree,
+/// `info/attributes` in the git dir or `core.attributesFile`.
+///
+/// Without one, only `crypt_list` can select files, so they are enumerated
+/// below its roots alone. Matching is textual, and a false positive only
+/// costs a pass over the whole tree. Outside a git repo this is `false`.
+#[must_use]
+pub fn crypt_attr_used(repo_path: &Path) -> bool {
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .current_dir(repo_path)
+            .args(args)
+            .output()
+            .ok()
+            .filter(|o| o.status.success())
+            .map(|o| o.stdout)
+    };
+    let Some(tree_files) = git(&[
+        "ls-files",
+        "-z",
+        "--cached",
+        "--others",
+        "--exclude-standard",
+        "--",
+        ":(glob)**/.gitattributes",
+    ]) else {
+        return false;
+    };
+    let mut files = tree_files
+        .split(|&b| b == 0)
+        .filter(|p| !p.is_empty())
+        .map(|p| repo_path.join(String::from_utf8_lossy(p).as_ref()))
+        .collect::<Vec<_>>();
+    for args in [
+        &["rev-parse", "--git-path", "info/attributes"][..],
+        &["config", "--path", "core.attributesFile"],
+    ] {
+        if let Some(out) = git(args) {
+            let path = String::from_utf8_lossy(&out).trim().to_owned();
+            if !path.is_empty() {
+                files.push(repo_path.join(path));
+            }
+        }
+    }
+    files.iter().any(|f| {
+        std::fs::read_to_string(f).is_ok_and(|content| {
+            content
+                .lines()
+                .filter(|l| !l.trim_start().starts_with('#'))
+                .flat_map(|l| l.split_whitespace().skip(1))
+                .any(|attr| {
+                    attr.trim_start_matches(['-', '!']).split('=').next() == Some(CRYPT_ATTR)
+                })
+        })
+    })
+}
+
+/// Replace (or insert, or remove) the managed block in `content`.
+fn replace_block(content: &str, lines: &[String]) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut in_block = false;
+    let mut inserted = false;
+
+    let push_block = |out: &mut String| {
+        if lines.is_empty() {
+            return;
+        }
+        out.push_str(BLOCK_BEGIN);
+        out.push('\n');
+        for line in lines {
+            out.push_str(line);
+            out.push('\n');
+        }
+        out.push_str(BLOCK_END);
+        out.push('\n');
+    };
+
+    for line in content.lines() {
+        if line.trim_end() == BLOCK_BEGIN {
+            in_block = true;
+            continue;
+        }
+        if in_block {
+            if line.trim_end() == BLOCK_END {
+                in_block = false;
+                push_block(&mut out);
+                inserted = true;
+            }
+            continue;
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+
+    if !inserted {
+        push_block(&mut out);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_block_roundtrip() {
+        let user = "*.png binary\n";
+        let lines = vec!["/a.txt diff=git-se".to_owned()];
+
+        let with_block = replace_block(user, &lines);
+        assert!(with_block.starts_with(user));
+        assert!(with_block.contains("/a.txt diff=git-se\n"));
+
+        // Replacing again is idempotent.
+        assert_eq!(replace_block(&with_block, &lines), with_block);
+
+        // An empty block is removed, leaving the user's lines intact.
+        assert_eq!(replace_block(&with_block, &[]), user);
+    }
+
+    #[test]
+    fn test_check_crypt_attrs_many_paths() {
+        // More output than a pipe buffer holds, which `git check-attr`
+        // writes while it is still reading the paths.
+        let dir = tempfile::TempDir::new().unwrap();
+        Command::new("git")
+            .arg("init")
+            .current_dir(dir.path())
+            .output()
+            .unwrap();
+        std::fs::write(dir.path().join(ATTRIBUTES_FILE), "*.key crypt\n").unwrap();
+        let paths = (0..20_000)
+            .map(|i| format!("dir/file-{i}.key"))
+            .collect::<Vec<_>>();
+        let attrs = check_crypt_attrs(dir.path(), &paths, false).unwrap();
+        assert_eq!(attrs.len(), paths.len());
+        assert!(attrs.iter().all(|a| a.crypt == Some(true)));
+    }
+
+    #[test]
+    fn test_crypt_attr_used() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path();
+        assert!(!crypt_attr_used(root));
+        Command::new("git")
+            .arg("init")
+            .current_dir(root)
+            .output()
+            .unwrap();
+        std::fs::write(root.join(ATTRIBUTES_FILE), "*.png binary\n# a.txt crypt\n").unwrap();
+        assert!(!crypt_attr_used(root));
+        std::fs::create_dir(root.join("sub")).unwrap();
+        std::fs::write(
+            root.join("sub").join(ATTRIBUTES_FILE),
+            "*.key -crypt-zstd\n",
+        )
+        .unwrap();
+        assert!(!crypt_attr_used(root));
+        std::fs::write(root.join("sub").join(ATTRIBUTES_FILE), "*.key crypt\n").unwrap();
+        assert!(crypt_attr_used(root));
+        std::fs::remove_file(root.join("sub").join(ATTRIBUTES_FILE)).unwrap();
+        std::fs::write(root.join(".git/info/attributes"), "secrets/** !crypt\n").unwrap();
+        assert!(crypt_attr_used(root));
+    }
+
+    #[test]
+    fn test_entry_to_pattern() {
+        let dir = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir(dir.path().join("secrets")).unwrap();
+        assert_eq!(entry_to_pattern("secrets", dir.path()), "/secrets/**");
+        assert_eq!(entry_to_pattern("a.env", dir.path()), "/a.env");
+        assert_eq!(entry_to_pattern("my file", dir.path()), "\"/my file\"");
+        assert_eq!(entry_to_pattern("*.env", dir.path()), "*.env");
+        assert_eq!(
+            entry_to_pattern("./config/*.key", dir.path()),
+            "config/*.key"
+        );
+    }
+
+    #[test]
+    fn test_check_crypt_attrs() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        Command::new("git")
+            .arg("init")
+            .current_dir(dir.path())
+            .output()?;
+        std::fs::write(
+            dir.path().join(ATTRIBUTES_FILE),
+            "secrets/** crypt\nsecrets/public.txt -crypt\n*.sql crypt-zstd=19\n*.png -crypt-zstd\n",
+        )?;
+        let paths = ["secrets/a.sql", "secrets/public.txt", "b.png", "c.txt"].map(String::from);
+        let attrs = check_crypt_attrs(dir.path(), &paths, false)?;
+        assert_eq!(
+            attrs,
+            [
+                CryptAttrs {
+                    crypt: Some(true),
+                    use_zstd: Some(true),
+                    zstd_level: Some(19),
+                },
+                CryptAttrs {
+                    crypt: Some(false),
+                    ..CryptAttrs::default()
+                },
+                CryptAttrs {
+                    use_zstd: Some(false),
+                    ..CryptAttrs::default()
+                },
+                CryptAttrs::default(),
+            ]
+        );
+        assert!(attrs[0].selects(&paths[0], false));
+        assert!(!attrs[1].selects(&paths[1], true));
+        assert!(attrs[3].selects(&paths[3], true));
+        assert!(!attrs[0].selects("secrets/.gitattributes", false));
+        Ok(())
+    }
+}
@@ -1,33 +1,109 @@
-use std::path::{Path, PathBuf};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
 
-use clap::{Parser, Subcommand};
+use clap::{
+    CommandFactory, FromArgMatches, Parser, Subcommand, error::ErrorKind, parser::ValueSource,
+};
 use config_file2::Storable;
 use log::{debug, info, warn};
 
 use crate::{
+    config::FileSource,
     error::{Error, Result},
+    hooks::Hook,
+    output::OutputFormat,
     repo::Repo,
 };
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None, after_help = r#"Examples:
 git-se p                    # Set/update master password
+echo $KEY | git-se p --stdin  # Set the password without a terminal
 git-se add file.txt  mydir  # Add files/folders to the encryption list
+git-se add --dry-run mydir  # Preview the files an add would encrypt
+git-se rm --decrypt mydir   # Decrypt and remove files/folders from the list
 git-se e                    # Encrypt all files in the list
 git-se d                    # Decrypt all files in the list
 git-se e xxx.txt dir1 ...   # Encrypt specific files
 git-se d xxx.txt dir1 ...   # Decrypt specific files
+git-se e --recurse-submodules  # Also encrypt in every submodule
+git-se stage                # Stage encrypted files, keeping plaintext in the working tree
 git-se i                    # Install a pre-commit hook to check encryption before committing
+git-se i --all-hooks        # Also check pushes and decrypt after checkout/merge
+git-se attributes sync      # Mark listed files `-text -diff -merge` in .gitattributes
+git-se st                   # Show the encryption state of every file in the list
+git-se --format json check  # Report results as JSON (or `ndjson`) for scripts
+git-se i --diff             # Show plaintext in `git diff` / `git log -p`
+git-se i --merge            # Merge encrypted files on their plaintext
+git-se cat a.env            # Print the plaintext of a file
+git-se show HEAD~1:a.env    # Print the plaintext of a file at any revision
+git-se verify-history       # Check every encrypted file in history decrypts
+git-se leaks                # Find secrets committed in plaintext
+git-se purge-history        # Rewrite history with those secrets encrypted
+git-se pre-receive          # Server-side hook rejecting pushed plaintext
 "#)]
-#[clap(args_conflicts_with_subcommands = true)]
 pub struct Cli {
     /// Encrypt, Decrypt and Add
     #[command(subcommand)]
     pub command: SubCommand,
-    /// Repository path, allow both relative and absolute path.
+    /// Run as if git-se was started in this directory, like `git -C`. The
+    /// repository root is found from it; relative and absolute paths are
+    /// allowed.
     #[arg(short, long, global = true)]
     #[clap(value_parser = repo_path_parser, default_value = ".")]
     pub repo: PathBuf,
+    /// How to report results. `json` prints one document and `ndjson` one
+    /// object per line to stdout, with human progress on stderr. Ignored by
+    /// the commands that print file contents (`cat`, `show`, `textconv`).
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Human)]
+    pub format: OutputFormat,
+    /// Print debug output, such as the compression used for each file; the
+    /// same as `RUST_LOG=debug`.
+    #[arg(short, long, global = true, default_value_t = false)]
+    pub verbose: bool,
+}
+
+impl Cli {
+    /// Parse `args` like [`Parser::try_parse_from`], also rejecting
+    /// `status --porcelain` together with `--format`.
+    ///
+    /// # Errors
+    ///
+    /// The clap error for invalid arguments, to be reported with
+    /// [`clap::Error::exit`].
+    pub fn try_parse_args_from<I, T>(args: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        let mut command = Self::command();
+        let matches = command.try_get_matches_from_mut(args)?;
+        // Not a `conflicts_with` on `porcelain`: clap checks conflicts per
+        // subcommand, so it misses a global `--format` given before `status`.
+        let porcelain = matches
+            .subcommand_matches("status")
+            .is_some_and(|m| m.get_flag("porcelain"));
+        if porcelain && matches.value_source("format") == Some(ValueSource::CommandLine) {
+            return Err(command.error(
+                ErrorKind::ArgumentConflict,
+                "the argument '--porcelain' cannot be used with '--format <FORMAT>'",
+            ));
+        }
+        Self::from_arg_matches(&matches)
+    }
+
+    /// The format to report results in, see [`Cli::format`].
+    #[must_use]
+    pub const fn output_format(&self) -> OutputFormat {
+        match self.command {
+            SubCommand::Cat { .. } | SubCommand::Show { .. } | SubCommand::Textconv { .. } => {
+                OutputFormat::Human
+            }
+            _ => self.format,
+        }
+    }
 }
 
 fn repo_path_parser(path: &str) -> Result<PathBuf, String> {
@@ -44,15 +120,49 @@ pub enum SubCommand {
     Encrypt {
         /// The files or folders to be encrypted.
         paths: Vec<PathBuf>,
+        /// Also encrypt in every initialised submodule, recursively, with its
+        /// own config and key.
+        #[arg(long, default_value_t = false)]
+        recurse_submodules: bool,
     },
     /// Decrypt all files with crypt attr and `.enc` extension.
     #[clap(alias("d"))]
     Decrypt {
         /// The files or folders to be decrypted.
         paths: Vec<PathBuf>,
+        /// Also decrypt in every initialised submodule, recursively, with its
+        /// own config and key.
+        #[arg(long, default_value_t = false)]
+        recurse_submodules: bool,
+    },
+    /// Encrypt files into the index without touching the working tree.
+    Stage {
+        /// The files or folders to stage. If empty, stages every file in the
+        /// crypt list.
+        paths: Vec<PathBuf>,
+        /// Encrypt the already staged content of staged files instead (used
+        /// by the pre-commit-stage hook).
+        #[arg(long, default_value_t = false, conflicts_with = "paths")]
+        staged: bool,
     },
     /// Mark files or folders as need-to-be-crypted.
-    Add { paths: Vec<PathBuf> },
+    Add {
+        paths: Vec<PathBuf>,
+        /// Print the files the new entries would put under encryption,
+        /// without changing the list.
+        #[arg(long, default_value_t = false)]
+        dry_run: bool,
+    },
+    /// Remove files, folders or patterns from the crypt list.
+    #[clap(alias("remove"))]
+    Rm {
+        /// Entries to remove, as any path form or the pattern as listed.
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Decrypt the files that are no longer covered first.
+        #[arg(long, default_value_t = false)]
+        decrypt: bool,
+    },
     /// Set key or other config items.
     Set {
         #[clap(subcommand)]
@@ -60,7 +170,12 @@ pub enum SubCommand {
     },
     /// Set password interactively.
     #[clap(alias("p"))]
-    Pwd,
+    Pwd {
+        /// Read the password from the first line of stdin instead of
+        /// prompting on the terminal.
+        #[arg(long, default_value_t = false)]
+        stdin: bool,
+    },
     /// Check if all files in the crypt list are encrypted.
     #[clap(alias("c"))]
     Check {
@@ -70,10 +185,137 @@ pub enum SubCommand {
         /// Only check files staged for commit (used by pre-commit hook).
         #[arg(long, default_value_t = false)]
         staged: bool,
+        /// Also check in every initialised submodule, recursively, with its
+        /// own config and key.
+        #[arg(long, default_value_t = false)]
+        recurse_submodules: bool,
     },
-    /// Install a pre-commit hook to check encryption before committing.
+    /// Install git hooks (by default the pre-commit hook) or drivers. Existing
+    /// hook scripts are kept and chained.
     #[clap(alias("i"))]
-    Install,
+    Install {
+        /// Hooks to install; repeatable. Defaults to `pre-commit` unless a
+        /// driver is installed.
+        #[arg(long = "hook", value_enum)]
+        hooks: Vec<Hook>,
+        /// Install every hook.
+        #[arg(long, default_value_t = false, conflicts_with = "hooks")]
+        all_hooks: bool,
+        /// Install the textconv diff driver, so `git diff`, `git log -p` and
+        /// `git show` display plaintext locally.
+        #[arg(long, default_value_t = false)]
+        diff: bool,
+        /// Install the three-way merge driver, so git merges encrypted files
+        /// on their plaintext.
+        #[arg(long, default_value_t = false)]
+        merge: bool,
+    },
+    /// Remove the git-se part of every hook, keeping the rest of the scripts.
+    Uninstall,
+    /// Manage the git-se block in `.gitattributes`.
+    Attributes {
+        #[clap(subcommand)]
+        action: AttributesAction,
+    },
+    /// Show the encryption state of every file in the crypt list.
+    #[clap(alias("st"))]
+    Status {
+        /// The files or folders to inspect. If empty, inspects the whole
+        /// crypt list and also reports orphaned files and unmatched entries.
+        paths: Vec<PathBuf>,
+        /// Stable, uncoloured `<code> <path>` output for scripts. A format
+        /// of its own, so it cannot be combined with `--format`.
+        #[arg(long, default_value_t = false)]
+        porcelain: bool,
+        /// Also inspect every initialised submodule, recursively, with its
+        /// own config and key.
+        #[arg(long, default_value_t = false)]
+        recurse_submodules: bool,
+    },
+    /// Check that every encrypted file in history decrypts with the current
+    /// key.
+    VerifyHistory {
+        /// Revisions to walk, as accepted by `git rev-list` (e.g.
+        /// `v1.0..main`). Defaults to all refs.
+        revs: Vec<String>,
+    },
+    /// Scan the history of all refs for secrets committed in plaintext.
+    Leaks,
+    /// Rewrite history so that plaintext files in the crypt list are
+    /// replaced by their encrypted form. Never pushes.
+    PurgeHistory {
+        /// Branches or tags to rewrite. Defaults to all branches and tags.
+        refs: Vec<String>,
+    },
+    /// Server-side `pre-receive` hook: reject pushes that contain files of
+    /// the committed crypt list in plaintext. Reads ref updates from stdin.
+    PreReceive,
+    /// Client-side `pre-push` hook: refuse to push commits that contain files
+    /// of the crypt list in plaintext. Reads ref updates from stdin.
+    PrePush {
+        /// Name of the remote being pushed to.
+        remote: String,
+        /// URL of the remote (unused, passed by git).
+        url: Option<String>,
+    },
+    /// Print the plaintext of a working tree file without modifying it.
+    Cat { path: PathBuf },
+    /// Print the plaintext of a blob in history, e.g. `HEAD~2:secret.env`.
+    Show {
+        /// Any `<rev>:<path>` (or blob id) that `git cat-file blob` accepts.
+        object: String,
+    },
+    /// Decrypt a file to stdout (the `git diff` textconv driver).
+    Textconv { file: PathBuf },
+    /// Three-way merge of encrypted files (the git merge driver, called as
+    /// `git-se merge %O %A %B %P`).
+    Merge {
+        /// Common ancestor version (`%O`).
+        base: PathBuf,
+        /// Our version (`%A`); receives the merge result.
+        ours: PathBuf,
+        /// Their version (`%B`).
+        theirs: PathBuf,
+        /// Path of the file in the repo (`%P`), used in messages.
+        path: String,
+    },
+}
+
+impl SubCommand {
+    /// The full name of the command, as reported in machine-readable output.
+    #[must_use]
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Encrypt { .. } => "encrypt",
+            Self::Decrypt { .. } => "decrypt",
+            Self::Stage { .. } => "stage",
+            Self::Add { .. } => "add",
+            Self::Rm { .. } => "rm",
+            Self::Set { .. } => "set",
+            Self::Pwd { .. } => "pwd",
+            Self::Check { .. } => "check",
+            Self::Install { .. } => "install",
+            Self::Uninstall => "uninstall",
+            Self::Attributes { .. } => "attributes",
+            Self::Status { .. } => "status",
+            Self::VerifyHistory { .. } => "verify-history",
+            Self::Leaks => "leaks",
+            Self::PurgeHistory { .. } => "purge-history",
+            Self::PreReceive => "pre-receive",
+            Self::PrePush { .. } => "pre-push",
+            Self::Cat { .. } => "cat",
+            Self::Show { .. } => "show",
+            Self::Textconv { .. } => "textconv",
+            Self::Merge { .. } => "merge",
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum AttributesAction {
+    /// Rewrite the managed block from the crypt list, marking the listed
+    /// paths `-text -diff -merge` (or with the installed drivers).
+    Sync,
 }
 
 #[derive(Debug, Subcommand)]
@@ -90,6 +332,8 @@ pub enum SetField {
         #[clap(value_parser = validate_bool)]
         value: bool,
     },
+    /// Set where the files to encrypt are enumerated from
+    FileSource { value: FileSource },
 }
 
 impl SetField {
@@ -103,6 +347,7 @@ impl SetField {
         match self {
             Self::Key { value } => {
                 warn!("`set key` is deprecated, please use `pwd` or `p` instead.");
+                repo.conf.password_policy.check(value)?;
                 repo.set_config("key", value)?;
                 info!("Master key updated.");
             }
@@ -114,6 +359,10 @@ impl SetField {
                 repo.conf.zstd_level = *value;
                 info!("zstd compression level set to {value}");
             }
+            Self::FileSource { value } => {
+                repo.conf.file_source = *value;
+                info!("file source set to {value:?}");
+            }
         }
         debug!("store config to {}", repo.conf.config_path.display());
         repo.conf
@@ -154,4 +403,28 @@ mod tests {
         let parsed = repo_path_parser(".").unwrap();
         assert!(parsed.is_absolute());
     }
+
+    #[test]
+    fn recurse_submodules_only_on_recursive_commands() {
+        for command in ["e", "d", "check", "st"] {
+            assert!(Cli::try_parse_from(["git-se", command, "--recurse-submodules"]).is_ok());
+        }
+        for command in ["stage", "uninstall", "leaks"] {
+            assert!(Cli::try_parse_from(["git-se", command, "--recurse-submodules"]).is_err());
+        }
+        assert!(Cli::try_parse_from(["git-se", "--recurse-submodules", "e"]).is_err());
+    }
+
+    #[test]
+    fn porcelain_conflicts_with_format() {
+        assert!(Cli::try_parse_args_from(["git-se", "st", "--porcelain"]).is_ok());
+        assert!(
+            Cli::try_parse_args_from(["git-se", "--format", "json", "st", "--porcelain"]).is_err()
+        );
+        assert!(
+            Cli::try_parse_args_from(["git-se", "st", "--porcelain", "--format", "ndjson"])
+                .is_err()
+        );
+        assert!(Cli::try_parse_args_from(["git-se", "--json", "st"]).is_err());
+    }
 }
@@ -2,14 +2,15 @@ use std::path::{Path, PathBuf};
 
 use config_file2::Storable;
 use fuck_backslash::FuckBackslash;
-use log::{debug, info};
+use ignore::overrides::{Override, OverrideBuilder};
+use log::{debug, info, warn};
 use path_absolutize::Absolutize as _;
 use pathdiff::diff_paths;
 use serde::{Deserialize, Serialize};
 
 use crate::{
     error::{Error, Result},
-    utils::style::Colorize,
+    utils::{list_files, list_ignored_files, list_matching_files, style::Colorize},
 };
 
 pub const CONFIG_FILE_NAME: &str = concat!(env!("CARGO_CRATE_NAME"), ".toml");
@@ -28,8 +29,28 @@ pub struct Config {
     pub use_zstd: bool,
     /// zstd compression level (1-22).
     pub zstd_level: u8,
-    /// list of files (patterns) to encrypt
+    /// list of files (patterns) to encrypt, see [`CryptMatcher`]
     pub crypt_list: Vec<String>,
+    /// patterns that are never encrypted even if `crypt_list` covers them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// whether crypt list paths are tagged `diff=git-se` in `.gitattributes`,
+    /// so `git diff` shows plaintext through `git-se textconv`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub diff_driver: bool,
+    /// whether crypt list paths are tagged `merge=git-se` in
+    /// `.gitattributes`, so git merges them through `git-se merge`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub merge_driver: bool,
+    /// where the files to encrypt are enumerated from, see [`FileSource`]
+    #[serde(default, skip_serializing_if = "FileSource::is_default")]
+    pub file_source: FileSource,
+    /// requirements for new passwords, see [`PasswordPolicy`]
+    #[serde(default, skip_serializing_if = "PasswordPolicy::is_default")]
+    pub password_policy: PasswordPolicy,
+    /// per-path overrides of the encryption settings, see [`Rule`]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
 }
 
 impl Default for Config {
@@ -40,6 +61,12 @@ impl Default for Config {
             use_zstd: true,
             zstd_level: 15,
             crypt_list: vec![],
+            exclude: vec![],
+            diff_driver: false,
+            merge_driver: false,
+            file_source: FileSource::default(),
+            password_policy: PasswordPolicy::default(),
+            rules: vec![],
         }
     }
 }
@@ -64,20 +91,207 @@ impl Config {
         self
     }
 
-    /// Add one path to crypt list.
+    /// Escape the entries written before globs were supported: an entry with
+    /// `*`, `?` or `[` that names an existing path was a literal path then, so
+    /// it stays one, see [`escape_entry`]. Only the loaded config changes, the
+    /// file is rewritten the next time it is stored.
+    pub fn escape_literal_entries(&mut self) {
+        let repo_path = &self.repo_path;
+        for entry in self.crypt_list.iter_mut().chain(&mut self.exclude) {
+            let pattern = entry.strip_prefix('!').unwrap_or(entry);
+            let negation = &entry[..entry.len() - pattern.len()];
+            if !is_glob(pattern) || !repo_path.join(pattern).exists() {
+                continue;
+            }
+            let escaped = format!("{negation}{}", escape_entry(pattern));
+            warn!("`{entry}` names an existing path, treating it as `{escaped}`");
+            *entry = escaped;
+        }
+    }
+
+    /// Add one path to crypt list and return the new entry, or `None` if the
+    /// list already covers it.
     ///
     /// `path` may be either relative or absolute (it will be resolved against
     /// `repo_path`). Returns an error if the path does not exist or cannot be
-    /// expressed as a repo-relative path.
-    pub fn add_one_path_to_crypt_list(&mut self, path: impl AsRef<Path>) -> Result<()> {
+    /// expressed as a repo-relative path. Glob patterns and `!` negations are
+    /// added verbatim, unless the pattern names an existing path: then it is
+    /// added as a literal entry, see [`escape_entry`].
+    ///
+    /// Entries equal to an existing one are skipped, and so are paths whose
+    /// every file the list already covers. Adding a directory replaces the
+    /// literal entries below it.
+    pub fn add_one_path_to_crypt_list(&mut self, path: impl AsRef<Path>) -> Result<Option<String>> {
+        let raw = path.as_ref().to_string_lossy();
+        let literal = !raw.starts_with('!')
+            && (!is_glob(&raw) || self.repo_path.join(path.as_ref()).exists());
+        let entry = if literal {
+            debug!("adding path to crypt list: {}", path.as_ref().display());
+            let entry = self.to_entry(path.as_ref())?;
+            let absolute = self.repo_path.join(literal_path(&entry));
+            if !absolute.exists() {
+                return Err(Error::PathNotExist(absolute));
+            }
+            entry
+        } else {
+            raw.into_owned()
+        };
+
+        if let Some(existing) = self
+            .crypt_list
+            .iter()
+            .find(|e| normalize_entry(e) == normalize_entry(&entry))
+        {
+            warn!("`{entry}` is already in the encrypt list as `{existing}`, skipped");
+            return Ok(None);
+        }
+        if literal && let Some(covering) = self.covering_entry(&entry)? {
+            warn!("`{entry}` is already covered by `{covering}`, skipped");
+            return Ok(None);
+        }
+        if literal {
+            let (nested, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.crypt_list)
+                .into_iter()
+                .partition(|e| {
+                    !e.starts_with('!')
+                        && !is_glob(e)
+                        && entry_covers_path(&entry, &literal_path(normalize_entry(e)))
+                });
+            self.crypt_list = kept;
+            if !nested.is_empty() {
+                info!(
+                    "Replace nested entries with `{entry}`: {}",
+                    nested.join(", ")
+                );
+            }
+        }
+        info!("Add to encrypt list: {}", entry.as_str().green());
+        self.crypt_list.push(entry.clone());
+        Ok(Some(entry))
+    }
+
+    /// The last positive entry covering the literal `entry`, if the list
+    /// already covers it completely: the path itself for a file, every file
+    /// below it for a directory.
+    fn covering_entry(&self, entry: &str) -> Result<Option<&String>> {
+        let matcher = self.matcher()?;
+        let entry = literal_path(entry);
+        if !matcher.matches(&entry) {
+            return Ok(None);
+        }
+        let path = self.repo_path.join(&entry);
+        if path.is_dir()
+            && !list_files([&entry], &self.repo_path).iter().all(|f| {
+                diff_paths(f, &self.repo_path)
+                    .is_some_and(|f| matcher.matches(&f.fuck_backslash().to_string_lossy()))
+            })
+        {
+            return Ok(None);
+        }
+        Ok(self.crypt_list.iter().rev().find(|e| {
+            !e.starts_with('!')
+                && CryptMatcher::new(&self.repo_path, std::slice::from_ref(*e), &[])
+                    .is_ok_and(|m| m.matches(&entry))
+        }))
+    }
+
+    /// Add the given paths to the list in memory and warn about the files
+    /// below the new entries that ignore rules hide, since encryption skips
+    /// them. Returns the new entries.
+    fn add_entries(&mut self, paths: &[impl AsRef<Path>]) -> Result<Vec<String>> {
+        let mut added = Vec::new();
+        for x in paths {
+            added.extend(self.add_one_path_to_crypt_list(x.as_ref())?);
+        }
+        let positive = added
+            .iter()
+            .filter(|e| !e.starts_with('!'))
+            .cloned()
+            .collect::<Vec<_>>();
+        if positive.is_empty() {
+            return Ok(added);
+        }
+        let matcher = CryptMatcher::new(&self.repo_path, &positive, &self.exclude)?;
+        let ignored =
+            list_ignored_files(matcher.roots(), &self.repo_path, Some(matcher.overrides()));
+        for file in &ignored {
+            let file = diff_paths(file, &self.repo_path).unwrap_or_else(|| file.clone());
+            warn!(
+                "{} is ignored by git and will not be encrypted",
+                file.fuck_backslash().display()
+            );
+        }
+        Ok(added)
+    }
+
+    /// The files that adding `paths` would newly put under encryption,
+    /// without changing the list. Warnings are the same as for
+    /// [`Config::add_paths_to_crypt_list`].
+    pub fn resolve_added_files(&self, paths: &[impl AsRef<Path>]) -> Result<Vec<PathBuf>> {
+        let before = self.matcher()?;
+        let mut conf = self.clone();
+        conf.add_entries(paths)?;
+        let after = conf.matcher()?;
+        Ok(
+            list_matching_files(after.roots(), &self.repo_path, after.overrides())
+                .into_iter()
+                .filter_map(|f| diff_paths(&f, &self.repo_path))
+                .map(FuckBackslash::fuck_backslash)
+                .filter(|f| !before.matches(&f.to_string_lossy()))
+                .collect(),
+        )
+    }
+
+    /// Remove the entry for one path from the crypt list and return it.
+    ///
+    /// `path` may be relative, absolute, or a pattern exactly as it appears in
+    /// the list; it does not need to exist. A pattern also removes the
+    /// literal entry of the same name, see [`escape_entry`]. If no entry
+    /// equals it, the error names the entry that covers it, if any.
+    pub fn remove_one_path_from_crypt_list(&mut self, path: impl AsRef<Path>) -> Result<String> {
+        let raw = path.as_ref().to_string_lossy();
+        let wanted = if raw.starts_with('!') || is_glob(&raw) {
+            raw.clone().into_owned()
+        } else {
+            self.to_entry(path.as_ref())?
+        };
+        let escaped = if raw.starts_with('!') {
+            None
+        } else {
+            self.to_entry(path.as_ref()).ok()
+        };
+        let position = |wanted: &str| {
+            self.crypt_list
+                .iter()
+                .position(|entry| normalize_entry(entry) == normalize_entry(wanted))
+        };
+        if let Some(i) = position(&wanted).or_else(|| escaped.as_deref().and_then(position)) {
+            let entry = self.crypt_list.remove(i);
+            info!("Remove from encrypt list: {}", entry.as_str().green());
+            return Ok(entry);
+        }
+        let wanted_path = if is_glob(&wanted) {
+            wanted.clone()
+        } else {
+            literal_path(&wanted)
+        };
+        let covering = self.crypt_list.iter().find(|entry| {
+            !entry.starts_with('!')
+                && CryptMatcher::new(&self.repo_path, std::slice::from_ref(entry), &[])
+                    .is_ok_and(|m| m.matches(&wanted_path))
+        });
+        Err(covering.map_or_else(
+            || Error::NotInCryptList(wanted.clone()),
+            |entry| Error::CoveredByEntry(wanted.clone(), entry.clone()),
+        ))
+    }
+
+    /// The repo-relative, `/`-separated crypt list entry for `path`, escaped
+    /// with [`escape_entry`].
+    fn to_entry(&self, path: &Path) -> Result<String> {
         let path = path
-            .as_ref()
             .absolutize_from(&self.repo_path)
             .map_err(|e| Error::Other(format!("path absolutize failed: {e}")))?;
-        debug!("adding path to crypt list: {}", path.display());
-        if !path.exists() {
-            return Err(Error::PathNotExist(path.into_owned()));
-        }
         let path_relative_to_repo = diff_paths(path.as_ref(), &self.repo_path)
             .unwrap_or_else(|| path.to_path_buf())
             .fuck_backslash();
@@ -89,24 +303,418 @@ impl Config {
         if path_relative_to_repo.is_absolute() {
             return Err(Error::PathNotRelative(path_relative_to_repo));
         }
-        info!(
-            "Add to encrypt list: {}",
-            path_relative_to_repo.display().to_string().green()
-        );
-        self.crypt_list
-            .push(path_relative_to_repo.to_string_lossy().into_owned());
-        Ok(())
+        Ok(escape_entry(&path_relative_to_repo.to_string_lossy()))
+    }
+
+    /// The settings a submodule without a config file uses: these ones, but
+    /// without the paths, which belong to another repo, and without the
+    /// drivers, which are registered per repo.
+    #[must_use]
+    pub fn inherited(&self) -> Self {
+        Self {
+            crypt_list: Vec::new(),
+            exclude: Vec::new(),
+            rules: Vec::new(),
+            diff_driver: false,
+            merge_driver: false,
+            ..self.clone()
+        }
+    }
+
+    /// Build the matcher for `crypt_list` and `exclude`.
+    pub fn matcher(&self) -> Result<CryptMatcher> {
+        CryptMatcher::new(&self.repo_path, &self.crypt_list, &self.exclude)
+    }
+
+    /// Build the resolver for the per-path [`Rule`]s.
+    pub fn rule_set(&self) -> Result<RuleSet> {
+        let base = EncryptSettings {
+            use_zstd: self.use_zstd,
+            zstd_level: self.zstd_level,
+        };
+        let rules = self
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                if let Some(level) = rule.zstd_level
+                    && !(1..=22).contains(&level)
+                {
+                    return Err(Error::Config(format!(
+                        "rules[{i}]: zstd_level should be 1-22, got {level}"
+                    )));
+                }
+                let matcher = CryptMatcher::new(&self.repo_path, &rule.paths, &[])?;
+                Ok((matcher, rule.clone()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(RuleSet { base, rules })
     }
 
     /// Add the given paths to the encrypt list. This function will be called
     /// seldomly, so it's not a performance issue.
     pub fn add_paths_to_crypt_list(&mut self, paths: &[impl AsRef<Path>]) -> Result<()> {
-        for x in paths {
-            self.add_one_path_to_crypt_list(x.as_ref())?;
-        }
+        self.add_entries(paths)?;
         debug!("store config to {}", self.config_path.display());
         self.store().map_err(|e| Error::Config(e.to_string()))
     }
+
+    /// Remove the entries for the given paths from the encrypt list and store
+    /// the config. Nothing is stored if any path has no entry of its own.
+    pub fn remove_paths_from_crypt_list(
+        &mut self,
+        paths: &[impl AsRef<Path>],
+    ) -> Result<Vec<String>> {
+        let removed = paths
+            .iter()
+            .map(|x| self.remove_one_path_from_crypt_list(x.as_ref()))
+            .collect::<Result<Vec<_>>>()?;
+        debug!("store config to {}", self.config_path.display());
+        self.store().map_err(|e| Error::Config(e.to_string()))?;
+        Ok(removed)
+    }
+}
+
+/// `entry` without a leading `./` or trailing `/`, for comparing entries.
+fn normalize_entry(entry: &str) -> &str {
+    entry.trim_start_matches("./").trim_end_matches('/')
+}
+
+/// Whether the list entry `entry` (a file, or a directory covering everything
+/// below it) covers the repo-relative, `/`-separated `path`.
+#[must_use]
+pub fn entry_covers_path(entry: &str, path: &str) -> bool {
+    let entry = literal_path(entry.trim_start_matches("./").trim_end_matches('/'));
+    let entry = entry.as_str();
+    entry.is_empty()
+        || entry == "."
+        || path == entry
+        || path
+            .strip_prefix(entry)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Decides which paths the crypt list covers.
+///
+/// Entries are gitignore-style patterns, evaluated in order with the last
+/// match winning:
+///
+/// - A literal path (no `*`, `?` or `[`, unless escaped with a backslash) is
+///   anchored at the repo root and covers the file or everything inside the
+///   directory, which is what every entry meant before patterns were
+///   supported, so old configs keep working.
+/// - A glob such as `*.env` or `config/**/*.key` follows gitignore rules: it
+///   matches at any depth unless it contains a `/`.
+/// - A leading `!` negates the entry, e.g. `!secrets/README.md`.
+///
+/// `exclude` entries are appended as negations, so they always win.
+#[derive(Debug, Clone)]
+pub struct CryptMatcher {
+    overrides: Override,
+    /// Directories (repo-relative) that contain every covered file; `""` if
+    /// the whole repo has to be searched.
+    roots: Vec<String>,
+}
+
+impl CryptMatcher {
+    pub fn new(repo_path: &Path, crypt_list: &[String], exclude: &[String]) -> Result<Self> {
+        let mut builder = OverrideBuilder::new(repo_path);
+        let mut roots = Vec::new();
+        let excluded = exclude
+            .iter()
+            .map(|e| format!("!{}", e.trim_start_matches('!')));
+        for entry in crypt_list.iter().cloned().chain(excluded) {
+            let (bang, pattern) = entry
+                .strip_prefix('!')
+                .map_or(("", entry.as_str()), |p| ("!", p));
+            let mut globs = Vec::new();
+            let root = if is_glob(pattern) {
+                globs.push(format!("{bang}{pattern}"));
+                ""
+            } else {
+                let literal = pattern.trim_start_matches("./").trim_end_matches('/');
+                if literal.is_empty() || literal == "." {
+                    globs.push(format!("{bang}/**"));
+                    ""
+                } else {
+                    globs.push(format!("{bang}/{literal}"));
+                    globs.push(format!("{bang}/{literal}/**"));
+                    literal
+                }
+            };
+            // Negations only narrow down what the other entries cover.
+            if bang.is_empty() {
+                roots.push(literal_path(root));
+            }
+            for glob in &globs {
+                builder
+                    .add(glob)
+                    .map_err(|e| Error::InvalidPattern(format!("{entry}: {e}")))?;
+            }
+        }
+        if roots.iter().any(String::is_empty) {
+            roots = vec![String::new()];
+        }
+        roots.sort();
+        roots.dedup();
+        let overrides = builder
+            .build()
+            .map_err(|e| Error::InvalidPattern(e.to_string()))?;
+        Ok(Self { overrides, roots })
+    }
+
+    /// Whether the repo-relative, `/`-separated `path` is covered. Works on
+    /// paths that no longer exist in the working tree (e.g. in history).
+    #[must_use]
+    pub fn matches(&self, path: &str) -> bool {
+        self.overrides.matched(path, false).is_whitelist()
+    }
+
+    /// Where to start looking for covered files.
+    #[must_use]
+    pub fn roots(&self) -> &[String] {
+        &self.roots
+    }
+
+    #[must_use]
+    pub const fn overrides(&self) -> &Override {
+        &self.overrides
+    }
+}
+
+/// Where the files covered by the crypt list are enumerated from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "bin", derive(clap::ValueEnum))]
+pub enum FileSource {
+    /// Walk the working tree, skipping files matched by ignore rules.
+    #[default]
+    Walk,
+    /// The files in the git index (`git ls-files`), including tracked files
+    /// that match `.gitignore`. Falls back to walking outside a git repo.
+    Index,
+    /// Like `index`, plus untracked files that are not ignored.
+    IndexAndUntracked,
+}
+
+impl FileSource {
+    // serde's `skip_serializing_if` passes a reference.
+    #[allow(clippy::trivially_copy_pass_by_ref)]
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// A `[[rules]]` table: settings that override the top-level ones for the
+/// files matched by `paths`.
+///
+/// ```toml
+/// [[rules]]
+/// paths = ["media/"]
+/// use_zstd = false
+///
+/// [[rules]]
+/// paths = ["dumps/*.sql"]
+/// zstd_level = 19
+/// ```
+///
+/// When several rules match a file, later rules override earlier ones field
+/// by field. Rules only change how files are encrypted; which files are
+/// encrypted is still decided by `crypt_list`.
+///
+/// Only compression can be set per path. Every file is encrypted with
+/// XChaCha20-Poly1305 and without padding, and unknown keys such as
+/// `algorithm` or `padding` are rejected rather than ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    /// Paths or patterns, with the same syntax as `crypt_list`.
+    pub paths: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub use_zstd: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zstd_level: Option<u8>,
+}
+
+/// The settings used to encrypt one file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncryptSettings {
+    pub use_zstd: bool,
+    pub zstd_level: u8,
+}
+
+impl EncryptSettings {
+    /// The zstd level to compress with, or `None` to store uncompressed.
+    #[must_use]
+    pub const fn zstd(&self) -> Option<u8> {
+        if self.use_zstd {
+            Some(self.zstd_level)
+        } else {
+            None
+        }
+    }
+}
+
+impl std::fmt::Display for EncryptSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.zstd() {
+            Some(level) => write!(f, "zstd level {level}"),
+            None => f.write_str("no compression"),
+        }
+    }
+}
+
+/// Resolves the [`EncryptSettings`] of each file from the config's rules.
+#[derive(Debug, Clone)]
+pub struct RuleSet {
+    base: EncryptSettings,
+    rules: Vec<(CryptMatcher, Rule)>,
+}
+
+impl RuleSet {
+    /// Settings for the repo-relative, `/`-separated `path`.
+    #[must_use]
+    pub fn settings_for(&self, path: &str) -> EncryptSettings {
+        let mut settings = self.base;
+        for (matcher, rule) in &self.rules {
+            if matcher.matches(path) {
+                settings.use_zstd = rule.use_zstd.unwrap_or(settings.use_zstd);
+                settings.zstd_level = rule.zstd_level.unwrap_or(settings.zstd_level);
+            }
+        }
+        settings
+    }
+}
+
+/// Requirements a new password has to meet, the `[password_policy]` table.
+///
+/// ```toml
+/// [password_policy]
+/// min_length = 12
+/// min_entropy_bits = 60
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasswordPolicy {
+    /// Minimum number of characters.
+    pub min_length: usize,
+    /// Minimum [`estimate_entropy_bits`] of the password, 0 to disable.
+    pub min_entropy_bits: u32,
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> Self {
+        Self {
+            min_length: 8,
+            min_entropy_bits: 0,
+        }
+    }
+}
+
+impl PasswordPolicy {
+    fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Check `password` against the policy.
+    pub fn check(&self, password: &str) -> Result<()> {
+        let length = password.chars().count();
+        if length < self.min_length {
+            return Err(Error::WeakPassword(format!(
+                "it has {length} characters, at least {} are required",
+                self.min_length
+            )));
+        }
+        let bits = estimate_entropy_bits(password);
+        if bits < f64::from(self.min_entropy_bits) {
+            return Err(Error::WeakPassword(format!(
+                "its estimated entropy is {bits:.0} bits, at least {} are required",
+                self.min_entropy_bits
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A rough upper bound of the entropy of `password` in bits.
+///
+/// It is the number of distinct characters times `log2` of the size of the
+/// character classes used: lowercase, uppercase, digits, ASCII symbols and
+/// anything else.
+#[must_use]
+pub fn estimate_entropy_bits(password: &str) -> f64 {
+    let mut distinct = password.chars().collect::<Vec<_>>();
+    distinct.sort_unstable();
+    distinct.dedup();
+    let mut classes = distinct
+        .iter()
+        .map(|c| match c {
+            'a'..='z' => (0, 26),
+            'A'..='Z' => (1, 26),
+            '0'..='9' => (2, 10),
+            c if c.is_ascii() => (3, 33),
+            _ => (4, 100),
+        })
+        .collect::<Vec<(u8, u32)>>();
+    classes.sort_unstable();
+    classes.dedup();
+    let pool: u32 = classes.iter().map(|(_, size)| size).sum();
+    if pool == 0 {
+        return 0.0;
+    }
+    let length = u32::try_from(distinct.len()).unwrap_or(u32::MAX);
+    f64::from(length) * f64::from(pool).log2()
+}
+
+const GLOB_CHARS: [char; 3] = ['*', '?', '['];
+
+/// Whether a crypt list entry is a glob rather than a literal path. `*`, `?`
+/// and `[` escaped with a backslash are literal, see [`escape_entry`].
+#[must_use]
+pub fn is_glob(entry: &str) -> bool {
+    let mut chars = entry.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                chars.next();
+            }
+            c if GLOB_CHARS.contains(&c) => return true,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// The literal crypt list entry for the repo-relative `path`: `*`, `?` and
+/// `[` are escaped with a backslash, so that `config[prod].toml` does not
+/// become a glob.
+#[must_use]
+pub fn escape_entry(path: &str) -> String {
+    let mut entry = String::with_capacity(path.len());
+    for c in path.chars() {
+        if GLOB_CHARS.contains(&c) {
+            entry.push('\\');
+        }
+        entry.push(c);
+    }
+    entry
+}
+
+/// The path of a literal crypt list entry, the inverse of [`escape_entry`].
+#[must_use]
+pub fn literal_path(entry: &str) -> String {
+    let mut path = String::with_capacity(entry.len());
+    let mut chars = entry.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\'
+            && let Some(&next) = chars.peek()
+            && GLOB_CHARS.contains(&next)
+        {
+            continue;
+        }
+        path.push(c);
+    }
+    path
 }
 
 #[cfg(test)]
@@ -118,6 +726,130 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_password_policy() {
+        let policy = PasswordPolicy::default();
+        assert!(policy.check("short").is_err());
+        assert!(policy.check("aaaaaaaa").is_ok());
+
+        let policy = PasswordPolicy {
+            min_length: 8,
+            min_entropy_bits: 40,
+        };
+        // Repeated characters do not add entropy.
+        assert!(policy.check("aaaaaaaaaaaa").is_err());
+        assert!(policy.check("correct horse battery").is_ok());
+        assert!(policy.check("Tr0ub4dor&3").is_ok());
+        assert!(estimate_entropy_bits("abc") < estimate_entropy_bits("aB3"));
+        assert!(estimate_entropy_bits("").abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_defaults_not_serialized() -> crate::Result<()> {
+        let to_toml =
+            |config: &Config| toml::to_string(config).map_err(|e| Error::Config(e.to_string()));
+        assert_eq!(
+            to_toml(&Config::default())?,
+            "use_zstd = true\nzstd_level = 15\ncrypt_list = []\n"
+        );
+
+        let config = Config {
+            exclude: vec!["a".to_owned()],
+            diff_driver: true,
+            merge_driver: true,
+            file_source: FileSource::Index,
+            ..Default::default()
+        };
+        let serialized = to_toml(&config)?;
+        for key in ["exclude", "diff_driver", "merge_driver", "file_source"] {
+            assert!(serialized.contains(key), "{key}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_matcher_literal_entries() -> crate::Result<()> {
+        let config = Config {
+            crypt_list: vec!["secrets".to_owned(), "./a.env".to_owned()],
+            ..Default::default()
+        };
+        let matcher = config.matcher()?;
+        assert!(matcher.matches("secrets/x/y.txt"));
+        assert!(matcher.matches("a.env"));
+        assert!(!matcher.matches("secrets2/y.txt"));
+        assert!(!matcher.matches("b/a.env"));
+        assert_eq!(matcher.roots(), ["a.env", "secrets"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_matcher_globs_and_negations() -> crate::Result<()> {
+        let config = Config {
+            crypt_list: vec![
+                "*.env".to_owned(),
+                "secrets/".to_owned(),
+                "!secrets/README.md".to_owned(),
+            ],
+            exclude: vec!["example.env".to_owned()],
+            ..Default::default()
+        };
+        let matcher = config.matcher()?;
+        assert!(matcher.matches("a.env"));
+        assert!(matcher.matches("deep/dir/b.env"));
+        assert!(matcher.matches("secrets/key.pem"));
+        assert!(!matcher.matches("secrets/README.md"));
+        assert!(!matcher.matches("example.env"));
+        assert!(!matcher.matches("a.env.bak"));
+        assert_eq!(matcher.roots(), [""]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rule_set() -> crate::Result<()> {
+        let config: Config = toml::from_str(
+            r#"
+            use_zstd = true
+            zstd_level = 15
+            crypt_list = ["."]
+
+            [[rules]]
+            paths = ["media/"]
+            use_zstd = false
+
+            [[rules]]
+            paths = ["*.sql"]
+            zstd_level = 19
+            "#,
+        )
+        .unwrap();
+        let rules = config.rule_set()?;
+        assert_eq!(rules.settings_for("a.txt").zstd(), Some(15));
+        assert_eq!(rules.settings_for("media/a.png").zstd(), None);
+        assert_eq!(rules.settings_for("dump/a.sql").zstd(), Some(19));
+        // Both rules match, each overriding its own field.
+        let both = rules.settings_for("media/a.sql");
+        assert_eq!((both.use_zstd, both.zstd_level), (false, 19));
+        assert_eq!(both.to_string(), "no compression");
+        assert_eq!(rules.settings_for("a.sql").to_string(), "zstd level 19");
+
+        // Options that do not exist are errors, not silently ignored.
+        for key in ["algorithm = \"aes-256-gcm\"", "padding = 4096"] {
+            let toml = format!("[[rules]]\npaths = [\"x\"]\n{key}\n");
+            assert!(toml::from_str::<Config>(&toml).is_err(), "{key}");
+        }
+
+        let invalid = Config {
+            rules: vec![Rule {
+                paths: vec!["x".to_owned()],
+                zstd_level: Some(30),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert!(matches!(invalid.rule_set(), Err(Error::Config(_))));
+        Ok(())
+    }
+
     #[test]
     fn test_add_one_file_to_crypt_list() -> crate::Result<()> {
         let temp_dir = TempDir::new()?.keep();
@@ -140,4 +872,76 @@ mod tests {
         );
         Ok(())
     }
+
+    #[test]
+    fn test_add_dedupes_and_collapses_entries() -> crate::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let repo = temp_dir.path();
+        fs::create_dir_all(repo.join("secrets/sub"))?;
+        fs::write(repo.join("secrets/a.txt"), "a")?;
+        fs::write(repo.join("secrets/sub/b.txt"), "b")?;
+        let mut config = Config::default().with_repo_path(repo);
+
+        assert_eq!(
+            config.add_one_path_to_crypt_list("secrets/sub/b.txt")?,
+            Some("secrets/sub/b.txt".to_owned())
+        );
+        assert_eq!(
+            config.add_one_path_to_crypt_list("./secrets/sub/b.txt")?,
+            None
+        );
+        assert_eq!(
+            config.add_one_path_to_crypt_list("secrets/")?,
+            Some("secrets".to_owned())
+        );
+        assert_eq!(config.crypt_list, ["secrets"]);
+        // Covered by the directory entry.
+        assert_eq!(config.add_one_path_to_crypt_list("secrets/sub")?, None);
+
+        // Partly excluded, so adding the child re-includes something.
+        config.crypt_list.push("!secrets/sub/b.txt".to_owned());
+        assert_eq!(
+            config.add_one_path_to_crypt_list("secrets/sub")?,
+            Some("secrets/sub".to_owned())
+        );
+        assert_eq!(
+            config.add_one_path_to_crypt_list("*.env")?,
+            Some("*.env".to_owned())
+        );
+        assert_eq!(config.add_one_path_to_crypt_list("*.env")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_literal_entry_with_brackets() -> crate::Result<()> {
+        let temp_dir = TempDir::new()?;
+        let repo = temp_dir.path();
+        fs::write(repo.join("config[prod].toml"), "a")?;
+        fs::write(repo.join("configp.toml"), "b")?;
+        let mut config = Config::default().with_repo_path(repo);
+
+        assert!(is_glob("config[prod].toml"));
+        assert!(!is_glob("config\\[prod].toml"));
+        assert_eq!(
+            config.add_one_path_to_crypt_list("config[prod].toml")?,
+            Some("config\\[prod].toml".to_owned())
+        );
+        assert_eq!(literal_path(&config.crypt_list[0]), "config[prod].toml");
+
+        let matcher = config.matcher()?;
+        assert!(matcher.matches("config[prod].toml"));
+        assert!(!matcher.matches("configp.toml"));
+        assert_eq!(matcher.roots(), ["config[prod].toml"]);
+        assert!(entry_covers_path(
+            &config.crypt_list[0],
+            "config[prod].toml"
+        ));
+
+        assert_eq!(
+            config.remove_one_path_from_crypt_list("config[prod].toml")?,
+            "config\\[prod].toml"
+        );
+        assert_eq!(config.crypt_list, Vec::<String>::new());
+        Ok(())
+    }
 }
@@ -2,7 +2,7 @@ use std::{
     fs,
     io::Read,
     path::{Path, PathBuf},
-    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
 };
 
 use chacha20poly1305::{XChaCha20Poly1305, aead::KeyInit};
@@ -14,15 +14,18 @@ use tempfile::NamedTempFile;
 
 use crate::{
     crypt::{
-        file::{encrypt_file_to, persist_temp_file},
+        file::{encrypt_file_to_with_progress, persist_temp_file},
         header::{FileHeader, HEADER_LEN, MAGIC, SALT_LEN, is_encrypted_version},
         key::{KeyCache, get_or_derive_key, split_keys},
         stream::decrypt_body,
     },
     error::{Error, Result},
+    observer::{Observer, notify},
+    report::{BatchReport, FileAction, FileOutcome, FileReport, Summary},
 };
 
 /// Summary of a batch encrypt/decrypt run.
+#[deprecated(note = "the batch APIs return a `BatchReport`; convert it with `From` if needed")]
 #[derive(Debug, Default)]
 pub struct BatchSummary {
     pub total: usize,
@@ -32,6 +35,7 @@ pub struct BatchSummary {
     pub errors: Vec<(PathBuf, Error)>,
 }
 
+#[allow(deprecated)]
 impl BatchSummary {
     #[must_use]
     pub fn is_ok(&self) -> bool {
@@ -39,13 +43,37 @@ impl BatchSummary {
     }
 }
 
-/// Internal: decrypt `src` → `dst` using a shared Argon2 key cache.
-#[allow(dead_code)]
+#[allow(deprecated)]
+impl From<BatchReport> for BatchSummary {
+    fn from(report: BatchReport) -> Self {
+        let Summary {
+            total,
+            succeeded,
+            skipped,
+            failed,
+        } = report.summary();
+        Self {
+            total,
+            succeeded,
+            skipped,
+            failed,
+            errors: report
+                .files
+                .into_iter()
+                .filter_map(|f| f.error.map(|e| (f.path, e)))
+                .collect(),
+        }
+    }
+}
+
+/// Internal: decrypt `src` → `dst` using a shared Argon2 key cache, passing
+/// the plaintext bytes written to `progress` once per chunk.
 fn decrypt_file_to_with_key_cache(
     src: &Path,
     dst: &Path,
     key_cache: &KeyCache,
     master_key: &[u8],
+    progress: &mut dyn FnMut(u64),
 ) -> Result<Option<FileHeader>> {
     let mut src_file = fs::File::open(src)?;
 
@@ -73,7 +101,7 @@ fn decrypt_file_to_with_key_cache(
 
     let (key_enc, _) = split_keys(&derived_key);
     let cipher = XChaCha20Poly1305::new(key_enc.as_ref().into());
-    decrypt_body(&mut src_file, &mut temp_file, &cipher, &header)?;
+    decrypt_body(&mut src_file, &mut temp_file, &cipher, &header, progress)?;
 
     drop(src_file);
     persist_temp_file(temp_file, dst, Some(src))?;
@@ -82,108 +110,118 @@ fn decrypt_file_to_with_key_cache(
 }
 
 /// Decrypt multiple files in parallel, each to a caller-determined destination.
-#[allow(dead_code, clippy::unnecessary_wraps)]
-pub fn decrypt_files_to<I, P, F>(sources: I, master_key: &[u8], mapper: F) -> Result<BatchSummary>
+///
+/// Files that are not encrypted are skipped, as are sources for which
+/// `mapper` returns `None`; only the former are in the report. A file that
+/// fails does not stop the others, see [`BatchReport::result`].
+/// `observer` receives the events of the batch as it runs.
+#[allow(clippy::unnecessary_wraps)]
+pub fn decrypt_files_to<I, P, F>(
+    sources: I,
+    master_key: &[u8],
+    mapper: F,
+    observer: &dyn Observer,
+) -> Result<BatchReport>
 where
     I: IntoIterator<Item = P>,
     P: AsRef<Path> + Sync,
     F: Fn(&Path) -> Option<PathBuf> + Sync,
 {
-    let sources: Vec<PathBuf> = sources
-        .into_iter()
-        .map(|p| p.as_ref().to_path_buf())
-        .collect();
-    let total = sources.len();
-
     let key_cache: KeyCache = DashMap::new();
-    let errors: parking_lot::Mutex<Vec<(PathBuf, Error)>> = parking_lot::Mutex::new(Vec::new());
-    let skipped = AtomicUsize::new(0);
-    let succeeded = AtomicUsize::new(0);
-
-    sources.par_iter().for_each(|src| {
-        let Some(dst) = mapper(src) else { return };
-
-        match decrypt_file_to_with_key_cache(src, &dst, &key_cache, master_key) {
-            Ok(Some(_)) => {
-                succeeded.fetch_add(1, Ordering::Relaxed);
-            }
-            Ok(None) => {
-                skipped.fetch_add(1, Ordering::Relaxed);
-            }
-            Err(e) => {
-                errors.lock().push((src.clone(), e));
-            }
-        }
-    });
-
-    let errors = errors.into_inner();
-    let succeeded = succeeded.load(Ordering::Relaxed);
-    let skipped = skipped.load(Ordering::Relaxed);
-    let failed = errors.len();
-
-    Ok(BatchSummary {
-        total,
-        succeeded,
-        skipped,
-        failed,
-        errors,
-    })
+    Ok(run_batch(
+        FileAction::Decrypt,
+        sources,
+        mapper,
+        observer,
+        |src, dst, progress| {
+            decrypt_file_to_with_key_cache(src, dst, &key_cache, master_key, progress)
+                .map(|h| h.is_some())
+        },
+    ))
 }
 
 /// Encrypt multiple files in parallel, each from a caller-determined source to
 /// a caller-determined destination.
-#[allow(dead_code, clippy::unnecessary_wraps)]
+///
+/// Files that are already encrypted are skipped, as are sources for which
+/// `mapper` returns `None`; only the former are in the report. A file that
+/// fails does not stop the others, see [`BatchReport::result`].
+/// `observer` receives the events of the batch as it runs.
 pub fn encrypt_files_to<I, P, F>(
     sources: I,
     master_key: &[u8],
     mapper: F,
     zstd: Option<u8>,
-) -> Result<BatchSummary>
+    observer: &dyn Observer,
+) -> Result<BatchReport>
 where
     I: IntoIterator<Item = P>,
     P: AsRef<Path> + Sync,
     F: Fn(&Path) -> Option<PathBuf> + Sync,
 {
-    let sources: Vec<PathBuf> = sources
-        .into_iter()
-        .map(|p| p.as_ref().to_path_buf())
-        .collect();
-    let total = sources.len();
-
     let mut batch_salt = [0u8; SALT_LEN];
     rand::rng().fill_bytes(&mut batch_salt);
     let derived_key = crate::crypt::key::derive_key(master_key, &batch_salt)?;
 
-    let errors: parking_lot::Mutex<Vec<(PathBuf, Error)>> = parking_lot::Mutex::new(Vec::new());
-    let skipped = AtomicUsize::new(0);
-    let succeeded = AtomicUsize::new(0);
-
-    sources.par_iter().for_each(|src| {
-        let Some(dst) = mapper(src) else { return };
-
-        match encrypt_file_to(src, &dst, &derived_key, batch_salt, None, zstd) {
-            Ok(Some(_)) => {
-                succeeded.fetch_add(1, Ordering::Relaxed);
-            }
-            Ok(None) => {
-                skipped.fetch_add(1, Ordering::Relaxed);
-            }
-            Err(e) => {
-                errors.lock().push((src.clone(), e));
-            }
-        }
-    });
-
-    let errors = errors.into_inner();
-    let succeeded = succeeded.load(Ordering::Relaxed);
-    let skipped = skipped.load(Ordering::Relaxed);
-    let failed = errors.len();
-
-    Ok(BatchSummary {
-        total,
-        succeeded,
-        skipped,
-        failed,
-        errors,
-    })
+    Ok(run_batch(
+        FileAction::Encrypt,
+        sources,
+        mapper,
+        observer,
+        |src, dst, progress| {
+            encrypt_file_to_with_progress(src, dst, &derived_key, batch_salt, None, zstd, progress)
+                .map(|h| h.is_some())
+        },
+    ))
+}
+
+/// Run `f(src, dst, progress)` on every source that `mapper` maps to a
+/// `dst`, in parallel. `f` reports the bytes it processes to `progress` and
+/// returns whether it did anything, i.e. `false` for a skipped file.
+/// Unmapped sources are left out of the batch, including the file list
+/// passed to [`Observer::batch_started`].
+fn run_batch<I, P, F>(
+    action: FileAction,
+    sources: I,
+    mapper: F,
+    observer: &dyn Observer,
+    f: impl Fn(&Path, &Path, &mut dyn FnMut(u64)) -> Result<bool> + Sync,
+) -> BatchReport
+where
+    I: IntoIterator<Item = P>,
+    P: AsRef<Path> + Sync,
+    F: Fn(&Path) -> Option<PathBuf> + Sync,
+{
+    let batch_start = Instant::now();
+    let (sources, targets): (Vec<PathBuf>, Vec<PathBuf>) = sources
+        .into_iter()
+        .filter_map(|p| {
+            let src = p.as_ref();
+            mapper(src).map(|dst| (src.to_path_buf(), dst))
+        })
+        .unzip();
+
+    observer.batch_started(action, &sources);
+    let files = sources
+        .par_iter()
+        .zip(&targets)
+        .map(|(src, dst)| {
+            observer.file_started(src);
+            let start = Instant::now();
+            let bytes_in = fs::metadata(src).map_or(0, |m| m.len());
+            let report = match f(src, dst, &mut |bytes| observer.bytes_processed(src, bytes)) {
+                Ok(true) => {
+                    let bytes_out = fs::metadata(dst).map_or(0, |m| m.len());
+                    FileReport::new(src, FileOutcome::Ok, start).with_bytes(bytes_in, bytes_out)
+                }
+                Ok(false) => FileReport::new(src, FileOutcome::Skipped, start),
+                Err(e) => FileReport::failed(src, e, start),
+            };
+            notify(observer, report)
+        })
+        .collect();
+
+    let report = BatchReport::new(action, files, batch_start);
+    observer.batch_finished(action, &report.summary());
+    report
 }
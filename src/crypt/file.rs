@@ -12,7 +12,7 @@ use crate::{
     crypt::{
         header::{FILE_ID_LEN, FileHeader, HEADER_LEN, MAGIC, SALT_LEN, is_encrypted_version},
         key::{KeyCache, get_or_derive_key, split_keys},
-        stream::{decrypt_body, encrypt_into},
+        stream::{decrypt_body, encrypt_into_with_progress},
     },
     error::{Error, Result},
     salt_cache::{CacheRef, CachedEntry},
@@ -43,6 +43,19 @@ pub fn encrypt_file_to(
     salt: [u8; SALT_LEN],
     file_id: Option<[u8; FILE_ID_LEN]>,
     zstd: Option<u8>,
+) -> Result<Option<FileHeader>> {
+    encrypt_file_to_with_progress(src, dst, derived_key, salt, file_id, zstd, &mut |_| {})
+}
+
+/// [`encrypt_file_to`], passing the bytes read to `progress` once per chunk.
+pub(super) fn encrypt_file_to_with_progress(
+    src: &Path,
+    dst: &Path,
+    derived_key: &[u8; 32],
+    salt: [u8; SALT_LEN],
+    file_id: Option<[u8; FILE_ID_LEN]>,
+    zstd: Option<u8>,
+    progress: &mut dyn FnMut(u64),
 ) -> Result<Option<FileHeader>> {
     let mut src_file = fs::File::open(src)?;
 
@@ -62,13 +75,14 @@ pub fn encrypt_file_to(
     fs::create_dir_all(dst_parent)?;
     let mut temp_file = NamedTempFile::new_in(dst_parent)?;
 
-    let header = encrypt_into(
+    let header = encrypt_into_with_progress(
         &mut src_file,
         &mut temp_file,
         derived_key,
         salt,
         file_id,
         zstd,
+        progress,
     )?;
 
     drop(src_file);
@@ -105,7 +119,7 @@ pub fn decrypt_file_to(src: &Path, dst: &Path, master_key: &[u8]) -> Result<Opti
 
     let (key_enc, _) = split_keys(&derived_key);
     let cipher = XChaCha20Poly1305::new(key_enc.as_ref().into());
-    decrypt_body(&mut src_file, &mut temp_file, &cipher, &header)?;
+    decrypt_body(&mut src_file, &mut temp_file, &cipher, &header, &mut |_| {})?;
 
     drop(src_file);
     persist_temp_file(temp_file, dst, Some(src))?;
@@ -136,6 +150,18 @@ pub fn decrypt_file_with_cache(
     key_cache: &KeyCache,
     cache: Option<CacheRef<'_>>,
     master_key: &[u8],
+) -> Result<()> {
+    decrypt_file_with_progress(path, key_cache, cache, master_key, &mut |_| {})
+}
+
+/// [`decrypt_file_with_cache`], passing the plaintext bytes written to
+/// `progress` once per chunk.
+pub(super) fn decrypt_file_with_progress(
+    path: &Path,
+    key_cache: &KeyCache,
+    cache: Option<CacheRef<'_>>,
+    master_key: &[u8],
+    progress: &mut dyn FnMut(u64),
 ) -> Result<()> {
     let mut file = fs::File::open(path)?;
 
@@ -175,7 +201,7 @@ pub fn decrypt_file_with_cache(
     let parent_dir = path.parent().unwrap_or_else(|| Path::new("."));
     let mut temp_file = NamedTempFile::new_in(parent_dir)?;
 
-    decrypt_body(&mut file, &mut temp_file, &cipher, &header)?;
+    decrypt_body(&mut file, &mut temp_file, &cipher, &header, progress)?;
     drop(file);
 
     persist_temp_file(temp_file, path, Some(path))?;
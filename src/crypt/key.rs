@@ -39,10 +39,9 @@ pub(super) fn derive_nonce(
     nonce
 }
 
-pub(super) type KeyCache =
-    DashMap<[u8; SALT_LEN], Arc<OnceLock<Result<Zeroizing<[u8; 32]>, String>>>>;
+pub type KeyCache = DashMap<[u8; SALT_LEN], Arc<OnceLock<Result<Zeroizing<[u8; 32]>, String>>>>;
 
-pub(super) fn get_or_derive_key(
+pub fn get_or_derive_key(
     key_cache: &KeyCache,
     master_key: &[u8],
     salt: &[u8; SALT_LEN],
@@ -52,16 +52,21 @@ mod key;
 mod repo;
 mod stream;
 
+#[allow(deprecated)]
 pub use batch::BatchSummary;
+pub use batch::{decrypt_files_to, encrypt_files_to};
 pub use file::{
     decrypt_file, decrypt_file_to, decrypt_file_with_cache, encrypt_file, encrypt_file_to,
 };
 pub use header::{
-    FILE_ID_LEN, FileHeader, HEADER_LEN, MAGIC, NONCE_LEN, SALT_LEN, VERSION, is_encrypted_version,
+    CHUNK_SIZE, FILE_ID_LEN, FileHeader, HEADER_LEN, MAGIC, NONCE_LEN, SALT_LEN, VERSION,
+    is_encrypted_version,
 };
 pub use key::derive_key;
+pub(crate) use key::{KeyCache, get_or_derive_key};
 pub use repo::{cache_key, decrypt_repo, encrypt_repo};
-pub use stream::{decrypt_into, encrypt_into};
+pub use stream::{decrypt_into, decrypt_or_copy_into, encrypt_into};
+pub(crate) use stream::{decrypt_into_cached, encrypt_into_with_progress, probe_key};
 
 #[cfg(test)]
 mod tests;
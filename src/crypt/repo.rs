@@ -1,25 +1,27 @@
 use std::{
     path::{Path, PathBuf},
-    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
 };
 
 use dashmap::DashMap;
+use log::debug;
 use pathdiff::diff_paths;
 use rand::prelude::*;
 use rayon::prelude::*;
 
 use crate::{
+    attributes::check_crypt_attrs,
     crypt::{
-        file::{decrypt_file_with_cache, encrypt_file},
+        file::{decrypt_file_with_progress, encrypt_file_to_with_progress},
         header::SALT_LEN,
         key::{KeyCache, get_or_derive_key},
     },
     error::{Error, Result},
+    observer::{Observer, notify},
     repo::Repo,
+    report::{BatchReport, FileAction, FileOutcome, FileReport},
     salt_cache::{self, CacheRef},
-    utils::{
-        Progress, is_file_encrypted, print_post_report, print_pre_report, resolve_target_files,
-    },
+    utils::{is_file_encrypted, resolve_target_files},
 };
 
 /// Compute a repo-relative cache key from a file path.
@@ -39,168 +41,170 @@ pub fn cache_key(file_path: &Path, repo_path: &Path) -> Vec<u8> {
     bytes
 }
 
-/// Encrypt given files in the repo.
-pub fn encrypt_repo(repo: &Repo, paths: &[PathBuf]) -> Result<()> {
+/// Encrypt the given files in the repo (by default the whole crypt list).
+///
+/// A file that fails does not stop the others; see [`BatchReport::result`]
+/// for turning the report into an error. Files that are already encrypted
+/// are [`FileOutcome::Skipped`]. `observer` receives the events of the
+/// batch as it runs.
+pub fn encrypt_repo(
+    repo: &Repo,
+    paths: &[PathBuf],
+    observer: &dyn Observer,
+) -> Result<BatchReport> {
+    let start = Instant::now();
     let key = repo.get_key()?;
     if key.is_empty() {
         return Err(Error::EmptyKey);
     }
 
-    let target_files = resolve_target_files(paths, &repo.conf.crypt_list, repo.path());
+    let target_files = resolve_target_files(paths, &repo.conf)?;
     if target_files.is_empty() {
         return Err(Error::NoFile("encrypt"));
     }
 
-    print_pre_report("Encrypting", &target_files, repo.path());
-
+    let rule_set = repo.conf.rule_set()?;
+    let relative = target_files
+        .iter()
+        .map(|f| String::from_utf8_lossy(&cache_key(f, repo.path())).into_owned())
+        .collect::<Vec<_>>();
+    let attrs = check_crypt_attrs(repo.path(), &relative, false)?;
     let reader = salt_cache::SaltCacheReader::load(repo.path());
     let key_cache: KeyCache = DashMap::new();
 
     let mut batch_salt = [0u8; SALT_LEN];
     rand::rng().fill_bytes(&mut batch_salt);
 
-    let pb = Progress::new(target_files.len(), "Encrypt");
-    let skipped = AtomicUsize::new(0);
-    let failed = AtomicUsize::new(0);
-
-    let result = {
-        let errors: parking_lot::Mutex<Vec<Error>> = parking_lot::Mutex::new(Vec::new());
-        target_files.par_iter().for_each(|f| {
-            let relative_key = cache_key(f, repo.path());
+    observer.batch_started(
+        FileAction::Encrypt,
+        &relative.iter().map(PathBuf::from).collect::<Vec<_>>(),
+    );
+    let files = target_files
+        .par_iter()
+        .zip(relative)
+        .zip(&attrs)
+        .map(|((f, relative), attrs)| {
+            let start = Instant::now();
+            observer.file_started(Path::new(&relative));
+            let settings = attrs.apply(rule_set.settings_for(&relative));
+            debug!("{}: {settings}", f.display());
             let (salt, cached_file_id) = reader
-                .get(&relative_key)
+                .get(relative.as_bytes())
                 .map_or((batch_salt, None), |entry| {
                     (entry.salt, Some(entry.file_id))
                 });
 
-            let derived_key = match get_or_derive_key(&key_cache, key.as_bytes(), &salt) {
-                Ok(k) => k,
-                Err(e) => {
-                    failed.fetch_add(1, Ordering::Relaxed);
-                    errors.lock().push(e);
-                    pb.inc(1);
-                    return;
-                }
+            let bytes_in = file_len(f);
+            let result = get_or_derive_key(&key_cache, key.as_bytes(), &salt).and_then(|k| {
+                encrypt_file_to_with_progress(
+                    f,
+                    f,
+                    &k,
+                    salt,
+                    cached_file_id,
+                    settings.zstd(),
+                    &mut |bytes| observer.bytes_processed(Path::new(&relative), bytes),
+                )
+            });
+            let report = match result {
+                Ok(Some(_)) => FileReport::new(relative, FileOutcome::Ok, start)
+                    .with_bytes(bytes_in, file_len(f)),
+                Ok(None) => FileReport::new(relative, FileOutcome::Skipped, start),
+                Err(e) => FileReport::failed(relative, e, start),
             };
+            notify(observer, report)
+        })
+        .collect();
 
-            let r = encrypt_file(
-                f,
-                &derived_key,
-                &salt,
-                cached_file_id,
-                repo.conf.use_zstd.then_some(repo.conf.zstd_level),
-            )
-            .map_err(|e| Error::Other(format!("Failed to encrypt {}: {e}", f.display())));
-
-            match r {
-                Ok(Some(_)) => {}
-                Ok(None) => {
-                    skipped.fetch_add(1, Ordering::Relaxed);
-                }
-                Err(e) => {
-                    failed.fetch_add(1, Ordering::Relaxed);
-                    errors.lock().push(e);
-                }
-            }
-
-            pb.inc(1);
-        });
-        errors.into_inner()
-    };
-
-    pb.finish_and_clear();
-
-    print_post_report(
-        "Encrypt",
-        target_files.len(),
-        skipped.load(Ordering::Relaxed),
-        failed.load(Ordering::Relaxed),
-    );
-
-    if let Some(first) = result.into_iter().next() {
-        return Err(first);
-    }
-
-    Ok(())
+    let report = BatchReport::new(FileAction::Encrypt, files, start);
+    observer.batch_finished(report.action, &report.summary());
+    Ok(report)
 }
 
-/// Decrypt given files in the repo.
-pub fn decrypt_repo(repo: &Repo, paths: &[PathBuf]) -> Result<()> {
+/// Decrypt the given files in the repo (by default the whole crypt list).
+///
+/// A file that fails does not stop the others; see [`BatchReport::result`]
+/// for turning the report into an error. Files that are not encrypted are
+/// [`FileOutcome::Skipped`]. `observer` receives the events of the batch
+/// as it runs.
+pub fn decrypt_repo(
+    repo: &Repo,
+    paths: &[PathBuf],
+    observer: &dyn Observer,
+) -> Result<BatchReport> {
+    let start = Instant::now();
     let key = repo.get_key()?;
     if key.is_empty() {
         return Err(Error::EmptyKey);
     }
 
-    let target_files = resolve_target_files(paths, &repo.conf.crypt_list, repo.path());
+    let target_files = resolve_target_files(paths, &repo.conf)?;
     if target_files.is_empty() {
         return Err(Error::NoFile("decrypt"));
     }
 
-    print_pre_report("Decrypting", &target_files, repo.path());
-
     let key_cache: KeyCache = DashMap::new();
     let (sender, saver) = salt_cache::create_writer(repo.path());
 
-    let pb = Progress::new(target_files.len(), "Decrypt");
-    let skipped = AtomicUsize::new(0);
-    let failed = AtomicUsize::new(0);
-
-    let result = {
-        let errors: parking_lot::Mutex<Vec<Error>> = parking_lot::Mutex::new(Vec::new());
-        target_files.par_iter().for_each(|f| {
-            match is_file_encrypted(f) {
-                Ok(true) => {}
-                Ok(false) => {
-                    skipped.fetch_add(1, Ordering::Relaxed);
-                    pb.inc(1);
-                    return;
-                }
-                Err(e) => {
-                    failed.fetch_add(1, Ordering::Relaxed);
-                    errors.lock().push(e);
-                    pb.inc(1);
-                    return;
-                }
-            }
-
-            let relative_key = cache_key(f, repo.path());
-
-            let r = decrypt_file_with_cache(
-                f,
-                &key_cache,
-                Some(CacheRef {
-                    sender: &sender,
-                    key: &relative_key,
-                }),
-                key.as_bytes(),
-            )
-            .map_err(|e| Error::Other(format!("Failed to decrypt {}: {e}", f.display())));
-
-            if let Err(e) = r {
-                failed.fetch_add(1, Ordering::Relaxed);
-                errors.lock().push(e);
-            }
-
-            pb.inc(1);
-        });
-        errors.into_inner()
-    };
+    let relative_keys = target_files
+        .iter()
+        .map(|f| cache_key(f, repo.path()))
+        .collect::<Vec<_>>();
+    observer.batch_started(
+        FileAction::Decrypt,
+        &relative_keys
+            .iter()
+            .map(|k| relative_path(k))
+            .collect::<Vec<_>>(),
+    );
+    let files = target_files
+        .par_iter()
+        .zip(&relative_keys)
+        .map(|(f, relative_key)| {
+            let start = Instant::now();
+            let relative = relative_path(relative_key);
+            observer.file_started(&relative);
+            let bytes_in = file_len(f);
+            let result = is_file_encrypted(f).and_then(|encrypted| {
+                encrypted
+                    .then(|| {
+                        decrypt_file_with_progress(
+                            f,
+                            &key_cache,
+                            Some(CacheRef {
+                                sender: &sender,
+                                key: relative_key,
+                            }),
+                            key.as_bytes(),
+                            &mut |bytes| observer.bytes_processed(&relative, bytes),
+                        )
+                    })
+                    .transpose()
+            });
+            let report = match result {
+                Ok(Some(())) => FileReport::new(relative, FileOutcome::Ok, start)
+                    .with_bytes(bytes_in, file_len(f)),
+                Ok(None) => FileReport::new(relative, FileOutcome::Skipped, start),
+                Err(e) => FileReport::failed(relative, e, start),
+            };
+            notify(observer, report)
+        })
+        .collect();
 
     drop(sender);
     saver.save();
 
-    pb.finish_and_clear();
-
-    print_post_report(
-        "Decrypt",
-        target_files.len(),
-        skipped.load(Ordering::Relaxed),
-        failed.load(Ordering::Relaxed),
-    );
+    let report = BatchReport::new(FileAction::Decrypt, files, start);
+    observer.batch_finished(report.action, &report.summary());
+    Ok(report)
+}
 
-    if let Some(first) = result.into_iter().next() {
-        return Err(first);
-    }
+/// A [`cache_key`] as a path.
+fn relative_path(key: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(key).as_ref())
+}
 
-    Ok(())
+/// The size of `path`, 0 if it cannot be read.
+fn file_len(path: &Path) -> u64 {
+    std::fs::metadata(path).map_or(0, |m| m.len())
 }
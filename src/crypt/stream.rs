@@ -1,4 +1,7 @@
-use std::io::{Read, Write};
+use std::{
+    cell::Cell,
+    io::{Read, Write},
+};
 
 use chacha20poly1305::{
     XChaCha20Poly1305, XNonce,
@@ -8,14 +11,17 @@ use zeroize::Zeroizing;
 
 use crate::{
     crypt::{
-        header::{CHUNK_SIZE, FILE_ID_LEN, FileHeader, HEADER_LEN, NONCE_LEN},
-        key::{derive_key, derive_nonce, split_keys},
+        header::{
+            CHUNK_SIZE, FILE_ID_LEN, FileHeader, HEADER_LEN, MAGIC, NONCE_LEN, is_encrypted_version,
+        },
+        key::{KeyCache, derive_key, derive_nonce, get_or_derive_key, split_keys},
     },
     error::{Error, Result},
 };
 
 /// Streaming encryption loop: read plaintext chunks from `reader`, encrypt
 /// each with the cipher, and write `[NONCE | CIPHERTEXT | TAG]` to `writer`.
+/// `on_chunk` is called after every chunk is written.
 fn encrypt_chunks(
     reader: &mut dyn Read,
     writer: &mut dyn std::io::Write,
@@ -23,6 +29,7 @@ fn encrypt_chunks(
     key_mac: &[u8; 32],
     file_id: &[u8; FILE_ID_LEN],
     header_bytes: &[u8; HEADER_LEN],
+    on_chunk: &mut dyn FnMut(),
 ) -> Result<()> {
     let mut buffer = Zeroizing::new(vec![0u8; CHUNK_SIZE]);
     let mut out_buf: Vec<u8> = Vec::with_capacity(NONCE_LEN + CHUNK_SIZE + 16);
@@ -63,6 +70,7 @@ fn encrypt_chunks(
         out_buf.extend_from_slice(&nonce_bytes);
         out_buf.extend_from_slice(&ciphertext);
         writer.write_all(&out_buf)?;
+        on_chunk();
 
         chunk_idx += 1;
 
@@ -78,11 +86,14 @@ fn encrypt_chunks(
 /// and write plaintext to `writer`.
 ///
 /// Chunk layout: `[NONCE (24B)] [CIPHERTEXT] [TAG (16B)]`
+///
+/// `on_chunk` is called after every chunk is written.
 fn decrypt_chunks(
     reader: &mut dyn Read,
     writer: &mut dyn std::io::Write,
     cipher: &XChaCha20Poly1305,
     header_bytes: &[u8; HEADER_LEN],
+    on_chunk: &mut dyn FnMut(),
 ) -> Result<()> {
     let mut nonce_buf = [0u8; NONCE_LEN];
     let mut ct_buffer = Zeroizing::new(vec![0u8; CHUNK_SIZE + 16]);
@@ -133,6 +144,7 @@ fn decrypt_chunks(
         );
 
         writer.write_all(&plaintext)?;
+        on_chunk();
 
         chunk_idx += 1;
 
@@ -149,20 +161,83 @@ fn decrypt_chunks(
     Ok(())
 }
 
-/// Decrypt the body (with optional Zstd decompression)
+/// Counts the bytes read or written through it. Wrapped around the plaintext
+/// side of a stream, so that progress is reported in plaintext bytes even
+/// when zstd sits between the plaintext and the chunks.
+struct Counted<'a, T> {
+    inner: T,
+    count: &'a Cell<u64>,
+}
+
+impl<R: Read> Read for Counted<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+}
+
+impl<W: Write> Write for Counted<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count.set(self.count.get() + n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A callback that passes to `progress` how much `count` grew since its last
+/// call, if at all.
+fn report_growth<'a>(count: &'a Cell<u64>, progress: &'a mut dyn FnMut(u64)) -> impl FnMut() + 'a {
+    let mut reported = 0;
+    move || {
+        let total = count.get();
+        if total > reported {
+            progress(total - reported);
+            reported = total;
+        }
+    }
+}
+
+/// Decrypt the body (with optional Zstd decompression). `progress` receives
+/// the plaintext bytes written, once per chunk.
 pub(super) fn decrypt_body(
     reader: &mut dyn Read,
     writer: &mut dyn std::io::Write,
     cipher: &XChaCha20Poly1305,
     header: &FileHeader,
+    progress: &mut dyn FnMut(u64),
 ) -> Result<()> {
+    let count = Cell::new(0);
+    let mut on_chunk = report_growth(&count, progress);
+    let mut writer = Counted {
+        inner: writer,
+        count: &count,
+    };
     if header.is_compressed() {
-        let mut decoder = zstd::stream::write::Decoder::new(writer)?.auto_flush();
-        decrypt_chunks(reader, &mut decoder, cipher, header.as_bytes())?;
+        let mut decoder = zstd::stream::write::Decoder::new(&mut writer)?.auto_flush();
+        decrypt_chunks(
+            reader,
+            &mut decoder,
+            cipher,
+            header.as_bytes(),
+            &mut on_chunk,
+        )?;
         decoder.flush()?;
     } else {
-        decrypt_chunks(reader, writer, cipher, header.as_bytes())?;
+        decrypt_chunks(
+            reader,
+            &mut writer,
+            cipher,
+            header.as_bytes(),
+            &mut on_chunk,
+        )?;
     }
+    // Whatever the decoder still held back.
+    on_chunk();
     Ok(())
 }
 
@@ -174,16 +249,44 @@ pub fn encrypt_into<R: Read, W: std::io::Write>(
     salt: [u8; crate::crypt::header::SALT_LEN],
     file_id: Option<[u8; FILE_ID_LEN]>,
     zstd: Option<u8>,
+) -> Result<FileHeader> {
+    encrypt_into_with_progress(
+        reader,
+        writer,
+        derived_key,
+        salt,
+        file_id,
+        zstd,
+        &mut |_| {},
+    )
+}
+
+/// [`encrypt_into`], passing the plaintext bytes read to `progress` once per
+/// chunk.
+pub fn encrypt_into_with_progress(
+    reader: &mut dyn Read,
+    writer: &mut dyn std::io::Write,
+    derived_key: &[u8; 32],
+    salt: [u8; crate::crypt::header::SALT_LEN],
+    file_id: Option<[u8; FILE_ID_LEN]>,
+    zstd: Option<u8>,
+    progress: &mut dyn FnMut(u64),
 ) -> Result<FileHeader> {
     let file_id = file_id.unwrap_or_else(FileHeader::generate_file_id);
     let header = FileHeader::new(zstd.is_some(), salt, file_id);
-    header.write_to(writer)?;
+    writer.write_all(header.as_bytes())?;
 
     let (key_enc, key_mac) = split_keys(derived_key);
     let cipher = XChaCha20Poly1305::new(key_enc.as_ref().into());
 
+    let count = Cell::new(0);
+    let mut on_chunk = report_growth(&count, progress);
+    let mut reader = Counted {
+        inner: reader,
+        count: &count,
+    };
     if let Some(level) = zstd {
-        let mut encoder = zstd::stream::read::Encoder::new(reader, i32::from(level))?;
+        let mut encoder = zstd::stream::read::Encoder::new(&mut reader, i32::from(level))?;
         encrypt_chunks(
             &mut encoder,
             writer,
@@ -191,15 +294,17 @@ pub fn encrypt_into<R: Read, W: std::io::Write>(
             &key_mac,
             &file_id,
             header.as_bytes(),
+            &mut on_chunk,
         )?;
     } else {
         encrypt_chunks(
-            reader,
+            &mut reader,
             writer,
             &cipher,
             &key_mac,
             &file_id,
             header.as_bytes(),
+            &mut on_chunk,
         )?;
     }
 
@@ -218,6 +323,102 @@ pub fn decrypt_into<R: Read, W: std::io::Write>(
     let (key_enc, _) = split_keys(&derived_key);
     let cipher = XChaCha20Poly1305::new(key_enc.as_ref().into());
 
-    decrypt_body(reader, writer, &cipher, &header)?;
+    decrypt_body(reader, writer, &cipher, &header, &mut |_| {})?;
+    Ok(header)
+}
+
+/// Decrypt data from `reader` into `writer` if it starts with a GITSE header,
+/// otherwise copy it through unchanged.
+///
+/// Uses the same magic/version check as [`crate::crypt::decrypt_file_to`].
+/// Returns the header if the data was decrypted.
+pub fn decrypt_or_copy_into<R: Read, W: std::io::Write>(
+    reader: &mut R,
+    writer: &mut W,
+    master_key: &[u8],
+) -> Result<Option<FileHeader>> {
+    let mut head = Vec::with_capacity(HEADER_LEN);
+    reader
+        .by_ref()
+        .take(HEADER_LEN as u64)
+        .read_to_end(&mut head)?;
+    if head.len() < HEADER_LEN || &head[0..5] != MAGIC || !is_encrypted_version(head[5]) {
+        writer.write_all(&head)?;
+        std::io::copy(reader, writer)?;
+        return Ok(None);
+    }
+    decrypt_into(&mut head.as_slice().chain(reader), writer, master_key).map(Some)
+}
+
+/// Like [`decrypt_into`], but reuses derived keys from a shared cache.
+pub fn decrypt_into_cached<R: Read, W: std::io::Write>(
+    reader: &mut R,
+    writer: &mut W,
+    key_cache: &KeyCache,
+    master_key: &[u8],
+) -> Result<FileHeader> {
+    let header = FileHeader::read_from(reader)?;
+
+    let derived_key = get_or_derive_key(key_cache, master_key, &header.salt)?;
+    let (key_enc, _) = split_keys(&derived_key);
+    let cipher = XChaCha20Poly1305::new(key_enc.as_ref().into());
+
+    decrypt_body(reader, writer, &cipher, &header, &mut |_| {})?;
+    Ok(header)
+}
+
+/// Check that `master_key` opens the data in `reader` by decrypting only its
+/// first chunk. Much cheaper than a full decryption for large files.
+///
+/// A wrong key and a tampered first chunk are indistinguishable here; both
+/// surface as [`Error::DecryptFailed`].
+pub fn probe_key<R: Read>(
+    reader: &mut R,
+    key_cache: &KeyCache,
+    master_key: &[u8],
+) -> Result<FileHeader> {
+    let header = FileHeader::read_from(reader)?;
+
+    let derived_key = get_or_derive_key(key_cache, master_key, &header.salt)?;
+    let (key_enc, _) = split_keys(&derived_key);
+    let cipher = XChaCha20Poly1305::new(key_enc.as_ref().into());
+
+    let mut nonce_buf = [0u8; NONCE_LEN];
+    match reader.read_exact(&mut nonce_buf) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            return Err(Error::FileTruncated);
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    let mut ct_buffer = vec![0u8; CHUNK_SIZE + 16];
+    let mut bytes_read = 0;
+    while bytes_read < ct_buffer.len() {
+        let n = reader.read(&mut ct_buffer[bytes_read..])?;
+        if n == 0 {
+            break;
+        }
+        bytes_read += n;
+    }
+    if bytes_read == 0 {
+        return Err(Error::TruncatedChunk);
+    }
+
+    let mut aad = [0u8; HEADER_LEN + 9];
+    aad[..HEADER_LEN].copy_from_slice(header.as_bytes());
+    aad[HEADER_LEN + 8] = u8::from(bytes_read < ct_buffer.len());
+
+    let payload = Payload {
+        msg: &ct_buffer[..bytes_read],
+        aad: &aad,
+    };
+    // Drop the plaintext right away; only the authentication result matters.
+    drop(Zeroizing::new(
+        cipher
+            .decrypt(&XNonce::from(nonce_buf), payload)
+            .map_err(|e| Error::DecryptFailed(e.to_string()))?,
+    ));
+
     Ok(header)
 }
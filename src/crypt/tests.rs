@@ -12,7 +12,12 @@ use super::{
     file::*,
     header::*,
     key::*,
-    stream::{decrypt_into, encrypt_into},
+    stream::{decrypt_into, decrypt_or_copy_into, encrypt_into},
+};
+use crate::{
+    error::Error,
+    observer::{NoopObserver, Observer},
+    report::{FileAction, Summary},
 };
 
 // --- Helper Functions ---
@@ -341,7 +346,7 @@ fn test_metadata_preservation() {
 
     let plaintext = b"Executable script content";
     let file = create_temp_file(plaintext);
-    let path = file.path();
+    let path: &Path = &file;
 
     let mut perms = std::fs::metadata(path).unwrap().permissions();
     perms.set_mode(0o755);
@@ -443,6 +448,34 @@ fn test_stream_encrypt_decrypt_roundtrip() {
     assert_eq!(header.file_id, dec_header.file_id);
 }
 
+#[test]
+fn test_decrypt_or_copy_into() {
+    let plaintext = b"shown as-is or decrypted";
+    let (key, salt) = get_test_key_and_salt();
+    let master_key = b"super_secret_password";
+
+    // Plaintext (even shorter than a header) passes through unchanged.
+    let mut out = Vec::new();
+    let header = decrypt_or_copy_into(&mut &plaintext[..], &mut out, master_key).unwrap();
+    assert!(header.is_none());
+    assert_eq!(out, plaintext);
+
+    let mut ciphertext = Vec::new();
+    encrypt_into(
+        &mut &plaintext[..],
+        &mut ciphertext,
+        &key,
+        salt,
+        None,
+        Some(3),
+    )
+    .unwrap();
+    let mut out = Vec::new();
+    let header = decrypt_or_copy_into(&mut ciphertext.as_slice(), &mut out, master_key).unwrap();
+    assert!(header.is_some_and(|h| h.is_compressed()));
+    assert_eq!(out, plaintext);
+}
+
 #[test]
 fn test_stream_encrypt_with_compression() {
     let plaintext = b"X".repeat(50_000);
@@ -586,16 +619,24 @@ fn test_decrypt_files_to_batch() {
     let sources: Vec<PathBuf> = temp_paths.iter().map(PathBuf::from).collect();
 
     let out_dir = tempfile::TempDir::new().unwrap();
-    let summary = decrypt_files_to(&sources, master_key, |src: &Path| {
-        Some(out_dir.path().join(src.file_name().unwrap()))
-    })
+    let report = decrypt_files_to(
+        &sources,
+        master_key,
+        |src: &Path| Some(out_dir.path().join(src.file_name().unwrap())),
+        &NoopObserver,
+    )
     .unwrap();
 
-    assert_eq!(summary.total, 3);
-    assert_eq!(summary.succeeded, 3);
-    assert_eq!(summary.skipped, 0);
-    assert_eq!(summary.failed, 0);
-    assert!(summary.is_ok());
+    assert_eq!(
+        report.summary(),
+        Summary {
+            total: 3,
+            succeeded: 3,
+            skipped: 0,
+            failed: 0,
+        }
+    );
+    assert!(report.result().is_ok());
 
     for (i, src) in sources.iter().enumerate() {
         let dec_path = out_dir.path().join(src.file_name().unwrap());
@@ -614,19 +655,36 @@ fn test_decrypt_files_to_skips_non_encrypted() {
     let sources: Vec<PathBuf> = temp_paths.iter().map(PathBuf::from).collect();
 
     let out_dir = tempfile::TempDir::new().unwrap();
-    let summary = decrypt_files_to(&sources, b"any", |src: &Path| {
-        Some(out_dir.path().join(src.file_name().unwrap()))
-    })
+    let report = decrypt_files_to(
+        &sources,
+        b"any",
+        |src: &Path| Some(out_dir.path().join(src.file_name().unwrap())),
+        &NoopObserver,
+    )
     .unwrap();
 
-    assert_eq!(summary.total, 3);
-    assert_eq!(summary.succeeded, 0);
-    assert_eq!(summary.skipped, 3);
-    assert_eq!(summary.failed, 0);
+    assert_eq!(
+        report.summary(),
+        Summary {
+            total: 3,
+            succeeded: 0,
+            skipped: 3,
+            failed: 0,
+        }
+    );
 }
 
 #[test]
 fn test_decrypt_files_to_mapper_skip() {
+    /// Records the files passed to `batch_started`.
+    #[derive(Default)]
+    struct Started(parking_lot::Mutex<Vec<PathBuf>>);
+    impl Observer for Started {
+        fn batch_started(&self, _action: FileAction, files: &[PathBuf]) {
+            self.0.lock().extend_from_slice(files);
+        }
+    }
+
     let master_key = b"batch_password";
     let mut salt = [0u8; SALT_LEN];
     rand::rng().fill_bytes(&mut salt);
@@ -645,17 +703,28 @@ fn test_decrypt_files_to_mapper_skip() {
 
     let out_dir = tempfile::TempDir::new().unwrap();
     let skip_path = sources[1].clone();
-    let summary = decrypt_files_to(&sources, master_key, |src: &Path| {
-        if src == skip_path.as_path() {
-            None
-        } else {
-            Some(out_dir.path().join(src.file_name().unwrap()))
-        }
-    })
+    let started = Started::default();
+    let report = decrypt_files_to(
+        &sources,
+        master_key,
+        |src: &Path| {
+            if src == skip_path.as_path() {
+                None
+            } else {
+                Some(out_dir.path().join(src.file_name().unwrap()))
+            }
+        },
+        &started,
+    )
     .unwrap();
 
-    assert_eq!(summary.succeeded, 2);
-    assert!(summary.is_ok());
+    assert_eq!(report.summary().total, 2);
+    assert_eq!(report.summary().succeeded, 2);
+    assert!(report.files.iter().all(|f| f.path != skip_path));
+    assert_eq!(
+        started.0.into_inner(),
+        [sources[0].clone(), sources[2].clone()]
+    );
 }
 
 #[test]
@@ -668,18 +737,18 @@ fn test_encrypt_files_to_batch() {
     let sources: Vec<PathBuf> = temp_paths.iter().map(PathBuf::from).collect();
 
     let out_dir = tempfile::TempDir::new().unwrap();
-    let summary = encrypt_files_to(
+    let report = encrypt_files_to(
         &sources,
         master_key,
         |src: &Path| Some(out_dir.path().join(src.file_name().unwrap())),
         None,
+        &NoopObserver,
     )
     .unwrap();
 
-    assert_eq!(summary.total, 3);
-    assert_eq!(summary.succeeded, 3);
-    assert_eq!(summary.failed, 0);
-    assert!(summary.is_ok());
+    assert_eq!(report.summary().total, 3);
+    assert_eq!(report.summary().succeeded, 3);
+    assert!(report.result().is_ok());
 
     for (i, src) in sources.iter().enumerate() {
         let enc_path = out_dir.path().join(src.file_name().unwrap());
@@ -695,6 +764,85 @@ fn test_encrypt_files_to_batch() {
     }
 }
 
+#[test]
+#[allow(deprecated)]
+fn test_encrypt_files_to_failure() {
+    let source = create_temp_file(b"kept");
+    let missing = source.with_extension("missing");
+    let sources = vec![source.to_path_buf(), missing.clone()];
+
+    let out_dir = tempfile::TempDir::new().unwrap();
+    let report = encrypt_files_to(
+        &sources,
+        b"batch_failure_password",
+        |src: &Path| Some(out_dir.path().join(src.file_name().unwrap())),
+        None,
+        &NoopObserver,
+    )
+    .unwrap();
+
+    // The failure is reported per file, the other file is still encrypted.
+    assert_eq!(
+        (report.summary().succeeded, report.summary().failed),
+        (1, 1)
+    );
+    assert!(matches!(
+        report.result(),
+        Err(Error::BatchFailed("encrypt", 1, 2))
+    ));
+    let summary = BatchSummary::from(report);
+    assert_eq!(summary.errors.len(), 1);
+    assert_eq!(summary.errors[0].0, missing);
+}
+
+#[test]
+fn test_files_to_report_bytes_per_chunk() {
+    /// Records the sizes passed to `bytes_processed`.
+    #[derive(Default)]
+    struct Chunks(parking_lot::Mutex<Vec<u64>>);
+    impl Observer for Chunks {
+        fn bytes_processed(&self, _path: &Path, bytes: u64) {
+            self.0.lock().push(bytes);
+        }
+    }
+
+    let master_key = b"batch_chunk_password";
+    let len = CHUNK_SIZE * 5 / 2;
+    let temp_path = create_temp_file(&b"Y".repeat(len));
+    let out_dir = tempfile::TempDir::new().unwrap();
+    let to = |dir: &str| {
+        let dir = out_dir.path().join(dir);
+        move |src: &Path| Some(dir.join(src.file_name().unwrap()))
+    };
+    let full = CHUNK_SIZE as u64;
+    let expected = [full, full, full / 2];
+
+    let chunks = Chunks::default();
+    encrypt_files_to([&temp_path], master_key, to("enc"), None, &chunks).unwrap();
+    assert_eq!(*chunks.0.lock(), expected);
+
+    let chunks = Chunks::default();
+    let encrypted = out_dir
+        .path()
+        .join("enc")
+        .join(temp_path.file_name().unwrap());
+    decrypt_files_to([&encrypted], master_key, to("dec"), &chunks).unwrap();
+    assert_eq!(*chunks.0.lock(), expected);
+
+    // Compressed, the chunks no longer line up with the plaintext, but the
+    // plaintext bytes still add up.
+    let chunks = Chunks::default();
+    encrypt_files_to([&temp_path], master_key, to("zstd"), Some(3), &chunks).unwrap();
+    assert_eq!(chunks.0.lock().iter().sum::<u64>(), len as u64);
+    let chunks = Chunks::default();
+    let compressed = out_dir
+        .path()
+        .join("zstd")
+        .join(temp_path.file_name().unwrap());
+    decrypt_files_to([&compressed], master_key, to("unzstd"), &chunks).unwrap();
+    assert_eq!(chunks.0.lock().iter().sum::<u64>(), len as u64);
+}
+
 #[test]
 fn test_encrypt_files_to_with_compression() {
     let master_key = b"batch_compress_password";
@@ -703,15 +851,16 @@ fn test_encrypt_files_to_with_compression() {
     let sources: Vec<PathBuf> = vec![temp_path.to_path_buf()];
 
     let out_dir = tempfile::TempDir::new().unwrap();
-    let summary = encrypt_files_to(
+    let report = encrypt_files_to(
         &sources,
         master_key,
         |src: &Path| Some(out_dir.path().join(src.file_name().unwrap())),
         Some(15),
+        &NoopObserver,
     )
     .unwrap();
 
-    assert_eq!(summary.succeeded, 1);
+    assert_eq!(report.summary().succeeded, 1);
 
     let enc_path = out_dir.path().join(sources[0].file_name().unwrap());
     assert!(std::fs::metadata(&enc_path).unwrap().len() < 5_000);
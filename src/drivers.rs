@@ -0,0 +1,145 @@
+//! Custom git drivers that let git show encrypted files as plaintext.
+//!
+//! - **textconv** (`git-se textconv <file>`): registered as
+//!   `diff.git-se.textconv`, so `git diff`, `git log -p` and `git show` print
+//!   the decrypted content of every path tagged `diff=git-se`.
+//! - **merge** (`git-se merge %O %A %B %P`): registered as
+//!   `merge.git-se.driver`. Decrypts base, ours and theirs in memory, runs a
+//!   textual three-way merge and re-encrypts the result with ours' salt and
+//!   `file_id`, so a merge that changes nothing keeps ours' ciphertext.
+//!
+//! The drivers only affect the local clone: git config is per clone, and the
+//! `.gitattributes` tags are inert wherever the driver is not registered.
+
+use std::{fs, io::Write, path::Path};
+
+use tempfile::NamedTempFile;
+
+use config_file2::Storable;
+use log::{debug, info, warn};
+
+use crate::{
+    attributes::{self, DRIVER_NAME},
+    crypt::{FileHeader, decrypt_or_copy_into, derive_key, encrypt_into},
+    error::{Error, Result},
+    repo::Repo,
+};
+
+/// Write the plaintext of `path` to `writer`. Files that are not encrypted are
+/// copied through unchanged.
+pub fn textconv(repo: &Repo, path: &Path, writer: &mut impl Write) -> Result<()> {
+    let key = repo.get_key()?;
+    let mut file = fs::File::open(path)?;
+    decrypt_or_copy_into(&mut file, writer, key.as_bytes())?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Three-way merge `base`, `ours` and `theirs`, writing the result to `ours`
+/// (git's `%O %A %B`). `path` (git's `%P`) is only used for messages.
+///
+/// Each side is decrypted in memory (plaintext sides are used as-is). The
+/// result is encrypted again if any side was encrypted, reusing the header of
+/// ours, falling back to theirs, then base. On conflict the result still
+/// contains the conflict markers (encrypted), and [`Error::MergeConflict`] is
+/// returned so git marks the path as conflicted; run `git-se d <path>` to
+/// resolve it.
+pub fn merge(repo: &Repo, base: &Path, ours: &Path, theirs: &Path, path: &str) -> Result<()> {
+    let key = repo.get_key()?;
+    let read = |p: &Path| -> Result<(Vec<u8>, Option<FileHeader>)> {
+        let mut plaintext = Vec::new();
+        let header = decrypt_or_copy_into(&mut fs::File::open(p)?, &mut plaintext, key.as_bytes())?;
+        Ok((plaintext, header))
+    };
+    let (base_text, base_header) = read(base)?;
+    let (ours_text, ours_header) = read(ours)?;
+    let (theirs_text, theirs_header) = read(theirs)?;
+
+    let (merged, clean) = match diffy::merge_bytes(&base_text, &ours_text, &theirs_text) {
+        Ok(merged) => (merged, true),
+        Err(conflicted) => (conflicted, false),
+    };
+    debug!("merged {path}: clean = {clean}");
+
+    let parent = ours.parent().unwrap_or_else(|| Path::new("."));
+    let mut temp_file = NamedTempFile::new_in(parent)?;
+    if let Some(header) = ours_header.or(theirs_header).or(base_header) {
+        let derived_key = derive_key(key.as_bytes(), &header.salt)?;
+        let level = repo.conf.rule_set()?.settings_for(path).zstd_level;
+        let zstd = header.is_compressed().then_some(level);
+        encrypt_into(
+            &mut merged.as_slice(),
+            &mut temp_file,
+            &derived_key,
+            header.salt,
+            Some(header.file_id),
+            zstd,
+        )?;
+    } else {
+        warn!("No side of {path} is encrypted, writing the merge result as plaintext");
+        temp_file.write_all(&merged)?;
+    }
+    temp_file
+        .persist(ours)
+        .map_err(|e| Error::AtomicPersist(ours.to_path_buf(), e.to_string()))?;
+
+    if clean {
+        Ok(())
+    } else {
+        Err(Error::MergeConflict(path.to_owned()))
+    }
+}
+
+/// Register the textconv diff driver in the repo-local git config and tag the
+/// crypt list paths with `diff=git-se` in `.gitattributes`.
+pub fn install_diff_driver(repo: &mut Repo) -> Result<()> {
+    let section = format!("diff.{DRIVER_NAME}");
+    repo.run(&[
+        "config",
+        "--local",
+        &format!("{section}.textconv"),
+        "git-se textconv",
+    ])?;
+    repo.run(&[
+        "config",
+        "--local",
+        &format!("{section}.cachetextconv"),
+        "true",
+    ])?;
+
+    repo.conf.diff_driver = true;
+    repo.conf
+        .store()
+        .map_err(|e| Error::Config(e.to_string()))?;
+    attributes::sync(repo)?;
+
+    info!("Registered `{section}` in git config");
+    Ok(())
+}
+
+/// Register the three-way merge driver in the repo-local git config and tag
+/// the crypt list paths with `merge=git-se` in `.gitattributes`.
+pub fn install_merge_driver(repo: &mut Repo) -> Result<()> {
+    let section = format!("merge.{DRIVER_NAME}");
+    repo.run(&[
+        "config",
+        "--local",
+        &format!("{section}.name"),
+        "git-se encrypted file merge",
+    ])?;
+    repo.run(&[
+        "config",
+        "--local",
+        &format!("{section}.driver"),
+        "git-se merge %O %A %B %P",
+    ])?;
+
+    repo.conf.merge_driver = true;
+    repo.conf
+        .store()
+        .map_err(|e| Error::Config(e.to_string()))?;
+    attributes::sync(repo)?;
+
+    info!("Registered `{section}` in git config");
+    Ok(())
+}
@@ -40,6 +40,19 @@ pub enum Error {
     #[error("password must not be empty")]
     EmptyPassword,
 
+    /// The two passwords entered when setting a new one differ.
+    #[error("passwords do not match")]
+    PasswordMismatch,
+
+    /// A new password does not meet the configured `password_policy`.
+    #[error("password too weak: {0}")]
+    WeakPassword(String),
+
+    /// A password has to be entered, but there is no terminal to read it
+    /// from.
+    #[error("no terminal to read the password from; pipe it to `git-se pwd --stdin` instead")]
+    NoTerminal,
+
     /// Operation had no target files to act on.
     #[error("no file to {0}")]
     NoFile(&'static str),
@@ -90,13 +103,61 @@ pub enum Error {
     Git(String),
 
     /// A pre-commit hook already exists at the target path.
+    #[deprecated(note = "hooks are chained with existing ones now, this is never returned")]
     #[error("a pre-commit hook already exists at {0}; remove it manually before installing")]
     HookExists(PathBuf),
 
+    /// Some files of an `encrypt`, `decrypt` or `stage` batch failed, or
+    /// `check` found files it could not verify but none unencrypted; the
+    /// errors are in the [`BatchReport`](crate::report::BatchReport). The
+    /// counts are `(failed, total)`.
+    #[error("failed to {0} {1} out of {2} files")]
+    BatchFailed(&'static str, usize, usize),
+
     /// `check` found unencrypted files. The count is `(unencrypted, total)`.
     #[error("{0} out of {1} files are not encrypted")]
     FilesNotEncrypted(usize, usize),
 
+    /// `verify-history` found blobs that do not decrypt with the current key.
+    #[error("{0} blobs in history failed verification")]
+    HistoryVerifyFailed(usize),
+
+    /// `leaks` found plaintext blobs at crypt list paths in history.
+    #[error("{0} plaintext blobs found in history")]
+    LeaksFound(usize),
+
+    /// `pre-receive` found files in the crypt list pushed without encryption.
+    #[error("push rejected: {0} files in the crypt list are not encrypted")]
+    PlaintextPushed(usize),
+
+    /// The working tree has uncommitted changes to tracked files.
+    #[error("working tree has uncommitted changes; commit or stash them first")]
+    DirtyWorktree,
+
+    /// The merge driver could not merge a file cleanly; the result contains
+    /// conflict markers.
+    #[error("merge conflict in {0}")]
+    MergeConflict(String),
+
+    /// A command run with `--recurse-submodules` failed in several
+    /// repositories; the errors were logged.
+    #[error("failed in {}", .0.join(", "))]
+    SubmodulesFailed(Vec<String>),
+
+    /// A `crypt_list` or `exclude` entry is not a valid glob.
+    #[error("invalid crypt list pattern {0}")]
+    InvalidPattern(String),
+
+    /// `rm` was given a path that is not in the crypt list.
+    #[error("{0} is not in the crypt list")]
+    NotInCryptList(String),
+
+    /// `rm` was given a path that is only covered by another entry.
+    #[error(
+        "{0} is covered by the crypt list entry `{1}`; remove that entry, or add `!{0}` to exclude the path"
+    )]
+    CoveredByEntry(String, String),
+
     /// Config file parse/serialize error.
     #[error("config error: {0}")]
     Config(String),
@@ -114,5 +175,54 @@ pub enum Error {
     Other(String),
 }
 
+impl Error {
+    /// A stable `snake_case` name of the variant, for machine-readable
+    /// output.
+    #[must_use]
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::RepoPathNotAbsolute(_) => "repo_path_not_absolute",
+            Self::RepoNotFound(_) => "repo_not_found",
+            Self::NotADirectory(_) => "not_a_directory",
+            Self::PathNotExist(_) => "path_not_exist",
+            Self::PathNotRelative(_) => "path_not_relative",
+            Self::EmptyKey => "empty_key",
+            Self::EmptyPassword => "empty_password",
+            Self::PasswordMismatch => "password_mismatch",
+            Self::WeakPassword(_) => "weak_password",
+            Self::NoTerminal => "no_terminal",
+            Self::NoFile(_) => "no_file",
+            Self::InvalidMagic => "invalid_magic",
+            Self::UnsupportedVersion(_) => "unsupported_version",
+            Self::UnsupportedAlgo(_) => "unsupported_algo",
+            Self::CorruptHeader(_) => "corrupt_header",
+            Self::EncryptFailed(_) => "encrypt_failed",
+            Self::DecryptFailed(_) => "decrypt_failed",
+            Self::Argon2(_) => "argon2",
+            Self::TruncatedChunk => "truncated_chunk",
+            Self::FileTruncated => "file_truncated",
+            Self::AtomicPersist(..) => "atomic_persist",
+            Self::Git(_) => "git",
+            #[allow(deprecated)]
+            Self::HookExists(_) => "hook_exists",
+            Self::BatchFailed(..) => "batch_failed",
+            Self::FilesNotEncrypted(..) => "files_not_encrypted",
+            Self::HistoryVerifyFailed(_) => "history_verify_failed",
+            Self::LeaksFound(_) => "leaks_found",
+            Self::PlaintextPushed(_) => "plaintext_pushed",
+            Self::DirtyWorktree => "dirty_worktree",
+            Self::MergeConflict(_) => "merge_conflict",
+            Self::SubmodulesFailed(_) => "submodules_failed",
+            Self::InvalidPattern(_) => "invalid_pattern",
+            Self::NotInCryptList(_) => "not_in_crypt_list",
+            Self::CoveredByEntry(..) => "covered_by_entry",
+            Self::Config(_) => "config",
+            Self::Io(_) => "io",
+            Self::SaltCache(_) => "salt_cache",
+            Self::Other(_) => "other",
+        }
+    }
+}
+
 /// Convenience alias used throughout the crate.
 pub type Result<T, E = Error> = std::result::Result<T, E>;
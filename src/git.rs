@@ -0,0 +1,128 @@
+//! Thin wrappers around git plumbing commands.
+//!
+//! Everything here shells out to the `git` binary (like the rest of the
+//! crate) instead of linking a git library, so behaviour always matches the
+//! user's installed git, including its config and hooks.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    process::{Child, ChildStdin, ChildStdout, Command, Stdio},
+};
+
+use crate::{
+    error::{Error, Result},
+    repo::Repo,
+};
+
+/// A long-running `git cat-file --batch` process.
+///
+/// Spawning one git process per object is far too slow when reading many
+/// blobs, so the objects are requested one by one over the same pipe.
+pub struct CatFile {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl CatFile {
+    /// Spawn `git cat-file --batch` inside the given repo.
+    pub fn spawn(repo: &Repo) -> Result<Self> {
+        let mut child = repo
+            .git_command()
+            .args(["cat-file", "--batch"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        Ok(Self {
+            child,
+            stdin,
+            stdout,
+        })
+    }
+
+    /// Read the object named by `object` (an oid, or any revision syntax such
+    /// as `HEAD:path` or `:path` for the index).
+    ///
+    /// Returns `None` if the object does not exist.
+    pub fn read(&mut self, object: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.read_with_oid(object)?.map(|(_, content)| content))
+    }
+
+    /// Like [`Self::read`], but also returns the object id `object` resolved
+    /// to.
+    pub fn read_with_oid(&mut self, object: &str) -> Result<Option<(String, Vec<u8>)>> {
+        writeln!(self.stdin, "{object}")?;
+        self.stdin.flush()?;
+
+        let mut line = String::new();
+        self.stdout.read_line(&mut line)?;
+        let line = line.trim_end();
+        // `<oid> <type> <size>` on success, `<object> missing` (or
+        // `ambiguous`) otherwise.
+        if line.ends_with(" missing") || line.ends_with(" ambiguous") {
+            return Ok(None);
+        }
+        let mut fields = line.rsplitn(3, ' ');
+        let (Some(size), Some(_kind), Some(oid)) = (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(Error::Git(format!("unexpected cat-file output: {line}")));
+        };
+        let size: usize = size
+            .parse()
+            .map_err(|_| Error::Git(format!("unexpected cat-file output: {line}")))?;
+
+        let mut content = vec![0u8; size];
+        self.stdout.read_exact(&mut content)?;
+        // Every object is followed by a single LF.
+        let mut lf = [0u8; 1];
+        self.stdout.read_exact(&mut lf)?;
+        Ok(Some((oid.to_owned(), content)))
+    }
+}
+
+impl Drop for CatFile {
+    fn drop(&mut self) {
+        // Reap the child so no zombie is left behind.
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Run a prepared git `command`, feeding `input` to its stdin, and return its
+/// stdout.
+pub fn run_with_input(mut command: Command, input: &[u8]) -> Result<Vec<u8>> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    // Some commands (`git check-attr --stdin`) write while they still read,
+    // so stdin is fed from another thread while stdout is drained here.
+    // Dropping `stdin` at the end of the thread closes the pipe.
+    let (written, output) = std::thread::scope(|scope| {
+        let writer = scope.spawn(move || stdin.write_all(input));
+        let output = child.wait_with_output();
+        (writer.join().expect("stdin writer panicked"), output)
+    });
+    let output = output?;
+    if !output.status.success() {
+        return Err(Error::Git(
+            String::from_utf8_lossy(&output.stderr).into_owned(),
+        ));
+    }
+    written?;
+    Ok(output.stdout)
+}
+
+/// Write `content` to the object database as an object of type `kind`
+/// (`blob`, `commit`, `tag`) and return its id.
+pub fn hash_object(repo: &Repo, kind: &str, content: &[u8]) -> Result<String> {
+    let mut command = repo.git_command();
+    command.args(["hash-object", "-t", kind, "-w", "--stdin"]);
+    let oid = run_with_input(command, content)?;
+    Ok(String::from_utf8_lossy(&oid).trim().to_owned())
+}
@@ -0,0 +1,170 @@
+//! `git-se leaks`: find secrets that were committed in plaintext.
+//!
+//! The pre-commit hook only guards new commits. This scans the history of
+//! every ref for blobs at crypt-list paths that lack the GITSE magic. Paths
+//! that were later renamed into the crypt list are scanned too, since their
+//! old names hold the same secret.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::Serialize;
+
+use super::{BACKUP_REF_PREFIX, ls_tree, rev_list};
+use crate::{
+    config::CryptMatcher,
+    crypt::MAGIC,
+    error::{Error, Result},
+    git::CatFile,
+    repo::Repo,
+    utils::{progress::Progress, style::Colorize},
+};
+
+/// A plaintext blob at a sensitive path, reported once for the commit that
+/// introduced it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Leak {
+    pub commit: String,
+    pub path: String,
+    pub oid: String,
+    /// `Name <email>` of the commit author.
+    pub author: String,
+    /// Author date, strict ISO 8601.
+    pub date: String,
+}
+
+/// Scan the history of all refs for plaintext blobs at crypt-list paths
+/// (including their names before a rename), oldest first.
+pub fn leaks(repo: &Repo) -> Result<Vec<Leak>> {
+    let matcher = repo.conf.matcher()?;
+    let renamed = renamed_into_crypt_list(repo, &matcher)?;
+    let is_sensitive = |path: &str| matcher.matches(path) || renamed.contains(path);
+
+    let commits = rev_list(repo, &["--reverse"], &[])?;
+    let mut cat_file = CatFile::spawn(repo)?;
+    let mut seen = HashSet::new();
+    let mut is_plaintext = HashMap::new();
+    let mut leaks = Vec::new();
+    let pb = Progress::new(commits.len(), "Scan");
+
+    for commit in &commits {
+        for entry in ls_tree(repo, commit)? {
+            if !is_sensitive(&entry.path) || !seen.insert((entry.path.clone(), entry.oid.clone())) {
+                continue;
+            }
+            let plaintext = if let Some(&plaintext) = is_plaintext.get(&entry.oid) {
+                plaintext
+            } else {
+                let blob = cat_file
+                    .read(&entry.oid)?
+                    .ok_or_else(|| Error::Git(format!("missing object {}", entry.oid)))?;
+                let plaintext = !blob.starts_with(MAGIC);
+                is_plaintext.insert(entry.oid.clone(), plaintext);
+                plaintext
+            };
+            if plaintext {
+                let (author, date) = author_of(repo, commit)?;
+                leaks.push(Leak {
+                    commit: commit.clone(),
+                    path: entry.path,
+                    oid: entry.oid,
+                    author,
+                    date,
+                });
+            }
+        }
+        pb.inc(1);
+    }
+    pb.finish_and_clear();
+    Ok(leaks)
+}
+
+/// Every path that was renamed, directly or through a chain of renames, to a
+/// path in the crypt list.
+fn renamed_into_crypt_list(repo: &Repo, matcher: &CryptMatcher) -> Result<HashSet<String>> {
+    let exclude_backups = format!("--exclude={BACKUP_REF_PREFIX}*");
+    let output = repo.run_with_bytes(&[
+        "log",
+        &exclude_backups,
+        "--all",
+        "-M",
+        "--diff-filter=R",
+        "--name-status",
+        "-z",
+        "--format=",
+    ])?;
+    let pairs = parse_renames(&output);
+
+    let mut renamed = HashSet::new();
+    loop {
+        let before = renamed.len();
+        for (old, new) in &pairs {
+            if matcher.matches(new) || renamed.contains(new) {
+                renamed.insert(old.clone());
+            }
+        }
+        if renamed.len() == before {
+            return Ok(renamed);
+        }
+    }
+}
+
+/// Parse `git log --name-status -z` output into `(old, new)` rename pairs.
+fn parse_renames(output: &[u8]) -> Vec<(String, String)> {
+    let mut fields = output
+        .split(|&b| b == 0)
+        .map(|f| String::from_utf8_lossy(f).trim_matches('\n').to_owned());
+    let mut renames = Vec::new();
+    while let Some(status) = fields.next() {
+        if status.starts_with('R')
+            && let (Some(old), Some(new)) = (fields.next(), fields.next())
+        {
+            renames.push((old, new));
+        }
+    }
+    renames
+}
+
+fn author_of(repo: &Repo, commit: &str) -> Result<(String, String)> {
+    let output = repo.run_with_output(&["show", "-s", "--format=%an <%ae>%x00%aI", commit])?;
+    let (author, date) = output.trim_end().split_once('\0').unwrap_or((&output, ""));
+    Ok((author.to_owned(), date.to_owned()))
+}
+
+/// Print a coloured, human-readable list of leaks to stdout.
+pub fn print_leaks(leaks: &[Leak]) {
+    if leaks.is_empty() {
+        println!("{}", "No plaintext secrets found in history.".green());
+        return;
+    }
+    println!(
+        "\n{} {}:",
+        leaks.len().to_string().red(),
+        "plaintext blobs found at crypt list paths".red().bold()
+    );
+    for leak in leaks {
+        println!(
+            "  {} {} {}",
+            leak.commit[..leak.commit.len().min(10)].yellow(),
+            leak.path,
+            format!("({}, {})", leak.author, leak.date).dimmed()
+        );
+    }
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_renames() {
+        let output = b"R100\0s/x.env\0s/y.env\0\nR087\0old.env\0s/x.env\0";
+        assert_eq!(
+            parse_renames(output),
+            vec![
+                ("s/x.env".to_owned(), "s/y.env".to_owned()),
+                ("old.env".to_owned(), "s/x.env".to_owned()),
+            ]
+        );
+    }
+}
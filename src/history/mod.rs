@@ -0,0 +1,109 @@
+//! Operations on the committed history rather than the working tree.
+//!
+//! | Module | Contents |
+//! |---|---|
+//! | [`verify`] | `git-se verify-history`: decrypt every crypt-list blob in history |
+//! | [`leaks`] | `git-se leaks`: find crypt-list blobs committed in plaintext |
+//! | [`purge`] | `git-se purge-history`: rewrite history with those blobs encrypted |
+//!
+//! Commits and trees are read with git plumbing (`rev-list`, `ls-tree`) and
+//! blobs through a single [`CatFile`](crate::git::CatFile) process.
+
+mod leaks;
+mod purge;
+mod verify;
+
+pub use leaks::{Leak, leaks, print_leaks};
+pub use purge::{
+    BACKUP_REF_PREFIX, CommitMapping, PurgeReport, RefUpdate, print_purge_report, purge_history,
+};
+pub use verify::{BlobState, HistoryBlob, VerifyReport, verify_history};
+pub(crate) use verify::{classify, classify_header};
+
+use crate::{
+    error::{Error, Result},
+    repo::Repo,
+};
+
+/// A blob in a commit's tree, as listed by `git ls-tree -r`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeEntry {
+    /// File mode, e.g. `100644`.
+    pub mode: String,
+    /// Blob object id.
+    pub oid: String,
+    /// Repo-relative, `/`-separated path.
+    pub path: String,
+}
+
+/// Commits reachable from `revs` (any `git rev-list` arguments, e.g.
+/// `v1.0..main`), newest first unless `options` say otherwise. Empty `revs`
+/// means every ref except the backups made by [`purge_history`].
+pub fn rev_list(repo: &Repo, options: &[&str], revs: &[String]) -> Result<Vec<String>> {
+    let exclude_backups = format!("--exclude={BACKUP_REF_PREFIX}*");
+    let mut args = vec!["rev-list"];
+    args.extend_from_slice(options);
+    if revs.is_empty() {
+        args.extend([exclude_backups.as_str(), "--all"]);
+    } else {
+        args.extend(revs.iter().map(String::as_str));
+    }
+    args.push("--");
+    Ok(repo
+        .run_with_output(&args)?
+        .lines()
+        .map(ToOwned::to_owned)
+        .collect())
+}
+
+/// All blobs in the tree of `commit`. Submodule entries are skipped.
+pub fn ls_tree(repo: &Repo, commit: &str) -> Result<Vec<TreeEntry>> {
+    let output = repo.run_with_bytes(&["ls-tree", "-r", "-z", "--full-tree", commit])?;
+    output
+        .split(|&b| b == 0)
+        .filter(|record| !record.is_empty())
+        .filter_map(|record| parse_tree_record(record).transpose())
+        .collect()
+}
+
+/// Parse one `<mode> SP <type> SP <oid> TAB <path>` record. Returns `None` for
+/// entries that are not blobs.
+fn parse_tree_record(record: &[u8]) -> Result<Option<TreeEntry>> {
+    let record = String::from_utf8_lossy(record);
+    let malformed = || Error::Git(format!("unexpected ls-tree output: {record}"));
+    let (meta, path) = record.split_once('\t').ok_or_else(malformed)?;
+    let mut meta = meta.split(' ');
+    let (Some(mode), Some(kind), Some(oid)) = (meta.next(), meta.next(), meta.next()) else {
+        return Err(malformed());
+    };
+    if kind != "blob" {
+        return Ok(None);
+    }
+    Ok(Some(TreeEntry {
+        mode: mode.to_owned(),
+        oid: oid.to_owned(),
+        path: path.to_owned(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tree_record() {
+        let entry = parse_tree_record(b"100644 blob 0123abcd\tdir/a b.txt")
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.mode, "100644");
+        assert_eq!(entry.oid, "0123abcd");
+        assert_eq!(entry.path, "dir/a b.txt");
+
+        assert!(
+            parse_tree_record(b"160000 commit 0123abcd\tsub")
+                .unwrap()
+                .is_none()
+        );
+        assert!(parse_tree_record(b"garbage").is_err());
+    }
+}
@@ -0,0 +1,408 @@
+//! `git-se purge-history`: rewrite history so that plaintext blobs at
+//! crypt-list paths are replaced by ciphertext.
+//!
+//! Commits are rewritten oldest first. A commit is only recreated if one of
+//! its blobs or parents changed; its raw object is otherwise copied verbatim
+//! (author, committer, dates, message, extra headers), except that signatures
+//! are dropped because they no longer match.
+//!
+//! Encryption is deterministic per blob: the salt is derived from the key and
+//! the `file_id` from the key and the plaintext blob id, so identical blobs
+//! map to identical ciphertext and a rerun produces the same commits. Both are
+//! keyed, so the ciphertext does not reveal the plaintext's blob id.
+//!
+//! The old tips are kept under `refs/git-se-original/`. Nothing is pushed.
+
+use std::{collections::HashMap, fmt::Write as _};
+
+use log::{debug, info, warn};
+use serde::Serialize;
+use tempfile::TempDir;
+
+use super::{ls_tree, rev_list};
+use crate::{
+    config::{CryptMatcher, RuleSet},
+    crypt::{FILE_ID_LEN, MAGIC, SALT_LEN, derive_key, encrypt_into},
+    error::{Error, Result},
+    git::{CatFile, hash_object, run_with_input},
+    repo::Repo,
+    utils::{progress::Progress, style::Colorize},
+};
+
+/// Namespace the rewritten refs' old values are backed up to.
+pub const BACKUP_REF_PREFIX: &str = "refs/git-se-original/";
+
+/// An old commit and the commit that replaces it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CommitMapping {
+    pub old: String,
+    pub new: String,
+}
+
+/// A ref moved by the rewrite.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RefUpdate {
+    pub name: String,
+    pub old: String,
+    pub new: String,
+}
+
+/// Result of [`purge_history`].
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PurgeReport {
+    /// Number of plaintext blobs that were encrypted.
+    pub blobs: usize,
+    /// Rewritten commits, oldest first. Unchanged commits are omitted.
+    pub commits: Vec<CommitMapping>,
+    /// Refs that now point to rewritten history.
+    pub refs: Vec<RefUpdate>,
+}
+
+/// Rewrite `refs` (all branches and tags if empty) so that every plaintext
+/// blob at a crypt-list path is encrypted.
+///
+/// Refuses to run when tracked files have uncommitted changes. If the checked
+/// out branch is rewritten, the index and working tree are reset to the new
+/// commit, so the working tree then holds ciphertext (run `git-se d`).
+pub fn purge_history(repo: &Repo, refs: &[String]) -> Result<PurgeReport> {
+    if !repo
+        .run_with_output(&["status", "--porcelain", "--untracked-files=no"])?
+        .trim()
+        .is_empty()
+    {
+        return Err(Error::DirtyWorktree);
+    }
+    let key = repo.get_key()?;
+    let refs = resolve_refs(repo, refs)?;
+
+    let mut tips = Vec::new();
+    for name in &refs {
+        let object = repo
+            .run_with_output(&["rev-parse", name])?
+            .trim()
+            .to_owned();
+        match repo.run_with_output(&["rev-parse", "--verify", "-q", &format!("{name}^{{commit}}")])
+        {
+            Ok(commit) => tips.push((name.clone(), object, commit.trim().to_owned())),
+            Err(_) => warn!("{name} does not point to a commit, skipping"),
+        }
+    }
+    if tips.is_empty() {
+        return Err(Error::NoFile("purge"));
+    }
+
+    let tip_commits: Vec<String> = tips.iter().map(|(_, _, commit)| commit.clone()).collect();
+    let commits = rev_list(
+        repo,
+        &["--topo-order", "--reverse", "--parents"],
+        &tip_commits,
+    )?;
+
+    let mut rewriter = Rewriter::new(repo, key.as_bytes())?;
+    let mut report = PurgeReport::default();
+    let pb = Progress::new(commits.len(), "Rewrite");
+    for line in &commits {
+        let mut ids = line.split(' ').map(ToOwned::to_owned);
+        let commit = ids.next().unwrap_or_default();
+        let parents: Vec<String> = ids.collect();
+        let new = rewriter.rewrite_commit(&commit, &parents)?;
+        if new != commit {
+            report.commits.push(CommitMapping { old: commit, new });
+        }
+        pb.inc(1);
+    }
+    pb.finish_and_clear();
+    report.blobs = rewriter.blobs.values().filter(|b| b.is_some()).count();
+
+    let head = repo.run_with_output(&["symbolic-ref", "-q", "HEAD"]).ok();
+    let mut head_moved = false;
+    for (name, object, commit) in &tips {
+        let new_commit = rewriter.commits[commit].clone();
+        if &new_commit == commit {
+            continue;
+        }
+        let new = if object == commit {
+            new_commit
+        } else {
+            rewriter.rewrite_tag(object, commit, &new_commit)?
+        };
+        let backup = format!(
+            "{BACKUP_REF_PREFIX}{}",
+            name.strip_prefix("refs/").unwrap_or(name)
+        );
+        repo.run(&["update-ref", &backup, object])?;
+        repo.run(&[
+            "update-ref",
+            "-m",
+            "git-se purge-history",
+            name,
+            &new,
+            object,
+        ])?;
+        info!("Rewrote {name}: {object} -> {new}");
+        head_moved |= head.as_deref().map(str::trim) == Some(name.as_str());
+        report.refs.push(RefUpdate {
+            name: name.clone(),
+            old: object.clone(),
+            new,
+        });
+    }
+    if head_moved {
+        repo.run(&["reset", "-q", "--hard"])?;
+    }
+    Ok(report)
+}
+
+/// Full ref names for `refs`, or every branch and tag if empty.
+fn resolve_refs(repo: &Repo, refs: &[String]) -> Result<Vec<String>> {
+    if refs.is_empty() {
+        return Ok(repo
+            .run_with_output(&[
+                "for-each-ref",
+                "--format=%(refname)",
+                "refs/heads",
+                "refs/tags",
+            ])?
+            .lines()
+            .map(ToOwned::to_owned)
+            .collect());
+    }
+    refs.iter()
+        .map(|r| {
+            let full = repo
+                .run_with_output(&["rev-parse", "--symbolic-full-name", r])?
+                .trim()
+                .to_owned();
+            if full.starts_with("refs/") {
+                Ok(full)
+            } else {
+                Err(Error::Other(format!("not a branch or tag: {r}")))
+            }
+        })
+        .collect()
+}
+
+struct Rewriter<'a> {
+    repo: &'a Repo,
+    matcher: CryptMatcher,
+    cat_file: CatFile,
+    /// Temporary index used to build rewritten trees.
+    index_dir: TempDir,
+    derived_key: zeroize::Zeroizing<[u8; 32]>,
+    salt: [u8; SALT_LEN],
+    file_id_key: [u8; 32],
+    rule_set: RuleSet,
+    /// Old commit id -> new commit id.
+    commits: HashMap<String, String>,
+    /// (Old blob id, zstd level) -> encrypted blob id, or `None` if already
+    /// encrypted.
+    blobs: HashMap<(String, Option<u8>), Option<String>>,
+}
+
+impl<'a> Rewriter<'a> {
+    fn new(repo: &'a Repo, key: &[u8]) -> Result<Self> {
+        let purge_key = blake3::derive_key("git-simple-encrypt purge-history", key);
+        let mut salt = [0u8; SALT_LEN];
+        salt.copy_from_slice(&blake3::keyed_hash(&purge_key, b"salt").as_bytes()[..SALT_LEN]);
+        Ok(Self {
+            repo,
+            matcher: repo.conf.matcher()?,
+            cat_file: CatFile::spawn(repo)?,
+            index_dir: TempDir::new()?,
+            derived_key: derive_key(key, &salt)?,
+            salt,
+            file_id_key: purge_key,
+            rule_set: repo.conf.rule_set()?,
+            commits: HashMap::new(),
+            blobs: HashMap::new(),
+        })
+    }
+
+    fn read(&mut self, oid: &str) -> Result<Vec<u8>> {
+        self.cat_file
+            .read(oid)?
+            .ok_or_else(|| Error::Git(format!("missing object {oid}")))
+    }
+
+    /// The encrypted replacement of blob `oid`, compressed with `zstd`, or
+    /// `None` if it is already encrypted.
+    fn encrypted_blob(&mut self, oid: &str, zstd: Option<u8>) -> Result<Option<String>> {
+        let cache_key = (oid.to_owned(), zstd);
+        if let Some(new) = self.blobs.get(&cache_key) {
+            return Ok(new.clone());
+        }
+        let blob = self.read(oid)?;
+        let new = if blob.starts_with(MAGIC) {
+            None
+        } else {
+            let mut file_id = [0u8; FILE_ID_LEN];
+            file_id.copy_from_slice(
+                &blake3::keyed_hash(&self.file_id_key, oid.as_bytes()).as_bytes()[..FILE_ID_LEN],
+            );
+            let mut ciphertext = Vec::with_capacity(blob.len());
+            encrypt_into(
+                &mut blob.as_slice(),
+                &mut ciphertext,
+                &self.derived_key,
+                self.salt,
+                Some(file_id),
+                zstd,
+            )?;
+            Some(hash_object(self.repo, "blob", &ciphertext)?)
+        };
+        debug!("blob {oid} -> {new:?}");
+        self.blobs.insert(cache_key, new.clone());
+        Ok(new)
+    }
+
+    fn rewrite_commit(&mut self, commit: &str, parents: &[String]) -> Result<String> {
+        let new_parents: Vec<String> = parents
+            .iter()
+            .map(|p| self.commits.get(p).cloned().unwrap_or_else(|| p.clone()))
+            .collect();
+
+        let mut index_info = String::new();
+        for entry in ls_tree(self.repo, commit)? {
+            if !self.matcher.matches(&entry.path) {
+                continue;
+            }
+            let zstd = self.rule_set.settings_for(&entry.path).zstd();
+            if let Some(new) = self.encrypted_blob(&entry.oid, zstd)? {
+                let _ = writeln!(index_info, "{} {new}\t{}", entry.mode, entry.path);
+            }
+        }
+
+        let new = if index_info.is_empty() && new_parents == parents {
+            commit.to_owned()
+        } else {
+            let tree = if index_info.is_empty() {
+                self.repo
+                    .run_with_output(&["rev-parse", &format!("{commit}^{{tree}}")])?
+                    .trim()
+                    .to_owned()
+            } else {
+                self.write_tree(commit, &index_info)?
+            };
+            let raw = self.read(commit)?;
+            hash_object(
+                self.repo,
+                "commit",
+                &rewrite_commit_object(&raw, &tree, &new_parents),
+            )?
+        };
+        self.commits.insert(commit.to_owned(), new.clone());
+        Ok(new)
+    }
+
+    /// Build the tree of `commit` with the `--index-info` lines applied.
+    fn write_tree(&self, commit: &str, index_info: &str) -> Result<String> {
+        let index = self.index_dir.path().join("index");
+        let git = |args: &[&str], input: &[u8]| {
+            let mut command = self.repo.git_command();
+            command.env("GIT_INDEX_FILE", &index).args(args);
+            run_with_input(command, input)
+        };
+        git(&["read-tree", commit], b"")?;
+        git(&["update-index", "--index-info"], index_info.as_bytes())?;
+        let tree = git(&["write-tree"], b"")?;
+        Ok(String::from_utf8_lossy(&tree).trim().to_owned())
+    }
+
+    /// Recreate the annotated tag `tag` (pointing to `old`) so it points to
+    /// `new`.
+    fn rewrite_tag(&mut self, tag: &str, old: &str, new: &str) -> Result<String> {
+        let raw = self.read(tag)?;
+        let object_line = format!("object {old}\n");
+        if !raw.starts_with(object_line.as_bytes()) {
+            return Err(Error::Other(format!(
+                "tag {tag} does not point directly to a commit; rewrite it manually"
+            )));
+        }
+        let mut rewritten = format!("object {new}\n").into_bytes();
+        let rest = &raw[object_line.len()..];
+        // A tag signature is appended to the message and no longer matches.
+        let signature = rest
+            .windows(SIGNATURE_BEGIN.len())
+            .position(|w| w == SIGNATURE_BEGIN);
+        rewritten.extend_from_slice(&rest[..signature.unwrap_or(rest.len())]);
+        hash_object(self.repo, "tag", &rewritten)
+    }
+}
+
+const SIGNATURE_BEGIN: &[u8] = b"-----BEGIN ";
+
+/// Replace the `tree` and `parent` headers of a raw commit object and drop
+/// its signature headers, keeping everything else byte for byte.
+fn rewrite_commit_object(raw: &[u8], tree: &str, parents: &[String]) -> Vec<u8> {
+    let header_end = raw
+        .windows(2)
+        .position(|w| w == b"\n\n")
+        .map_or(raw.len(), |i| i + 1);
+    let (headers, message) = raw.split_at(header_end);
+
+    let mut out = format!("tree {tree}\n").into_bytes();
+    for parent in parents {
+        out.extend_from_slice(format!("parent {parent}\n").as_bytes());
+    }
+    let mut in_signature = false;
+    for line in headers.split_inclusive(|&b| b == b'\n') {
+        if line.starts_with(b" ") {
+            // Continuation of the previous (multi-line) header.
+            if !in_signature {
+                out.extend_from_slice(line);
+            }
+            continue;
+        }
+        in_signature = line.starts_with(b"gpgsig");
+        if in_signature || line.starts_with(b"tree ") || line.starts_with(b"parent ") {
+            continue;
+        }
+        out.extend_from_slice(line);
+    }
+    out.extend_from_slice(message);
+    out
+}
+
+/// Print the old -> new mapping and a reminder to push manually.
+pub fn print_purge_report(report: &PurgeReport) {
+    if report.refs.is_empty() {
+        println!("{}", "No plaintext blobs found, nothing rewritten.".green());
+        return;
+    }
+    println!(
+        "\n{} {} plaintext blobs in {} commits:",
+        "Encrypted".green().bold(),
+        report.blobs,
+        report.commits.len()
+    );
+    for c in &report.commits {
+        println!("  {} -> {}", c.old, c.new);
+    }
+    println!("\n{}", "Rewritten refs:".bold());
+    for r in &report.refs {
+        println!("  {} {} -> {}", r.name, r.old.as_str().dimmed(), r.new);
+    }
+    println!(
+        "\nOld refs are kept under {}. Review the result, then update the remote yourself, e.g. {}",
+        BACKUP_REF_PREFIX.cyan(),
+        "git push --force-with-lease".cyan()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_commit_object() {
+        let raw = b"tree aaa\nparent p1\nparent p2\nauthor A <a> 1 +0000\n\
+committer C <c> 1 +0000\ngpgsig -----BEGIN PGP SIGNATURE-----\n \n abc\n \
+-----END PGP SIGNATURE-----\nencoding x\n\nmsg\n\nbody\n";
+        let out = rewrite_commit_object(raw, "bbb", &["q1".to_owned()]);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "tree bbb\nparent q1\nauthor A <a> 1 +0000\ncommitter C <c> 1 +0000\n\
+encoding x\n\nmsg\n\nbody\n"
+        );
+    }
+}
@@ -0,0 +1,210 @@
+//! `git-se verify-history`: check that every encrypted blob in history still
+//! decrypts with the current key.
+//!
+//! Each unique blob is read and fully decrypted exactly once (into a sink),
+//! no matter how many commits contain it, sharing one [`KeyCache`] so Argon2
+//! runs once per salt.
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    io,
+};
+
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use rayon::prelude::*;
+use serde::Serialize;
+
+use super::{ls_tree, rev_list};
+use crate::{
+    crypt::{FileHeader, HEADER_LEN, KeyCache, MAGIC, decrypt_into_cached, probe_key},
+    error::{Error, Result},
+    git::CatFile,
+    repo::Repo,
+    utils::{progress::Progress, style::Colorize},
+};
+
+/// Verification result of a single blob.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlobState {
+    /// Decrypts completely with the current key.
+    Ok,
+    /// The first chunk does not open with the current key (an old password,
+    /// or a tampered first chunk; AEAD cannot tell them apart).
+    WrongKey,
+    /// The ciphertext ends early.
+    Truncated,
+    /// Invalid header, or a later chunk fails authentication.
+    Tampered,
+    /// The path is in the crypt list, but the blob is not encrypted.
+    Plaintext,
+}
+
+impl BlobState {
+    const fn label(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::WrongKey => "wrong key",
+            Self::Truncated => "truncated",
+            Self::Tampered => "tampered",
+            Self::Plaintext => "plaintext",
+        }
+    }
+}
+
+/// One crypt-list path in one commit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HistoryBlob {
+    pub commit: String,
+    pub path: String,
+    pub oid: String,
+    pub state: BlobState,
+}
+
+/// Result of [`verify_history`].
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VerifyReport {
+    /// Number of commits walked.
+    pub commits: usize,
+    /// Number of unique blobs verified.
+    pub blobs: usize,
+    /// Every crypt-list path in every commit, in `rev-list` order.
+    pub entries: Vec<HistoryBlob>,
+}
+
+/// Verify every blob at a crypt-list path in the commits reachable from
+/// `revs` (all refs if empty).
+pub fn verify_history(repo: &Repo, revs: &[String]) -> Result<VerifyReport> {
+    let key = repo.get_key()?;
+    let commits = rev_list(repo, &[], revs)?;
+
+    let matcher = repo.conf.matcher()?;
+    let mut listed = Vec::new();
+    for commit in &commits {
+        for entry in ls_tree(repo, commit)? {
+            if matcher.matches(&entry.path) {
+                listed.push((commit.clone(), entry));
+            }
+        }
+    }
+
+    let unique: BTreeSet<&str> = listed.iter().map(|(_, e)| e.oid.as_str()).collect();
+    let key_cache: KeyCache = DashMap::new();
+    let cat_file = Mutex::new(CatFile::spawn(repo)?);
+    let pb = Progress::new(unique.len(), "Verify");
+
+    let states = unique
+        .into_par_iter()
+        .map(|oid| {
+            let blob = cat_file
+                .lock()
+                .read(oid)?
+                .ok_or_else(|| Error::Git(format!("missing object {oid}")))?;
+            let state = classify(&blob, &key_cache, key.as_bytes())?;
+            pb.inc(1);
+            Ok((oid, state))
+        })
+        .collect::<Result<HashMap<_, _>>>()?;
+    pb.finish_and_clear();
+
+    let blobs = states.len();
+    let entries = listed
+        .iter()
+        .map(|(commit, entry)| HistoryBlob {
+            commit: commit.clone(),
+            path: entry.path.clone(),
+            oid: entry.oid.clone(),
+            state: states[entry.oid.as_str()],
+        })
+        .collect();
+    Ok(VerifyReport {
+        commits: commits.len(),
+        blobs,
+        entries,
+    })
+}
+
+/// The state of `blob` with `key`, see [`BlobState`]. I/O and key derivation
+/// errors are returned.
+pub fn classify(blob: &[u8], key_cache: &KeyCache, key: &[u8]) -> Result<BlobState> {
+    if let Some(state) = classify_header(blob) {
+        return Ok(state);
+    }
+    let state = match probe_key(&mut &*blob, key_cache, key) {
+        Err(Error::DecryptFailed(_)) => BlobState::WrongKey,
+        Err(e) => state_from_error(e)?,
+        Ok(_) => match decrypt_into_cached(&mut &*blob, &mut io::sink(), key_cache, key) {
+            Ok(_) => BlobState::Ok,
+            Err(e) => state_from_error(e)?,
+        },
+    };
+    Ok(state)
+}
+
+/// The state of `blob` as far as its header tells, without a key: `None` if
+/// the header is valid.
+#[must_use]
+pub fn classify_header(blob: &[u8]) -> Option<BlobState> {
+    if !blob.starts_with(MAGIC) {
+        return Some(BlobState::Plaintext);
+    }
+    let Ok(header_bytes) = <&[u8; HEADER_LEN]>::try_from(&blob[..HEADER_LEN.min(blob.len())])
+    else {
+        return Some(BlobState::Truncated);
+    };
+    FileHeader::from_bytes(header_bytes)
+        .is_err()
+        .then_some(BlobState::Tampered)
+}
+
+fn state_from_error(e: Error) -> Result<BlobState> {
+    match e {
+        Error::FileTruncated | Error::TruncatedChunk => Ok(BlobState::Truncated),
+        Error::Io(e) => Err(Error::Io(e)),
+        Error::Argon2(msg) => Err(Error::Argon2(msg)),
+        _ => Ok(BlobState::Tampered),
+    }
+}
+
+impl VerifyReport {
+    /// Entries whose blob is not [`BlobState::Ok`].
+    pub fn failures(&self) -> impl Iterator<Item = &HistoryBlob> {
+        self.entries.iter().filter(|e| e.state != BlobState::Ok)
+    }
+
+    /// Print a coloured, human-readable report to stdout. Only failures are
+    /// listed individually.
+    pub fn print_human(&self) {
+        println!(
+            "\n{} {} {}",
+            "Verified history".bold(),
+            format!(
+                "({} commits, {} unique blobs, {} entries)",
+                self.commits,
+                self.blobs,
+                self.entries.len()
+            )
+            .cyan(),
+            ":".dimmed()
+        );
+        let mut failed = 0;
+        for e in self.failures() {
+            failed += 1;
+            let label = format!("{:<10}", e.state.label());
+            let label = match e.state {
+                BlobState::Plaintext => label.yellow(),
+                _ => label.red(),
+            };
+            println!(
+                "  {label} {} {}",
+                &e.commit[..e.commit.len().min(10)],
+                e.path
+            );
+        }
+        if failed == 0 {
+            println!("  {}", "all blobs decrypt with the current key".green());
+        }
+        println!();
+    }
+}
@@ -0,0 +1,274 @@
+//! Client-side git hooks (`git-se install --hook ...`, `git-se uninstall`).
+//!
+//! Every hook gets a managed block right after the shebang, so it runs before
+//! any existing script and chains with it instead of replacing it. The block
+//! only exits on failure; otherwise the rest of the script runs as before.
+//! Hooks are written to `git rev-parse --git-path hooks`, which respects
+//! `core.hooksPath`.
+
+use std::path::{Path, PathBuf};
+
+use log::{debug, info};
+
+use crate::{error::Result, repo::Repo, utils::atomic_write};
+
+const BLOCK_BEGIN: &str = "# >>> git-se managed, do not edit >>>";
+const BLOCK_END: &str = "# <<< git-se managed <<<";
+const SHEBANG: &str = "#!/bin/sh";
+
+/// A hook git-se can manage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "bin", derive(clap::ValueEnum))]
+pub enum Hook {
+    /// Refuse to commit staged files of the crypt list in plaintext.
+    PreCommit,
+    /// Encrypt staged files of the crypt list into the index before
+    /// committing, keeping plaintext in the working tree. Replaces
+    /// `pre-commit`.
+    PreCommitStage,
+    /// Refuse to push commits that contain files of the crypt list in
+    /// plaintext.
+    PrePush,
+    /// Decrypt the crypt list after switching branches.
+    PostCheckout,
+    /// Decrypt the crypt list after a merge (including `git pull`).
+    PostMerge,
+}
+
+impl Hook {
+    /// Every hook, in installation order. [`Self::PreCommitStage`] is left
+    /// out since it shares its file with [`Self::PreCommit`].
+    pub const ALL: [Self; 4] = [
+        Self::PreCommit,
+        Self::PrePush,
+        Self::PostCheckout,
+        Self::PostMerge,
+    ];
+
+    /// File name of the hook in the hooks directory.
+    #[must_use]
+    pub const fn file_name(self) -> &'static str {
+        match self {
+            Self::PreCommit | Self::PreCommitStage => "pre-commit",
+            Self::PrePush => "pre-push",
+            Self::PostCheckout => "post-checkout",
+            Self::PostMerge => "post-merge",
+        }
+    }
+
+    /// The shell lines inside the managed block.
+    const fn body(self) -> &'static str {
+        match self {
+            Self::PreCommit => {
+                r#"if ! git-se check --staged; then
+    echo "Please run 'git-se e' to encrypt them before committing."
+    exit 1
+fi"#
+            }
+            Self::PreCommitStage => {
+                r"git-se stage --staged || exit 1
+git-se check --staged || exit 1"
+            }
+            // The ref list on stdin is handed back to the rest of the script.
+            Self::PrePush => {
+                r#"git_se_input=$(cat)
+printf '%s\n' "$git_se_input" | git-se pre-push "$1" || exit 1
+exec <<GIT_SE_EOF
+$git_se_input
+GIT_SE_EOF"#
+            }
+            // `$3` is 1 for branch checkouts and 0 for file checkouts.
+            Self::PostCheckout => {
+                r#"if [ "$3" = "1" ]; then
+    git-se d || echo "git-se: decryption failed, run 'git-se d' manually"
+fi"#
+            }
+            Self::PostMerge => {
+                r#"git-se d || echo "git-se: decryption failed, run 'git-se d' manually""#
+            }
+        }
+    }
+}
+
+/// The hooks directory, honouring `core.hooksPath`.
+pub fn hooks_dir(repo: &Repo) -> Result<PathBuf> {
+    let dir = repo.run_with_output(&["rev-parse", "--git-path", "hooks"])?;
+    Ok(repo.path().join(dir.trim()))
+}
+
+/// Install (or refresh) the managed block of each of `hooks`. Existing hook
+/// scripts are kept and run after the block. Returns the path of each hook.
+pub fn install(repo: &Repo, hooks: &[Hook]) -> Result<Vec<PathBuf>> {
+    let dir = hooks_dir(repo)?;
+    std::fs::create_dir_all(&dir)?;
+    let mut installed = Vec::with_capacity(hooks.len());
+    for &hook in hooks {
+        let path = dir.join(hook.file_name());
+        let old = read_or_empty(&path)?;
+        let new = insert_block(&old, hook.body());
+        if new == old {
+            debug!("{} already up to date", path.display());
+        } else {
+            atomic_write(&path, new.as_bytes())?;
+            set_executable(&path)?;
+        }
+        installed.push(path);
+    }
+    Ok(installed)
+}
+
+/// Remove the managed block from every hook. Hook files that contain nothing
+/// else are deleted. Returns the hooks that had a block.
+pub fn uninstall(repo: &Repo) -> Result<Vec<Hook>> {
+    let dir = hooks_dir(repo)?;
+    let mut removed = Vec::new();
+    for hook in Hook::ALL {
+        let path = dir.join(hook.file_name());
+        let old = read_or_empty(&path)?;
+        if !old.contains(BLOCK_BEGIN) {
+            continue;
+        }
+        let new = remove_block(&old);
+        if new
+            .lines()
+            .all(|l| l.trim().is_empty() || l.trim() == SHEBANG)
+        {
+            std::fs::remove_file(&path)?;
+            info!("Removed {}", path.display());
+        } else {
+            atomic_write(&path, new.as_bytes())?;
+            info!("Removed the git-se block from {}", path.display());
+        }
+        removed.push(hook);
+    }
+    Ok(removed)
+}
+
+fn read_or_empty(path: &Path) -> Result<String> {
+    if path.exists() {
+        Ok(std::fs::read_to_string(path)?)
+    } else {
+        Ok(String::new())
+    }
+}
+
+fn set_executable(path: &Path) -> Result<()> {
+    // Windows users need Git for Windows (msys-based) which executes the sh
+    // hook regardless of the executable bit.
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = std::fs::metadata(path)?.permissions();
+        perms.set_mode(perms.mode() | 0o755);
+        std::fs::set_permissions(path, perms)?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// `content` without the managed block.
+fn remove_block(content: &str) -> String {
+    let mut out = String::with_capacity(content.len());
+    let mut in_block = false;
+    for line in content.lines() {
+        match line.trim_end() {
+            BLOCK_BEGIN => in_block = true,
+            BLOCK_END if in_block => in_block = false,
+            _ if !in_block => {
+                out.push_str(line);
+                out.push('\n');
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+/// Put a managed block with `body` right after the shebang of `content`,
+/// replacing any existing block. An empty `content` becomes a new script.
+fn insert_block(content: &str, body: &str) -> String {
+    let rest = remove_block(content);
+    let (shebang, rest) = match rest.split_once('\n') {
+        Some((first, rest)) if first.starts_with("#!") => (first, rest),
+        _ if rest.starts_with("#!") => (rest.trim_end(), ""),
+        _ => (SHEBANG, rest.as_str()),
+    };
+    format!("{shebang}\n{BLOCK_BEGIN}\n{body}\n{BLOCK_END}\n{rest}")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use path_absolutize::Absolutize;
+    use tempfile::TempDir;
+
+    use super::*;
+
+    fn init_temp_repo() -> (TempDir, Repo) {
+        let dir = TempDir::new().unwrap();
+        Command::new("git")
+            .args(["init"])
+            .current_dir(dir.path())
+            .output()
+            .unwrap();
+        let repo = Repo::open(dir.path().absolutize().unwrap()).unwrap();
+        (dir, repo)
+    }
+
+    #[test]
+    fn test_install_hook_creates_file() -> Result<()> {
+        let (_dir, repo) = init_temp_repo();
+
+        install(&repo, &[Hook::PreCommit])?;
+        let hook = repo.path().join(".git").join("hooks").join("pre-commit");
+        let content = std::fs::read_to_string(&hook)?;
+        assert!(
+            content.starts_with("#!/bin/sh"),
+            "hook should be a shell script"
+        );
+        assert!(content.contains("git-se check --staged"));
+
+        // Installing again is idempotent.
+        install(&repo, &[Hook::PreCommit])?;
+        assert_eq!(std::fs::read_to_string(&hook)?, content);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pre_commit_stage_replaces_pre_commit() -> Result<()> {
+        let (_dir, repo) = init_temp_repo();
+        install(&repo, &[Hook::PreCommit])?;
+        install(&repo, &[Hook::PreCommitStage])?;
+        let content = std::fs::read_to_string(hooks_dir(&repo)?.join("pre-commit"))?;
+        assert!(content.contains("git-se stage --staged"), "{content}");
+        assert_eq!(content.matches(BLOCK_BEGIN).count(), 1, "{content}");
+        Ok(())
+    }
+
+    #[test]
+    fn test_chain_and_uninstall() -> Result<()> {
+        let (_dir, repo) = init_temp_repo();
+        repo.run(&["config", "core.hooksPath", "my-hooks"])?;
+        let dir = hooks_dir(&repo)?;
+        assert_eq!(dir, repo.path().join("my-hooks"));
+
+        let user = "#!/bin/bash\necho user hook\n";
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(dir.join("post-merge"), user)?;
+
+        install(&repo, &Hook::ALL)?;
+        let chained = std::fs::read_to_string(dir.join("post-merge"))?;
+        assert!(
+            chained.starts_with("#!/bin/bash\n# >>> git-se"),
+            "{chained}"
+        );
+        assert!(chained.ends_with("echo user hook\n"), "{chained}");
+
+        uninstall(&repo)?;
+        assert_eq!(std::fs::read_to_string(dir.join("post-merge"))?, user);
+        assert!(!dir.join("pre-commit").exists());
+        Ok(())
+    }
+}
@@ -3,44 +3,364 @@
 #![allow(clippy::missing_panics_doc)]
 #![allow(clippy::multiple_crate_versions)]
 
+pub mod attributes;
 pub mod config;
 pub mod crypt;
+pub mod drivers;
 mod error;
+mod git;
+pub mod history;
+pub mod hooks;
+pub mod observer;
+pub mod output;
+pub mod pre_push;
+pub mod pre_receive;
 pub mod repo;
+pub mod report;
 pub mod salt_cache;
+pub mod show;
+pub mod stage;
+pub mod status;
 pub mod utils;
 
 #[cfg(feature = "bin")]
 mod cli;
 
 #[cfg(feature = "bin")]
-pub use crate::cli::{Cli, SetField, SubCommand};
+pub use crate::cli::{AttributesAction, Cli, SetField, SubCommand};
 #[cfg(feature = "bin")]
 use crate::crypt::{decrypt_repo, encrypt_repo};
 #[cfg(feature = "bin")]
-use crate::repo::Repo;
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "bin")]
+use log::error;
+
+#[allow(deprecated)]
+pub use crate::crypt::BatchSummary;
 pub use crate::{
-    crypt::{BatchSummary, FileHeader},
+    crypt::FileHeader,
     error::{Error, Result},
 };
+#[cfg(feature = "bin")]
+use crate::{
+    output::{FileEvent, Output},
+    repo::Repo,
+    report::{BatchReport, FileAction, FileOutcome},
+    utils::style::Colorize,
+};
 
-/// Dispatch a parsed CLI invocation.
+/// Dispatch a parsed CLI invocation, reporting the results in the format
+/// chosen with `--format`.
 ///
 /// Only available with the `bin` feature (default for the `git-se` binary).
 #[cfg(feature = "bin")]
 pub fn run(cli: Cli) -> Result<()> {
+    let output = Output::new(cli.output_format());
+    let command = cli.command.name();
+    let result = run_command(cli, &output);
+    output.finish(command, result.as_ref().err());
+    result
+}
+
+#[cfg(feature = "bin")]
+#[allow(clippy::too_many_lines)]
+fn run_command(cli: Cli, output: &Output) -> Result<()> {
     if !cli.repo.is_absolute() {
         return Err(Error::RepoPathNotAbsolute(cli.repo.clone()));
     }
     let mut repo = Repo::open(&cli.repo)?;
     match cli.command {
-        SubCommand::Encrypt { paths } => encrypt_repo(&repo, &paths)?,
-        SubCommand::Decrypt { paths } => decrypt_repo(&repo, &paths)?,
-        SubCommand::Add { paths } => repo.conf.add_paths_to_crypt_list(&paths)?,
+        SubCommand::Encrypt {
+            paths,
+            recurse_submodules: recurse,
+        } => {
+            for_each_repo(&repo, recurse, &paths, output, |repo, paths| {
+                report_batch(
+                    output,
+                    repo,
+                    &encrypt_repo(repo, paths, &output.observer(repo))?,
+                )
+            })?;
+        }
+        SubCommand::Decrypt {
+            paths,
+            recurse_submodules: recurse,
+        } => {
+            for_each_repo(&repo, recurse, &paths, output, |repo, paths| {
+                report_batch(
+                    output,
+                    repo,
+                    &decrypt_repo(repo, paths, &output.observer(repo))?,
+                )
+            })?;
+        }
+        SubCommand::Stage { paths, staged } => {
+            let report = stage::stage(
+                &repo,
+                &repo.resolve_cli_paths(&paths),
+                staged,
+                &output.observer(&repo),
+            )?;
+            if !report.files.is_empty() {
+                report_batch(output, &repo, &report)?;
+            }
+        }
+        SubCommand::Add {
+            paths,
+            dry_run: true,
+        } => {
+            for file in repo
+                .conf
+                .resolve_added_files(&repo.resolve_cli_paths(&paths))?
+            {
+                let path = repo.display_path(file);
+                if output.is_human() {
+                    println!("{}", path.display());
+                }
+                output.file(FileEvent::new(path, FileAction::Add, FileOutcome::DryRun));
+            }
+        }
+        SubCommand::Add {
+            paths,
+            dry_run: false,
+        } => {
+            let paths = repo.resolve_cli_paths(&paths);
+            repo.conf.add_paths_to_crypt_list(&paths)?;
+            attributes::sync(&repo)?;
+        }
+        SubCommand::Rm { paths, decrypt } => {
+            let paths = repo.resolve_cli_paths(&paths);
+            let observer = output.observer(&repo);
+            if let Some(report) = repo.remove_from_crypt_list(&paths, decrypt, &observer)? {
+                report_batch(output, &repo, &report)?;
+            }
+        }
         SubCommand::Set { field } => field.set(&mut repo)?,
-        SubCommand::Pwd => repo.set_key_interactive()?,
-        SubCommand::Check { paths, staged } => repo.check(&paths, staged)?,
-        SubCommand::Install => repo.install_hook()?,
+        SubCommand::Pwd { stdin: false } => repo.set_key_interactive()?,
+        SubCommand::Pwd { stdin: true } => repo.set_key_from_stdin()?,
+        SubCommand::Check {
+            paths,
+            staged,
+            recurse_submodules: recurse,
+        } => {
+            for_each_repo(&repo, recurse, &paths, output, |repo, paths| {
+                let report = repo.check(paths, staged, &output.observer(repo))?;
+                if report.files.is_empty() {
+                    output.println("No staged files need encryption check.");
+                    return Ok(());
+                }
+                report_batch(output, repo, &report)
+            })?;
+        }
+        SubCommand::Install {
+            hooks,
+            all_hooks,
+            diff,
+            merge,
+        } => {
+            let installed = "Installed".green().bold();
+            if diff {
+                drivers::install_diff_driver(&mut repo)?;
+                output.println(format!(
+                    "{installed} textconv diff driver, `git diff` now shows plaintext"
+                ));
+            }
+            if merge {
+                drivers::install_merge_driver(&mut repo)?;
+                output.println(format!(
+                    "{installed} three-way merge driver for encrypted files"
+                ));
+            }
+            let hooks = if all_hooks {
+                hooks::Hook::ALL.to_vec()
+            } else if hooks.is_empty() && !diff && !merge {
+                vec![hooks::Hook::PreCommit]
+            } else {
+                hooks
+            };
+            if !hooks.is_empty() {
+                for (hook, path) in hooks.iter().zip(hooks::install(&repo, &hooks)?) {
+                    output.println(format!(
+                        "{installed} {} hook at {}",
+                        hook.file_name(),
+                        path.display()
+                    ));
+                }
+            }
+        }
+        SubCommand::Uninstall => {
+            for hook in hooks::uninstall(&repo)? {
+                output.println(format!(
+                    "{} {} hook",
+                    "Uninstalled".green().bold(),
+                    hook.file_name()
+                ));
+            }
+        }
+        SubCommand::Attributes {
+            action: AttributesAction::Sync,
+        } => attributes::sync(&repo)?,
+        SubCommand::Status {
+            paths,
+            porcelain,
+            recurse_submodules: recurse,
+        } => {
+            let sections = repo_sections(&repo, recurse, &paths)?;
+            if !output.is_human() || porcelain {
+                let mut report = status::StatusReport::default();
+                for (name, repo, paths) in &sections {
+                    report.merge(name.as_deref(), status::status(repo, paths)?);
+                }
+                if output.is_human() {
+                    print!("{}", report.porcelain());
+                } else {
+                    output.report(&report)?;
+                }
+            } else {
+                for (name, repo, paths) in &sections {
+                    print_section_header(output, name.as_deref());
+                    status::status(repo, paths)?.print_human(repo);
+                }
+            }
+        }
+        SubCommand::VerifyHistory { revs } => {
+            let report = history::verify_history(&repo, &revs)?;
+            if output.is_human() {
+                report.print_human();
+            } else {
+                output.report(&report)?;
+            }
+            let failed = report.failures().count();
+            if failed > 0 {
+                return Err(Error::HistoryVerifyFailed(failed));
+            }
+        }
+        SubCommand::Leaks => {
+            let leaks = history::leaks(&repo)?;
+            if output.is_human() {
+                history::print_leaks(&leaks);
+            } else {
+                output.report(&leaks)?;
+            }
+            if !leaks.is_empty() {
+                return Err(Error::LeaksFound(leaks.len()));
+            }
+        }
+        SubCommand::PurgeHistory { refs } => {
+            let report = history::purge_history(&repo, &refs)?;
+            if output.is_human() {
+                history::print_purge_report(&report);
+            } else {
+                output.report(&report)?;
+            }
+        }
+        SubCommand::PreReceive => {
+            pre_receive::run_hook(&repo, &std::io::read_to_string(std::io::stdin())?)?;
+        }
+        SubCommand::PrePush { remote, url: _ } => {
+            pre_push::run_hook(&repo, &remote, &std::io::read_to_string(std::io::stdin())?)?;
+        }
+        SubCommand::Cat { path } => show::cat(
+            &repo,
+            &repo.resolve_cli_path(&path),
+            &mut std::io::stdout().lock(),
+        )?,
+        SubCommand::Show { object } => {
+            show::show(&repo, &object, &mut std::io::stdout().lock())?;
+        }
+        SubCommand::Textconv { file } => {
+            drivers::textconv(&repo, &file, &mut std::io::stdout().lock())?;
+        }
+        SubCommand::Merge {
+            base,
+            ours,
+            theirs,
+            path,
+        } => drivers::merge(&repo, &base, &ours, &theirs, &path)?,
     }
     Ok(())
 }
+
+/// A repo a command runs in: the submodule path (`None` for the repo itself),
+/// the repo, and the repo-relative paths to work on.
+#[cfg(feature = "bin")]
+type Section = (Option<String>, Repo, Vec<PathBuf>);
+
+/// The repos a command runs in: `repo` and, with `recurse`, its initialised
+/// submodules (named by their path), each with the ones of the command line
+/// `paths` that lie inside it. Repos without any of the given paths are left
+/// out.
+#[cfg(feature = "bin")]
+fn repo_sections(repo: &Repo, recurse: bool, paths: &[PathBuf]) -> Result<Vec<Section>> {
+    let mut paths = repo.resolve_cli_paths(paths);
+    if !recurse {
+        return Ok(vec![(None, repo.clone(), paths)]);
+    }
+    let given = !paths.is_empty();
+    let mut sections = Vec::new();
+    // Innermost submodules first, so each path goes to the repo it is in.
+    let mut submodules = repo.submodules()?;
+    submodules.reverse();
+    for (name, submodule) in submodules {
+        let (inside, rest): (Vec<_>, Vec<_>) = paths
+            .into_iter()
+            .partition(|p| p.is_relative() && p.starts_with(&name));
+        paths = rest;
+        let inside = inside
+            .iter()
+            .filter_map(|p| p.strip_prefix(&name).ok().map(Path::to_path_buf))
+            .collect::<Vec<_>>();
+        if !given || !inside.is_empty() {
+            sections.push((Some(name), submodule, inside));
+        }
+    }
+    if !given || !paths.is_empty() {
+        sections.push((None, repo.clone(), paths));
+    }
+    sections.reverse();
+    Ok(sections)
+}
+
+/// Run `f` in every repo of [`repo_sections`], under a header per
+/// submodule. A failure does not stop the other repos: a single error is
+/// returned as-is, several are logged and summarized.
+#[cfg(feature = "bin")]
+fn for_each_repo(
+    repo: &Repo,
+    recurse: bool,
+    paths: &[PathBuf],
+    output: &Output,
+    f: impl Fn(&Repo, &[PathBuf]) -> Result<()>,
+) -> Result<()> {
+    let mut failures = Vec::new();
+    for (name, repo, paths) in repo_sections(repo, recurse, paths)? {
+        print_section_header(output, name.as_deref());
+        if let Err(e) = f(&repo, &paths) {
+            failures.push((name.unwrap_or_else(|| ".".to_owned()), e));
+        }
+    }
+    if failures.len() <= 1 {
+        return failures.pop().map_or(Ok(()), |(_, e)| Err(e));
+    }
+    for (name, e) in &failures {
+        error!("{name}: {e}");
+    }
+    Err(Error::SubmodulesFailed(
+        failures.into_iter().map(|(name, _)| name).collect(),
+    ))
+}
+
+/// Print `report` and turn its failures into an error.
+#[cfg(feature = "bin")]
+fn report_batch(output: &Output, repo: &Repo, report: &BatchReport) -> Result<()> {
+    output.batch(repo, report);
+    report.result()
+}
+
+#[cfg(feature = "bin")]
+fn print_section_header(output: &Output, submodule: Option<&str>) {
+    if let Some(name) = submodule {
+        output.println(format!("\n{} {}", "Submodule".bold(), name.cyan()));
+    }
+}
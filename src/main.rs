@@ -1,18 +1,19 @@
-use clap::Parser;
 use git_simple_encrypt::{Cli, run};
 use log::LevelFilter;
 
 fn main() -> Result<(), git_simple_encrypt::Error> {
-    log_init();
-    run(Cli::parse())
+    let cli = Cli::try_parse_args_from(std::env::args_os()).unwrap_or_else(|e| e.exit());
+    log_init(cli.verbose);
+    run(cli)
 }
 
 #[inline]
-pub fn log_init() {
-    #[cfg(not(debug_assertions))]
-    log_init_with_default_level(LevelFilter::Info);
-    #[cfg(debug_assertions)]
-    log_init_with_default_level(LevelFilter::Debug);
+pub fn log_init(verbose: bool) {
+    if verbose || cfg!(debug_assertions) {
+        log_init_with_default_level(LevelFilter::Debug);
+    } else {
+        log_init_with_default_level(LevelFilter::Info);
+    }
 }
 
 #[inline]
@@ -0,0 +1,218 @@
+//! Events of the batch operations, for rendering progress.
+//!
+//! [`encrypt_repo`], [`decrypt_repo`], [`stage`], [`Repo::check`] and the
+//! batch APIs ([`encrypt_files_to`], [`decrypt_files_to`]) report what they
+//! are doing to an [`Observer`] while they run, and return a report when they
+//! are done. All callbacks have empty default bodies, so an implementation
+//! only overrides the events it cares about. Files are processed in
+//! parallel, so the per-file callbacks arrive from several threads and in no
+//! particular order.
+//!
+//! Shipped implementations: [`NoopObserver`], [`LogObserver`] and, with the
+//! `progress` feature, the indicatif-based [`ProgressObserver`].
+//!
+//! [`encrypt_repo`]: crate::crypt::encrypt_repo
+//! [`decrypt_repo`]: crate::crypt::decrypt_repo
+//! [`stage`]: crate::stage::stage
+//! [`Repo::check`]: crate::repo::Repo::check
+//! [`encrypt_files_to`]: crate::crypt::encrypt_files_to
+//! [`decrypt_files_to`]: crate::crypt::decrypt_files_to
+
+use std::path::{Path, PathBuf};
+
+use log::{debug, info, warn};
+
+use crate::report::{FileAction, FileOutcome, FileReport, Summary};
+
+/// Receives the events of a batch operation.
+///
+/// Paths are relative to the repo root for the repo-wide operations and as
+/// passed in for the batch APIs.
+#[allow(unused_variables)]
+pub trait Observer: Sync {
+    /// The batch is about to process `files`.
+    fn batch_started(&self, action: FileAction, files: &[PathBuf]) {}
+
+    /// Work on `path` has started.
+    fn file_started(&self, path: &Path) {}
+
+    /// Another `bytes` plaintext bytes of `path` were encrypted or
+    /// decrypted. Called once per chunk of up to
+    /// [`CHUNK_SIZE`](crate::crypt::CHUNK_SIZE) bytes, between
+    /// [`Self::file_started`] and the end of the file.
+    fn bytes_processed(&self, path: &Path, bytes: u64) {}
+
+    /// The file is done, with [`FileOutcome::Ok`] or [`FileOutcome::DryRun`].
+    fn file_finished(&self, report: &FileReport) {}
+
+    /// There was nothing to do for the file.
+    fn file_skipped(&self, report: &FileReport) {}
+
+    /// The file counts as failed, see [`FileOutcome::is_failure`];
+    /// [`FileReport::error`] holds the error, if there was one.
+    fn file_failed(&self, report: &FileReport) {}
+
+    /// The batch is done. Not called if the operation returns an error
+    /// rather than a report, e.g. when `git update-index` fails in `stage`.
+    fn batch_finished(&self, action: FileAction, summary: &Summary) {}
+}
+
+/// Pass `report` to the callback of its outcome and return it.
+pub(crate) fn notify(observer: &dyn Observer, report: FileReport) -> FileReport {
+    match report.outcome {
+        FileOutcome::Skipped => observer.file_skipped(&report),
+        o if o.is_failure() => observer.file_failed(&report),
+        _ => observer.file_finished(&report),
+    }
+    report
+}
+
+/// Ignores all events.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopObserver;
+
+impl Observer for NoopObserver {}
+
+/// Reports the events through `log`: the start and end of a batch as info,
+/// failed files as warnings and the rest as debug messages.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogObserver;
+
+impl Observer for LogObserver {
+    fn batch_started(&self, action: FileAction, files: &[PathBuf]) {
+        info!("{}: {} files", title(action), files.len());
+    }
+
+    fn file_finished(&self, report: &FileReport) {
+        debug!("{}: {:?}", report.path.display(), report.outcome);
+    }
+
+    fn file_skipped(&self, report: &FileReport) {
+        debug!("{}: skipped", report.path.display());
+    }
+
+    fn file_failed(&self, report: &FileReport) {
+        match &report.error {
+            Some(e) => warn!("{}: {e}", report.path.display()),
+            None => warn!("{}: {:?}", report.path.display(), report.outcome),
+        }
+    }
+
+    fn batch_finished(&self, action: FileAction, summary: &Summary) {
+        info!(
+            "{}: {} succeeded, {} skipped, {} failed",
+            title(action),
+            summary.succeeded,
+            summary.skipped,
+            summary.failed
+        );
+    }
+}
+
+/// Draws an indicatif progress bar on stderr, one per batch.
+#[cfg(feature = "progress")]
+#[derive(Default)]
+pub struct ProgressObserver {
+    bar: parking_lot::Mutex<Option<crate::utils::progress::Progress>>,
+}
+
+#[cfg(feature = "progress")]
+impl ProgressObserver {
+    fn inc(&self) {
+        if let Some(bar) = &*self.bar.lock() {
+            bar.inc(1);
+        }
+    }
+}
+
+#[cfg(feature = "progress")]
+impl Observer for ProgressObserver {
+    fn batch_started(&self, action: FileAction, files: &[PathBuf]) {
+        *self.bar.lock() = Some(crate::utils::progress::Progress::new(
+            files.len(),
+            title(action),
+        ));
+    }
+
+    fn file_finished(&self, _report: &FileReport) {
+        self.inc();
+    }
+
+    fn file_skipped(&self, _report: &FileReport) {
+        self.inc();
+    }
+
+    fn file_failed(&self, _report: &FileReport) {
+        self.inc();
+    }
+
+    fn batch_finished(&self, _action: FileAction, _summary: &Summary) {
+        let bar = self.bar.lock().take();
+        if let Some(bar) = bar {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+/// The label of `action` in progress output, e.g. `Encrypt`.
+const fn title(action: FileAction) -> &'static str {
+    match action {
+        FileAction::Add => "Add",
+        FileAction::Encrypt => "Encrypt",
+        FileAction::Decrypt => "Decrypt",
+        FileAction::Stage => "Stage",
+        FileAction::Check => "Check",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use parking_lot::Mutex;
+
+    use super::*;
+    use crate::error::Error;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl Observer for Recorder {
+        fn file_finished(&self, report: &FileReport) {
+            self.0
+                .lock()
+                .push(format!("done {}", report.path.display()));
+        }
+
+        fn file_skipped(&self, report: &FileReport) {
+            self.0
+                .lock()
+                .push(format!("skip {}", report.path.display()));
+        }
+
+        fn file_failed(&self, report: &FileReport) {
+            self.0
+                .lock()
+                .push(format!("fail {}", report.path.display()));
+        }
+    }
+
+    #[test]
+    fn test_notify() {
+        let start = Instant::now();
+        let recorder = Recorder::default();
+        for report in [
+            FileReport::new("a", FileOutcome::Ok, start).with_bytes(3, 70),
+            FileReport::new("b", FileOutcome::Skipped, start),
+            FileReport::new("c", FileOutcome::NotEncrypted, start),
+            FileReport::failed("d", Error::FileTruncated, start),
+            FileReport::new("e", FileOutcome::DryRun, start),
+        ] {
+            notify(&recorder, report);
+        }
+        assert_eq!(
+            recorder.0.into_inner(),
+            ["done a", "skip b", "fail c", "fail d", "done e"]
+        );
+    }
+}
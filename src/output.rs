@@ -0,0 +1,493 @@
+//! Command output in the format chosen with `--format`.
+//!
+//! The library returns reports (see [`crate::report`]) and the CLI prints
+//! them through [`Output`], and through [`Output::observer`] while a batch
+//! runs. Human output is coloured text: progress and the list of files to
+//! process go to stderr, results go to stdout. The machine
+//! formats print only structured results to stdout: `json` one document when
+//! the command finishes, `ndjson` one object per line as results come in.
+//! Both carry [`SCHEMA_VERSION`], which is bumped whenever a field is renamed
+//! or removed.
+//!
+//! A JSON document looks like this; `summary` and `report` are `null` for
+//! commands without a batch of files or a report, `error` when the command
+//! succeeded:
+//!
+//! ```json
+//! {
+//!   "schema_version": 1,
+//!   "command": "encrypt",
+//!   "ok": false,
+//!   "error": { "kind": "batch_failed", "message": "failed to encrypt 1 out of 3 files" },
+//!   "files": [
+//!     { "path": "a.env", "action": "encrypt", "outcome": "ok",
+//!       "bytes_in": 8, "bytes_out": 84, "elapsed_secs": 0.21 },
+//!     { "path": "b.env", "action": "encrypt", "outcome": "skipped",
+//!       "bytes_in": 0, "bytes_out": 0, "elapsed_secs": 0.0 },
+//!     { "path": "c.env", "action": "encrypt", "outcome": "failed",
+//!       "bytes_in": 0, "bytes_out": 0, "elapsed_secs": 0.0,
+//!       "error": { "kind": "io", "message": "Permission denied (os error 13)" } }
+//!   ],
+//!   "summary": { "total": 3, "succeeded": 1, "skipped": 1, "failed": 1 },
+//!   "report": null
+//! }
+//! ```
+//!
+//! In `ndjson`, every line has `schema_version` and a `type`: `file` and
+//! `summary` lines with the fields above, a `report` line with a `report`
+//! field, and a final `result` line with `command`, `ok` and `error`.
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use log::error;
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::{
+    error::{Error, Result},
+    observer::Observer,
+    repo::{self, Repo},
+    report::{BatchReport, FileAction, FileOutcome, FileReport, Summary},
+    utils::style::Colorize,
+};
+
+/// Version of the `json` / `ndjson` output. Adding fields keeps it, renaming
+/// or removing them bumps it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// How a command reports its results.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "bin", derive(clap::ValueEnum))]
+pub enum OutputFormat {
+    /// Coloured text for people.
+    #[default]
+    Human,
+    /// One JSON document when the command finishes.
+    Json,
+    /// One JSON object per line, as results come in.
+    Ndjson,
+}
+
+/// An error in machine-readable form.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ErrorInfo {
+    /// Stable name of the error, see [`Error::kind`].
+    pub kind: &'static str,
+    pub message: String,
+}
+
+impl From<&Error> for ErrorInfo {
+    fn from(e: &Error) -> Self {
+        Self {
+            kind: e.kind(),
+            message: e.to_string(),
+        }
+    }
+}
+
+/// The result of an action on one file, see [`FileReport`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileEvent {
+    /// The path, relative to the directory git-se was run from.
+    pub path: String,
+    pub action: FileAction,
+    pub outcome: FileOutcome,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub elapsed_secs: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<ErrorInfo>,
+}
+
+impl FileEvent {
+    #[must_use]
+    pub fn new(path: impl AsRef<Path>, action: FileAction, outcome: FileOutcome) -> Self {
+        Self {
+            path: path.as_ref().display().to_string(),
+            action,
+            outcome,
+            bytes_in: 0,
+            bytes_out: 0,
+            elapsed_secs: 0.0,
+            error: None,
+        }
+    }
+
+    /// The event of a file in a report of `repo`.
+    #[must_use]
+    pub fn from_report(repo: &Repo, action: FileAction, report: &FileReport) -> Self {
+        Self::with_path(repo.display_path(&report.path), action, report)
+    }
+
+    fn with_path(path: PathBuf, action: FileAction, report: &FileReport) -> Self {
+        Self {
+            bytes_in: report.bytes_in,
+            bytes_out: report.bytes_out,
+            elapsed_secs: report.elapsed.as_secs_f64(),
+            error: report.error.as_ref().map(ErrorInfo::from),
+            ..Self::new(path, action, report.outcome)
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Collected {
+    files: Vec<FileEvent>,
+    summary: Option<Summary>,
+    report: Option<serde_json::Value>,
+}
+
+/// Where a command sends its results, see the [module docs](self). The
+/// repos of `--recurse-submodules` all end up in one document.
+#[derive(Debug, Default)]
+pub struct Output {
+    format: OutputFormat,
+    collected: Mutex<Collected>,
+}
+
+impl Output {
+    #[must_use]
+    pub fn new(format: OutputFormat) -> Self {
+        Self {
+            format,
+            collected: Mutex::default(),
+        }
+    }
+
+    #[must_use]
+    pub const fn format(&self) -> OutputFormat {
+        self.format
+    }
+
+    #[must_use]
+    pub fn is_human(&self) -> bool {
+        self.format == OutputFormat::Human
+    }
+
+    /// Print a line of human output: to stdout, or to stderr in the machine
+    /// formats so that stdout stays parseable.
+    pub fn println(&self, line: impl Display) {
+        if self.is_human() {
+            println!("{line}");
+        } else {
+            eprintln!("{line}");
+        }
+    }
+
+    /// The [`Observer`] for a batch in `repo`. In human output it lists the
+    /// files to process first; in every format it draws a progress bar on
+    /// stderr (or logs, without the `progress` feature), and `ndjson` gets a
+    /// `file` line as soon as a file is done.
+    pub fn observer(&self, repo: &Repo) -> impl Observer + '_ {
+        CliObserver {
+            output: self,
+            root: repo.path().to_path_buf(),
+            cwd: repo.cwd.clone(),
+            action: Mutex::new(None),
+            progress: Progress::default(),
+        }
+    }
+
+    /// Report a batch of `repo`: every file and the [`Summary`]. Failed
+    /// files are logged as errors in every format. The files of `ndjson` are
+    /// left to [`Self::observer`].
+    pub fn batch(&self, repo: &Repo, report: &BatchReport) {
+        for f in report.failures() {
+            if let Some(e) = &f.error {
+                error!("{}: {e}", repo.display_path(&f.path).display());
+            }
+        }
+        match self.format {
+            OutputFormat::Human => print_human(repo, report),
+            OutputFormat::Json => {
+                for f in &report.files {
+                    self.file(FileEvent::from_report(repo, report.action, f));
+                }
+            }
+            OutputFormat::Ndjson => {}
+        }
+        self.summary(report.summary());
+    }
+
+    /// Record the result of an action on one file, for machine formats.
+    pub fn file(&self, event: FileEvent) {
+        match self.format {
+            OutputFormat::Human => {}
+            OutputFormat::Json => self.collected.lock().files.push(event),
+            OutputFormat::Ndjson => print_line("file", &event),
+        }
+    }
+
+    fn summary(&self, summary: Summary) {
+        match self.format {
+            OutputFormat::Human => {}
+            OutputFormat::Json => self
+                .collected
+                .lock()
+                .summary
+                .get_or_insert_default()
+                .add(summary),
+            OutputFormat::Ndjson => print_line("summary", &summary),
+        }
+    }
+
+    /// Record the report of a command such as `status`. Human output is left
+    /// to the caller.
+    pub fn report(&self, report: &impl Serialize) -> Result<()> {
+        let report = serde_json::to_value(report).map_err(|e| Error::Other(e.to_string()))?;
+        match self.format {
+            OutputFormat::Human => {}
+            OutputFormat::Json => self.collected.lock().report = Some(report),
+            OutputFormat::Ndjson => print_line("report", &ReportLine { report }),
+        }
+        Ok(())
+    }
+
+    /// Print the final result of `command`: the whole document for `json`,
+    /// the `result` line for `ndjson`.
+    pub fn finish(&self, command: &str, error: Option<&Error>) {
+        let result = ResultLine {
+            command,
+            ok: error.is_none(),
+            error: error.map(ErrorInfo::from),
+        };
+        match self.format {
+            OutputFormat::Human => {}
+            OutputFormat::Json => {
+                let collected = std::mem::take(&mut *self.collected.lock());
+                let document = Document {
+                    schema_version: SCHEMA_VERSION,
+                    result,
+                    files: &collected.files,
+                    summary: collected.summary,
+                    report: collected.report.as_ref(),
+                };
+                if let Ok(json) = serde_json::to_string_pretty(&document) {
+                    println!("{json}");
+                }
+            }
+            OutputFormat::Ndjson => print_line("result", &result),
+        }
+    }
+}
+
+#[cfg(feature = "progress")]
+type Progress = crate::observer::ProgressObserver;
+#[cfg(not(feature = "progress"))]
+type Progress = crate::observer::LogObserver;
+
+/// See [`Output::observer`].
+struct CliObserver<'a> {
+    output: &'a Output,
+    /// The root and cwd of the repo, for [`Repo::display_path`].
+    root: PathBuf,
+    cwd: PathBuf,
+    action: Mutex<Option<FileAction>>,
+    progress: Progress,
+}
+
+impl CliObserver<'_> {
+    fn display_path(&self, path: &Path) -> PathBuf {
+        repo::display_path(&self.root, &self.cwd, path)
+    }
+
+    fn stream(&self, report: &FileReport) {
+        if self.output.format == OutputFormat::Ndjson
+            && let Some(action) = *self.action.lock()
+        {
+            let event = FileEvent::with_path(self.display_path(&report.path), action, report);
+            print_line("file", &event);
+        }
+    }
+}
+
+impl Observer for CliObserver<'_> {
+    fn batch_started(&self, action: FileAction, files: &[PathBuf]) {
+        *self.action.lock() = Some(action);
+        if self.output.is_human() {
+            let files = files
+                .iter()
+                .map(|f| self.display_path(f))
+                .collect::<Vec<_>>();
+            match action {
+                FileAction::Encrypt => print_file_list("Encrypting", &files),
+                FileAction::Decrypt => print_file_list("Decrypting", &files),
+                FileAction::Stage => print_file_list("Staging", &files),
+                FileAction::Check => print_header("Checking encryption status", files.len()),
+                FileAction::Add => {}
+            }
+        }
+        self.progress.batch_started(action, files);
+    }
+
+    fn file_finished(&self, report: &FileReport) {
+        self.progress.file_finished(report);
+        self.stream(report);
+    }
+
+    fn file_skipped(&self, report: &FileReport) {
+        self.progress.file_skipped(report);
+        self.stream(report);
+    }
+
+    fn file_failed(&self, report: &FileReport) {
+        self.progress.file_failed(report);
+        self.stream(report);
+    }
+
+    fn batch_finished(&self, action: FileAction, summary: &Summary) {
+        self.progress.batch_finished(action, summary);
+    }
+}
+
+#[derive(Serialize)]
+struct ResultLine<'a> {
+    command: &'a str,
+    ok: bool,
+    error: Option<ErrorInfo>,
+}
+
+#[derive(Serialize)]
+struct ReportLine {
+    report: serde_json::Value,
+}
+
+#[derive(Serialize)]
+struct Document<'a> {
+    schema_version: u32,
+    #[serde(flatten)]
+    result: ResultLine<'a>,
+    files: &'a [FileEvent],
+    summary: Option<Summary>,
+    report: Option<&'a serde_json::Value>,
+}
+
+#[derive(Serialize)]
+struct Line<'a, T> {
+    schema_version: u32,
+    #[serde(rename = "type")]
+    kind: &'static str,
+    #[serde(flatten)]
+    body: &'a T,
+}
+
+fn print_line(kind: &'static str, body: &impl Serialize) {
+    let line = Line {
+        schema_version: SCHEMA_VERSION,
+        kind,
+        body,
+    };
+    if let Ok(json) = serde_json::to_string(&line) {
+        println!("{json}");
+    }
+}
+
+// --- Human output ---
+
+/// Maximum number of files to display individually before collapsing.
+const REPORT_LIST_LIMIT: usize = 10;
+
+fn print_human(repo: &Repo, report: &BatchReport) {
+    let verb = match report.action {
+        FileAction::Check => return print_check_result(repo, report),
+        FileAction::Decrypt => "Decrypt",
+        FileAction::Stage => "Stage",
+        _ => "Encrypt",
+    };
+    let Summary {
+        succeeded,
+        skipped,
+        failed,
+        ..
+    } = report.summary();
+    let label = format!("{verb} complete").bold();
+    if failed > 0 {
+        println!(
+            "\n{}: {} succeeded, {} skipped, {} {}",
+            label,
+            succeeded.to_string().green(),
+            skipped.to_string().yellow(),
+            failed.to_string().red(),
+            "failed".red(),
+        );
+    } else {
+        println!(
+            "\n{}: {} succeeded, {} skipped",
+            label,
+            succeeded.to_string().green(),
+            skipped.to_string().yellow(),
+        );
+    }
+}
+
+/// Print `files` with their count to stderr, as progress rather than a
+/// result. If the list exceeds `REPORT_LIST_LIMIT`, show the first few and
+/// summarize the rest as "... and N more files".
+fn print_file_list(label: &str, files: &[impl AsRef<Path>]) {
+    let count = files.len();
+    print_header(label, count);
+    for f in &files[..count.min(REPORT_LIST_LIMIT)] {
+        eprintln!("  {}", f.as_ref().display());
+    }
+    if count > REPORT_LIST_LIMIT {
+        let remaining = count - REPORT_LIST_LIMIT;
+        eprintln!("  {}", format!("... and {remaining} more files").dimmed());
+    }
+    eprintln!();
+}
+
+fn print_header(label: &str, count: usize) {
+    eprintln!(
+        "\n{} {} {}",
+        label.bold(),
+        format!("({count} files)").cyan(),
+        ":".dimmed()
+    );
+}
+
+/// Print the result of [`Repo::check`]. Files that could not be read are
+/// only logged.
+fn print_check_result(repo: &Repo, report: &BatchReport) {
+    let total = report.files.len();
+    let Summary { failed, .. } = report.summary();
+    if failed == 0 {
+        println!(
+            "\n{}: All {} files are encrypted.",
+            "Check complete".bold(),
+            total.to_string().green(),
+        );
+        return;
+    }
+    for (outcome, label) in [
+        (FileOutcome::NotEncrypted, "NOT encrypted"),
+        (FileOutcome::WrongKey, "encrypted with a different key"),
+        (FileOutcome::Truncated, "truncated"),
+        (FileOutcome::Tampered, "corrupt or tampered with"),
+    ] {
+        let files = report
+            .files
+            .iter()
+            .filter(|f| f.outcome == outcome)
+            .collect::<Vec<_>>();
+        if files.is_empty() {
+            continue;
+        }
+        println!(
+            "\n{} files are {}:",
+            files.len().to_string().yellow(),
+            label.yellow()
+        );
+        for f in files {
+            println!("  - {}", repo.display_path(&f.path).display());
+        }
+    }
+    println!(
+        "\n{}: {}/{} files encrypted",
+        "Check complete".bold(),
+        (total - failed).to_string().green(),
+        total,
+    );
+}
@@ -0,0 +1,72 @@
+//! Client-side `pre-push` hook (`git-se pre-push`), installed with
+//! `git-se i --hook pre-push`.
+//!
+//! Like [`pre_receive`](crate::pre_receive::pre_receive), it rejects commits
+//! that add or modify files of their committed crypt list without a valid
+//! GITSE header, but it runs before the push leaves the client and only
+//! checks the commits that the remote-tracking refs do not have yet. It is
+//! skipped by `git push --no-verify`.
+
+use crate::{
+    error::Result,
+    pre_receive::{Checker, PushedRef, Rejection, is_zero_oid, report},
+    repo::Repo,
+};
+
+/// Check the commits in `updates` that `remote` does not have yet, as far as
+/// the remote-tracking refs know.
+pub fn pre_push(repo: &Repo, remote: &str, updates: &[PushedRef]) -> Result<Vec<Rejection>> {
+    let mut checker = Checker::new(repo, &[])?;
+    let not_remote = format!("--remotes={remote}");
+    for update in updates {
+        if is_zero_oid(&update.new) {
+            continue;
+        }
+        checker.check(&update.name, &[&update.new, "--not", &not_remote])?;
+    }
+    Ok(checker.rejections)
+}
+
+/// Parse the hook's stdin (`<local ref> <local oid> <remote ref> <remote
+/// oid>` lines) into ref updates named after the remote ref.
+#[must_use]
+pub fn parse_push_updates(input: &str) -> Vec<PushedRef> {
+    input
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            let new = fields.next()?.to_owned();
+            let name = fields.next()?.to_owned();
+            let old = fields.next()?.to_owned();
+            Some(PushedRef { old, new, name })
+        })
+        .collect()
+}
+
+/// Entry point of the hook: check the ref updates in `input` (the hook's
+/// stdin) and explain every rejection on stderr.
+pub fn run_hook(repo: &Repo, remote: &str, input: &str) -> Result<()> {
+    let rejections = pre_push(repo, remote, &parse_push_updates(input))?;
+    report(
+        &rejections,
+        "run `git-se e` and amend, or push with `--no-verify` to skip this check",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_push_updates() {
+        let updates = parse_push_updates("refs/heads/a 1111 refs/heads/b 0000\n");
+        assert_eq!(
+            updates,
+            vec![PushedRef {
+                old: "0000".to_owned(),
+                new: "1111".to_owned(),
+                name: "refs/heads/b".to_owned(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,274 @@
+//! Server-side `pre-receive` hook (`git-se pre-receive`); its client-side
+//! counterpart is in [`crate::pre_push`].
+//!
+//! The pre-commit hook is client-side and skipped by `--no-verify`. This runs
+//! inside the (bare) server repository instead: for every pushed ref it walks
+//! the commits that are new to the server, and rejects the push if any of
+//! them adds or modifies a file in the crypt list without a valid GITSE
+//! header. The crypt list is read from the `git_simple_encrypt.toml` committed
+//! in each pushed commit, so no key or working tree is needed.
+//!
+//! Install it on the server with:
+//!
+//! ```sh
+//! printf '#!/bin/sh\nexec git-se pre-receive\n' > hooks/pre-receive
+//! chmod +x hooks/pre-receive
+//! ```
+//!
+//! Individual paths can be let through with a push option, which requires
+//! `receive.advertisePushOptions = true` on the server:
+//!
+//! ```sh
+//! git push -o git-se.allow=docs/example.env
+//! ```
+
+use std::collections::HashMap;
+
+use log::{debug, warn};
+use serde::Serialize;
+
+use crate::{
+    config::{CONFIG_FILE_NAME, Config, CryptMatcher, entry_covers_path},
+    crypt::{FileHeader, HEADER_LEN},
+    error::{Error, Result},
+    git::CatFile,
+    repo::Repo,
+};
+
+/// Push option prefix that allow-lists a path (or directory) for one push.
+pub const ALLOW_PUSH_OPTION: &str = "git-se.allow=";
+
+/// One `<old> <new> <ref>` line of the hook's stdin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushedRef {
+    pub old: String,
+    pub new: String,
+    pub name: String,
+}
+
+/// A plaintext (or corrupt) file in a pushed commit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Rejection {
+    pub refname: String,
+    pub commit: String,
+    pub path: String,
+    pub oid: String,
+}
+
+/// Parse the hook's stdin into ref updates.
+#[must_use]
+pub fn parse_updates(input: &str) -> Vec<PushedRef> {
+    input
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some(PushedRef {
+                old: fields.next()?.to_owned(),
+                new: fields.next()?.to_owned(),
+                name: fields.next()?.to_owned(),
+            })
+        })
+        .collect()
+}
+
+/// Paths allow-listed via `git push -o git-se.allow=<path>`, read from the
+/// `GIT_PUSH_OPTION_*` environment variables git passes to the hook.
+#[must_use]
+pub fn allowed_by_push_options() -> Vec<String> {
+    let count = std::env::var("GIT_PUSH_OPTION_COUNT")
+        .ok()
+        .and_then(|c| c.parse::<usize>().ok())
+        .unwrap_or(0);
+    (0..count)
+        .filter_map(|i| std::env::var(format!("GIT_PUSH_OPTION_{i}")).ok())
+        .filter_map(|option| {
+            option
+                .strip_prefix(ALLOW_PUSH_OPTION)
+                .map(ToOwned::to_owned)
+        })
+        .collect()
+}
+
+/// Check every commit that `updates` introduce to the repo, returning the
+/// files that should have been encrypted but are not. Paths covered by
+/// `allow` are skipped.
+pub fn pre_receive(repo: &Repo, updates: &[PushedRef], allow: &[String]) -> Result<Vec<Rejection>> {
+    let mut checker = Checker::new(repo, allow)?;
+    for update in updates {
+        if is_zero_oid(&update.new) {
+            debug!("{} deleted, nothing to check", update.name);
+            continue;
+        }
+        // Objects of the push are quarantined, but the refs are not updated
+        // yet, so `--not --all` leaves exactly the new commits.
+        checker.check(&update.name, &[&update.new, "--not", "--all"])?;
+    }
+    Ok(checker.rejections)
+}
+
+/// Collects the [`Rejection`]s of the commits it is asked to check.
+pub(crate) struct Checker<'a> {
+    repo: &'a Repo,
+    allow: &'a [String],
+    cat_file: CatFile,
+    /// Matchers of the committed configs, by blob id.
+    matchers: HashMap<String, CryptMatcher>,
+    pub(crate) rejections: Vec<Rejection>,
+}
+
+impl<'a> Checker<'a> {
+    pub(crate) fn new(repo: &'a Repo, allow: &'a [String]) -> Result<Self> {
+        Ok(Self {
+            repo,
+            allow,
+            cat_file: CatFile::spawn(repo)?,
+            matchers: HashMap::new(),
+            rejections: Vec::new(),
+        })
+    }
+
+    /// Check the commits listed by `git rev-list <range>`.
+    pub(crate) fn check(&mut self, refname: &str, range: &[&str]) -> Result<()> {
+        let mut args = vec!["rev-list"];
+        args.extend_from_slice(range);
+        args.push("--");
+        let commits = self.repo.run_with_output(&args)?;
+        for commit in commits.lines() {
+            let Some(matcher) = committed_matcher(&mut self.cat_file, &mut self.matchers, commit)?
+            else {
+                continue;
+            };
+            let crypt_paths: Vec<_> = changed_blobs(self.repo, commit)?
+                .into_iter()
+                .filter(|(_, path)| matcher.matches(path))
+                .collect();
+            for (oid, path) in crypt_paths {
+                if self
+                    .allow
+                    .iter()
+                    .any(|entry| entry_covers_path(entry, &path))
+                {
+                    warn!("Allowed by push option: {path}");
+                    continue;
+                }
+                let blob = self
+                    .cat_file
+                    .read(&oid)?
+                    .ok_or_else(|| Error::Git(format!("missing object {oid}")))?;
+                if !has_valid_header(&blob) {
+                    self.rejections.push(Rejection {
+                        refname: refname.to_owned(),
+                        commit: commit.to_owned(),
+                        path,
+                        oid,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Entry point of the `pre-receive` hook: check the ref updates in `input`
+/// (the hook's stdin) and explain every rejection on stderr, which git relays
+/// to the pusher.
+pub fn run_hook(repo: &Repo, input: &str) -> Result<()> {
+    let rejections = pre_receive(repo, &parse_updates(input), &allowed_by_push_options())?;
+    report(
+        &rejections,
+        &format!(
+            "run `git-se e` and amend, or push with `-o {ALLOW_PUSH_OPTION}<path>` to allow a file"
+        ),
+    )
+}
+
+/// Print every rejection with `hint` on stderr, and fail if there are any.
+pub(crate) fn report(rejections: &[Rejection], hint: &str) -> Result<()> {
+    if rejections.is_empty() {
+        return Ok(());
+    }
+    for r in rejections {
+        eprintln!(
+            "git-se: {} is not encrypted ({} in {})",
+            r.path, r.commit, r.refname
+        );
+    }
+    eprintln!("git-se: {hint}");
+    Err(Error::PlaintextPushed(rejections.len()))
+}
+
+pub(crate) fn is_zero_oid(oid: &str) -> bool {
+    oid.bytes().all(|b| b == b'0')
+}
+
+fn has_valid_header(blob: &[u8]) -> bool {
+    blob.get(..HEADER_LEN)
+        .and_then(|h| <&[u8; HEADER_LEN]>::try_from(h).ok())
+        .is_some_and(|h| FileHeader::from_bytes(h).is_ok())
+}
+
+/// The crypt list matcher of the config committed in `commit`, or `None` if
+/// it has none. Matchers are cached by the config's blob id.
+fn committed_matcher<'a>(
+    cat_file: &mut CatFile,
+    matchers: &'a mut HashMap<String, CryptMatcher>,
+    commit: &str,
+) -> Result<Option<&'a CryptMatcher>> {
+    let Some((oid, blob)) = cat_file.read_with_oid(&format!("{commit}:{CONFIG_FILE_NAME}"))? else {
+        return Ok(None);
+    };
+    if !matchers.contains_key(&oid) {
+        let config: Config = toml::from_str(&String::from_utf8_lossy(&blob))
+            .map_err(|e| Error::Config(format!("{CONFIG_FILE_NAME} in {commit}: {e}")))?;
+        matchers.insert(oid.clone(), config.matcher()?);
+    }
+    Ok(matchers.get(&oid))
+}
+
+/// Blobs added or modified by `commit` relative to each of its parents (or
+/// all of its blobs for a root commit), as `(oid, path)`.
+fn changed_blobs(repo: &Repo, commit: &str) -> Result<Vec<(String, String)>> {
+    let output = repo.run_with_bytes(&[
+        "diff-tree",
+        "-r",
+        "-z",
+        "-m",
+        "--root",
+        "--no-commit-id",
+        "--no-renames",
+        "--diff-filter=AMT",
+        commit,
+    ])?;
+    let mut fields = output
+        .split(|&b| b == 0)
+        .map(|f| String::from_utf8_lossy(f).into_owned());
+    let mut blobs = Vec::new();
+    // `:<old mode> <new mode> <old oid> <new oid> <status>` then the path.
+    while let Some(meta) = fields.next() {
+        let Some(path) = fields.next() else {
+            break;
+        };
+        let mut meta = meta.split(' ');
+        let new_mode = meta.nth(1).unwrap_or_default();
+        let new_oid = meta.nth(1).unwrap_or_default();
+        // Skip submodules and symlinks.
+        if new_mode.starts_with("100") {
+            blobs.push((new_oid.to_owned(), path));
+        }
+    }
+    Ok(blobs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_updates() {
+        let updates = parse_updates("0000 1111 refs/heads/main\n2222 0000 refs/tags/v1\n\n");
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].new, "1111");
+        assert_eq!(updates[1].name, "refs/tags/v1");
+        assert!(is_zero_oid(&updates[1].new));
+    }
+}
@@ -1,68 +1,106 @@
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
 use config_file2::LoadConfigFile;
-use log::{info, warn};
-use parking_lot::Mutex;
+use dashmap::DashMap;
+use log::{debug, error, info, warn};
+use path_absolutize::Absolutize as _;
+use pathdiff::diff_paths;
 use rayon::prelude::*;
 
 use crate::{
-    config::{CONFIG_FILE_NAME, Config},
+    attributes::{self, check_crypt_attrs},
+    config::{CONFIG_FILE_NAME, Config, is_glob},
+    crypt::{KeyCache, cache_key, decrypt_repo},
     error::{Error, Result},
-    utils::{Progress, is_file_encrypted, prompt_password, resolve_target_files, style::Colorize},
+    git::CatFile,
+    history::{BlobState, classify, classify_header},
+    hooks::{self, Hook},
+    observer::{Observer, notify},
+    report::{BatchReport, FileAction, FileOutcome, FileReport},
+    salt_cache,
+    utils::{
+        is_file_encrypted, list_matching_files, prompt_password, read_password,
+        resolve_target_files,
+    },
 };
 
 pub const GIT_CONFIG_PREFIX: &str =
     const_str::replace!(concat!(env!("CARGO_CRATE_NAME"), "."), "_", "-");
 
-/// The pre-commit hook content that runs `git-se check --staged` before every
-/// commit, so only files part of the current commit are checked.
-const PRE_COMMIT_HOOK: &[u8] = br#"#!/bin/sh
-# Auto-generated by git-se install
-git-se check --staged
-if [ $? -ne 0 ]; then
-    echo "Please run 'git-se e' to encrypt them before committing."
-    exit 1
-fi
-"#;
-
 #[derive(Debug, Clone, Default)]
 pub struct Repo {
     /// The absolute path of the opened repo.
     pub path: PathBuf,
+    /// The absolute path of the directory the repo was opened from, `path`
+    /// or one of its subdirectories. Paths given on the command line are
+    /// relative to it, and reports print paths relative to it.
+    pub cwd: PathBuf,
     pub conf: Config,
 }
 
 impl Repo {
-    /// Open a repo. The `path` argument must be an absolute path.
+    /// Open the repo containing `path`, which must be an absolute path to
+    /// the repo root, one of its subdirectories or its `.git` directory or
+    /// file.
+    ///
+    /// The root is found with `git rev-parse --show-prefix`. Outside a git
+    /// working tree, the nearest ancestor holding a config file is used, and
+    /// `path` itself if there is none.
     pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::open_with_fallback(path, None)
+    }
+
+    /// [`Repo::open`], but without a config file the settings of `fallback`
+    /// are used instead of the defaults.
+    fn open_with_fallback(path: impl AsRef<Path>, fallback: Option<&Config>) -> Result<Self> {
         debug_assert!(path.as_ref().is_absolute(), "given path must be absolute");
-        let mut repo_path = path.as_ref().to_path_buf();
-        if !repo_path.exists() {
-            return Err(Error::RepoNotFound(repo_path));
-        }
-        if !repo_path.is_dir() {
-            return Err(Error::NotADirectory(repo_path));
+        let mut cwd = path.as_ref().to_path_buf();
+        if !cwd.exists() {
+            return Err(Error::RepoNotFound(cwd));
         }
-        if repo_path
+        // `.git` is a file in linked worktrees, submodules and
+        // `--separate-git-dir` clones.
+        if cwd
             .file_name()
             .ok_or_else(|| Error::Other("Filename not found".to_string()))?
             == ".git"
         {
-            repo_path.pop();
+            cwd.pop();
         }
-        info!("Open repo: {}", repo_path.display());
-        let config_file_path = repo_path.join(CONFIG_FILE_NAME);
-        if !config_file_path.exists() {
-            warn!(
-                "Config file not found: `{}`, using default config instead...",
-                config_file_path.display()
-            );
+        if !cwd.is_dir() {
+            return Err(Error::NotADirectory(cwd));
         }
-        let conf = Config::load_or_default(&config_file_path)
-            .map_err(|e| Error::Config(e.to_string()))?
-            .with_repo_path(&repo_path);
+        let repo_path = find_root(&cwd);
+        debug!("Open repo: {}", repo_path.display());
+        let config_file_path = repo_path.join(CONFIG_FILE_NAME);
+        let conf = match fallback {
+            Some(fallback) if !config_file_path.exists() => {
+                debug!(
+                    "Config file not found: `{}`, using the parent's settings",
+                    config_file_path.display()
+                );
+                fallback.inherited().with_repo_path(&repo_path)
+            }
+            _ => {
+                if !config_file_path.exists() {
+                    warn!(
+                        "Config file not found: `{}`, using default config instead...",
+                        config_file_path.display()
+                    );
+                }
+                let mut conf = Config::load_or_default(&config_file_path)
+                    .map_err(|e| Error::Config(e.to_string()))?
+                    .with_repo_path(&repo_path);
+                conf.escape_literal_entries();
+                conf
+            }
+        };
         Ok(Self {
             path: repo_path,
+            cwd,
             conf,
         })
     }
@@ -72,24 +110,122 @@ impl Repo {
         &self.path
     }
 
+    /// Resolve a path given on the command line, relative to [`Repo::cwd`],
+    /// into a repo-relative path. Paths outside the repo are returned
+    /// absolute. Globs and `!` negations are returned unchanged, since
+    /// patterns are always relative to the repo root, as in `crypt_list`;
+    /// a glob that names an existing path, e.g. `config[prod].toml`, is a
+    /// path.
+    #[must_use]
+    pub fn resolve_cli_path(&self, path: &Path) -> PathBuf {
+        let raw = path.to_string_lossy();
+        if raw.starts_with('!') || (is_glob(&raw) && !self.cwd.join(path).exists()) {
+            return path.to_path_buf();
+        }
+        let absolute = path
+            .absolutize_from(&self.cwd)
+            .map_or_else(|_| self.cwd.join(path), std::borrow::Cow::into_owned);
+        if let Ok(relative) = absolute.strip_prefix(&self.path) {
+            return relative.to_path_buf();
+        }
+        absolute
+    }
+
+    /// [`Repo::resolve_cli_path`] for every path.
+    #[must_use]
+    pub fn resolve_cli_paths(&self, paths: &[PathBuf]) -> Vec<PathBuf> {
+        paths.iter().map(|p| self.resolve_cli_path(p)).collect()
+    }
+
+    /// `path` (absolute, or relative to the repo root) relative to
+    /// [`Repo::cwd`], for printing.
+    #[must_use]
+    pub fn display_path(&self, path: impl AsRef<Path>) -> PathBuf {
+        display_path(&self.path, &self.cwd, path.as_ref())
+    }
+
     pub fn to_absolute_path(&self, path: impl AsRef<Path>) -> PathBuf {
         self.path.join(path.as_ref())
     }
 
-    /// Read the master key from git config.
+    /// Read the master key from git config. In a submodule without a key of
+    /// its own, the key of the superproject is used.
     ///
     /// Returns an error if the key has not been configured.
     pub fn get_key(&self) -> Result<String> {
-        self.get_config("key").map_err(|e| {
+        self.find_key().map_err(|e| {
             Error::Other(format!(
                 "Key not found, please run `git-se p` (or `git-se set key <VALUE>`) first: {e}"
             ))
         })
     }
 
-    /// Set the key interactively by prompting on stdin.
+    fn find_key(&self) -> Result<String> {
+        self.get_config("key").or_else(|e| {
+            let parent = self.run_with_output(&["rev-parse", "--show-superproject-working-tree"]);
+            match parent.as_deref().map(str::trim) {
+                Ok(parent) if !parent.is_empty() => {
+                    debug!("Key not found, trying the superproject {parent}");
+                    Self {
+                        path: PathBuf::from(parent),
+                        ..Self::default()
+                    }
+                    .find_key()
+                }
+                _ => Err(e),
+            }
+        })
+    }
+
+    /// The initialised submodules, recursively, with their paths relative to
+    /// this repo, each opened as a repo of its own with the same
+    /// [`Repo::cwd`]. A submodule without a config file inherits the
+    /// settings of this one, see [`Config::inherited`].
+    pub fn submodules(&self) -> Result<Vec<(String, Self)>> {
+        let output = self.run_with_bytes(&[
+            "submodule",
+            "foreach",
+            "--quiet",
+            "--recursive",
+            r#"printf '%s\0' "$displaypath""#,
+        ])?;
+        let mut submodules: Vec<(String, Self)> = Vec::new();
+        for path in output.split(|&b| b == 0).filter(|p| !p.is_empty()) {
+            let path = String::from_utf8_lossy(path).into_owned();
+            // Nested submodules inherit from their closest parent.
+            let parent = submodules
+                .iter()
+                .rev()
+                .find(|(p, _)| path.starts_with(&format!("{p}/")))
+                .map_or(&self.conf, |(_, r)| &r.conf);
+            let mut repo = Self::open_with_fallback(self.path.join(&path), Some(parent))?;
+            repo.cwd.clone_from(&self.cwd);
+            submodules.push((path, repo));
+        }
+        Ok(submodules)
+    }
+
+    /// Set the key interactively by prompting on the terminal twice. The key
+    /// has to meet the configured
+    /// [`PasswordPolicy`](crate::config::PasswordPolicy).
     pub fn set_key_interactive(&self) -> Result<()> {
         let key = prompt_password("Please input your key: ")?;
+        self.conf.password_policy.check(&key)?;
+        let confirmation = prompt_password("Please input your key again: ")?;
+        if key != confirmation {
+            return Err(Error::PasswordMismatch);
+        }
+        self.set_config("key", key.as_str())?;
+        info!("Master key updated.");
+        Ok(())
+    }
+
+    /// Set the key to the first line of stdin, for scripts. The key has to
+    /// meet the configured
+    /// [`PasswordPolicy`](crate::config::PasswordPolicy).
+    pub fn set_key_from_stdin(&self) -> Result<()> {
+        let key = read_password(std::io::stdin().lock())?;
+        self.conf.password_policy.check(&key)?;
         self.set_config("key", key.as_str())?;
         info!("Master key updated.");
         Ok(())
@@ -99,156 +235,239 @@ impl Repo {
     ///
     /// When `staged` is true, only files staged for the current commit are
     /// checked, by intersecting staged files against the crypt list. This is
-    /// used by the pre-commit hook to avoid checking the entire repo.
+    /// used by the pre-commit hook to avoid checking the entire repo. The
+    /// staged (index) content is checked rather than the working tree copy,
+    /// and if a key is configured it must decrypt completely with that key.
     ///
-    /// Returns `Ok(())` if all files are encrypted, or an error summarizing
-    /// which files are not encrypted. The process exits with a non-zero code
-    /// when files are not encrypted, suitable for CI usage.
-    pub fn check(&self, paths: &[PathBuf], staged: bool) -> Result<()> {
-        let target_files = if staged {
-            let staged_output =
-                self.run_with_output(&["diff", "--cached", "--name-only", "--diff-filter=ACMR"])?;
-            let crypt_files = resolve_target_files(&[], &self.conf.crypt_list, self.path());
-
-            staged_output
-                .lines()
-                .map(|line| self.path.join(line.trim()))
-                .filter(|p| p.exists())
-                .filter(|f| crypt_files.contains(f))
-                .collect()
-        } else {
-            resolve_target_files(paths, &self.conf.crypt_list, self.path())
-        };
-
-        if staged && target_files.is_empty() {
-            println!("No staged files need encryption check.");
-            return Ok(());
+    /// Every file is [`FileOutcome::Ok`], [`FileOutcome::NotEncrypted`],
+    /// [`FileOutcome::WrongKey`], [`FileOutcome::Truncated`] or
+    /// [`FileOutcome::Tampered`] in the report, or failed if it could not be
+    /// read. [`BatchReport::result`] turns it into
+    /// [`Error::FilesNotEncrypted`] or [`Error::BatchFailed`], which the CLI
+    /// exits with, suitable for CI usage. The report is empty if `staged` is
+    /// set and nothing in the crypt list is staged. `observer` receives the
+    /// events of the check as it runs.
+    pub fn check(
+        &self,
+        paths: &[PathBuf],
+        staged: bool,
+        observer: &dyn Observer,
+    ) -> Result<BatchReport> {
+        if staged {
+            return self.check_staged(observer);
         }
+        let start = Instant::now();
+        let target_files = resolve_target_files(paths, &self.conf)?;
         if target_files.is_empty() {
             return Err(Error::NoFile("check"));
         }
 
-        println!(
-            "\n{} {} {}",
-            "Checking encryption status".bold(),
-            format!("({} files)", target_files.len()).cyan(),
-            ":".dimmed()
-        );
+        let relative = target_files
+            .iter()
+            .map(|f| PathBuf::from(String::from_utf8_lossy(&cache_key(f, self.path())).as_ref()))
+            .collect::<Vec<_>>();
+        observer.batch_started(FileAction::Check, &relative);
+        let files = target_files
+            .par_iter()
+            .zip(relative)
+            .map(|(f, path)| {
+                let start = Instant::now();
+                observer.file_started(&path);
+                let report = match is_file_encrypted(f) {
+                    Ok(true) => FileReport::new(path, FileOutcome::Ok, start),
+                    Ok(false) => FileReport::new(path, FileOutcome::NotEncrypted, start),
+                    Err(e) => FileReport::failed(path, e, start),
+                };
+                notify(observer, report)
+            })
+            .collect();
+
+        let report = BatchReport::new(FileAction::Check, files, start);
+        observer.batch_finished(report.action, &report.summary());
+        Ok(report)
+    }
 
-        let pb = Progress::new(target_files.len(), "Check");
-        let not_encrypted: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+    /// The `staged` mode of [`Self::check`]: validate the index blob of every
+    /// staged file in the crypt list.
+    fn check_staged(&self, observer: &dyn Observer) -> Result<BatchReport> {
+        let start = Instant::now();
+        let staged = self.staged_crypt_paths()?;
+        if staged.is_empty() {
+            return Ok(BatchReport::new(FileAction::Check, vec![], start));
+        }
+        observer.batch_started(
+            FileAction::Check,
+            &staged.iter().map(PathBuf::from).collect::<Vec<_>>(),
+        );
+        let key = self.get_key().ok();
+        let key_cache: KeyCache = DashMap::new();
+        let mut cat_file = CatFile::spawn(self)?;
+        let mut files = Vec::new();
+        for path in staged {
+            let file_start = Instant::now();
+            observer.file_started(Path::new(&path));
+            let Some(blob) = cat_file.read(&format!(":{path}"))? else {
+                let error = Error::Git(format!("`{path}` is not in the index"));
+                files.push(notify(
+                    observer,
+                    FileReport::failed(path, error, file_start),
+                ));
+                continue;
+            };
+            let state = match &key {
+                Some(key) => classify(&blob, &key_cache, key.as_bytes())?,
+                None => classify_header(&blob).unwrap_or(BlobState::Ok),
+            };
+            let outcome = match state {
+                BlobState::Ok => FileOutcome::Ok,
+                BlobState::WrongKey => FileOutcome::WrongKey,
+                BlobState::Truncated => FileOutcome::Truncated,
+                BlobState::Tampered => FileOutcome::Tampered,
+                BlobState::Plaintext => FileOutcome::NotEncrypted,
+            };
+            files.push(notify(observer, FileReport::new(path, outcome, file_start)));
+        }
+        let report = BatchReport::new(FileAction::Check, files, start);
+        observer.batch_finished(report.action, &report.summary());
+        Ok(report)
+    }
 
-        target_files.par_iter().try_for_each(|f| -> Result<()> {
-            if !is_file_encrypted(f)? {
-                let mut list = not_encrypted.lock();
-                let relative = pathdiff::diff_paths(f, &self.path).unwrap_or_else(|| f.clone());
-                list.push(relative);
+    /// Remove the entries for `paths` from the crypt list (`git-se rm`).
+    ///
+    /// Files that the list no longer covers are decrypted first if `decrypt`
+    /// is set, and the report of that is returned; otherwise a warning lists
+    /// the ones left encrypted; `observer` receives the events of the
+    /// decryption. Their salt cache entries are pruned either way, and
+    /// `.gitattributes` is synced.
+    pub fn remove_from_crypt_list(
+        &mut self,
+        paths: &[PathBuf],
+        decrypt: bool,
+        observer: &dyn Observer,
+    ) -> Result<Option<BatchReport>> {
+        let before = self.conf.matcher()?;
+        let mut conf = self.conf.clone();
+        for path in paths {
+            conf.remove_one_path_from_crypt_list(path)?;
+        }
+        let after = conf.matcher()?;
+        let no_longer_covered = |path: &str| before.matches(path) && !after.matches(path);
+
+        let affected: Vec<PathBuf> =
+            list_matching_files(before.roots(), self.path(), before.overrides())
+                .iter()
+                .map(|f| {
+                    PathBuf::from(String::from_utf8_lossy(&cache_key(f, self.path())).as_ref())
+                })
+                .filter(|f| no_longer_covered(&f.to_string_lossy()))
+                .collect();
+        let report = if decrypt && !affected.is_empty() {
+            let report = decrypt_repo(self, &affected, observer)?;
+            // Keep the entries if their files cannot be decrypted.
+            for f in report.failures() {
+                if let Some(e) = &f.error {
+                    error!("{}: {e}", f.path.display());
+                }
             }
-            pb.inc(1);
-            Ok(())
-        })?;
-
-        pb.finish_and_clear();
-
-        let not_encrypted = not_encrypted.into_inner();
-        let total = target_files.len();
-        let encrypted_count = total - not_encrypted.len();
-
-        if not_encrypted.is_empty() {
-            println!(
-                "\n{}: All {} files are encrypted.",
-                "Check complete".bold(),
-                total.to_string().green(),
-            );
-            Ok(())
+            report.result()?;
+            Some(report)
         } else {
-            println!(
-                "\n{} files are {}:",
-                not_encrypted.len().to_string().yellow(),
-                "NOT encrypted".yellow()
-            );
-            for f in &not_encrypted {
-                println!("  - {}", f.display());
-            }
-
-            println!(
-                "\n{}: {}/{} files encrypted",
-                "Check complete".bold(),
-                encrypted_count.to_string().green(),
-                total,
-            );
+            None
+        };
 
-            Err(Error::FilesNotEncrypted(not_encrypted.len(), total))
+        self.conf.remove_paths_from_crypt_list(paths)?;
+        attributes::sync(self)?;
+        let pruned = salt_cache::prune(self.path(), |key| {
+            no_longer_covered(&String::from_utf8_lossy(key))
+        });
+        debug!("pruned {pruned} salt cache entries");
+
+        if !decrypt {
+            let encrypted: Vec<&PathBuf> = affected
+                .iter()
+                .filter(|f| is_file_encrypted(&self.path().join(f)).unwrap_or(false))
+                .collect();
+            if !encrypted.is_empty() {
+                warn!(
+                    "{} files are no longer in the crypt list but still encrypted, run `git-se d <path>` (or `git-se rm --decrypt`) to decrypt them:",
+                    encrypted.len()
+                );
+                for f in encrypted {
+                    warn!("  - {}", f.display());
+                }
+            }
         }
+        Ok(report)
     }
 
-    /// Install a pre-commit hook that runs `git-se check` before each commit.
-    ///
-    /// Creates `<repo>/.git/hooks/pre-commit` with the check script.
-    /// Fails if a hook already exists and is not managed by git-se.
-    pub fn install_hook(&self) -> Result<()> {
-        let hooks_dir = self.path.join(".git").join("hooks");
-        std::fs::create_dir_all(&hooks_dir)?;
-
-        let hook_path = hooks_dir.join("pre-commit");
+    /// Repo-relative paths of the files to encrypt (per the crypt list and
+    /// the staged `crypt` attributes) that are added, copied, modified or
+    /// renamed in the index, relative to `HEAD`.
+    pub fn staged_crypt_paths(&self) -> Result<Vec<String>> {
+        let matcher = self.conf.matcher()?;
+        let output = self.run_with_bytes(&[
+            "diff",
+            "--cached",
+            "--name-only",
+            "-z",
+            "--diff-filter=ACMR",
+        ])?;
+        let staged = output
+            .split(|&b| b == 0)
+            .filter(|p| !p.is_empty())
+            .map(|p| String::from_utf8_lossy(p).into_owned())
+            .collect::<Vec<_>>();
+        let attrs = check_crypt_attrs(&self.path, &staged, true)?;
+        Ok(staged
+            .into_iter()
+            .zip(attrs)
+            .filter(|(p, attrs)| attrs.selects(p, matcher.matches(p)))
+            .map(|(p, _)| p)
+            .collect())
+    }
 
-        // Check if hook already exists
-        if hook_path.exists() {
-            return Err(Error::HookExists(hook_path));
-        }
+    /// Install a pre-commit hook that runs `git-se check --staged` before
+    /// each commit. An existing hook is chained, see [`hooks::install`].
+    #[deprecated(note = "use `hooks::install(repo, &[Hook::PreCommit])`")]
+    pub fn install_hook(&self) -> Result<()> {
+        hooks::install(self, &[Hook::PreCommit]).map(|_| ())
+    }
 
-        std::fs::write(&hook_path, PRE_COMMIT_HOOK)?;
+    /// Build a `git` command that runs inside the repo.
+    #[must_use]
+    pub fn git_command(&self) -> std::process::Command {
+        let mut cmd = std::process::Command::new("git");
 
-        // Set executable permission on unix. Windows users need Git for Windows
-        // (msys-based) which executes the sh hook regardless of the executable
-        // bit; a normal `write` is sufficient there.
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::PermissionsExt;
-            let mut perms = std::fs::metadata(&hook_path)?.permissions();
-            perms.set_mode(0o755);
-            std::fs::set_permissions(&hook_path, perms)?;
+        // Force English output in tests so we can match on stderr reliably.
+        if cfg!(test) {
+            cmd.env("LC_ALL", "C.UTF-8").env("LANGUAGE", "C.UTF-8");
         }
 
-        println!(
-            "{} pre-commit hook at {}",
-            "Installed".green().bold(),
-            hook_path.display()
-        );
-        Ok(())
+        cmd.current_dir(&self.path);
+        cmd
     }
 
     /// Run a `git` command in the repo, discarding its stdout/stderr.
     pub fn run(&self, args: &[&str]) -> Result<()> {
-        let output = std::process::Command::new("git")
-            .current_dir(&self.path)
-            .args(args)
-            .output()?;
-        if !output.status.success() {
-            return Err(Error::Git(
-                String::from_utf8_lossy(&output.stderr).into_owned(),
-            ));
-        }
-        Ok(())
+        self.run_with_bytes(args).map(|_| ())
     }
 
     /// Run a `git` command and return its trimmed stdout as a `String`.
     pub fn run_with_output(&self, args: &[&str]) -> Result<String> {
-        let mut cmd = std::process::Command::new("git");
-
-        // Force English output in tests so we can match on stderr reliably.
-        if cfg!(test) {
-            cmd.env("LC_ALL", "C.UTF-8").env("LANGUAGE", "C.UTF-8");
-        }
+        String::from_utf8(self.run_with_bytes(args)?)
+            .map_err(|e| Error::Other(format!("git output not UTF-8: {e}")))
+    }
 
-        let output = cmd.current_dir(&self.path).args(args).output()?;
+    /// Run a `git` command and return its raw stdout. Use this for `-z`
+    /// output and blob contents, which are not guaranteed to be UTF-8.
+    pub fn run_with_bytes(&self, args: &[&str]) -> Result<Vec<u8>> {
+        let output = self.git_command().args(args).output()?;
         if !output.status.success() {
             return Err(Error::Git(
                 String::from_utf8_lossy(&output.stderr).into_owned(),
             ));
         }
-        String::from_utf8(output.stdout)
-            .map_err(|e| Error::Other(format!("git output not UTF-8: {e}")))
+        Ok(output.stdout)
     }
 
     /// Write a value to `<prefix>.<key>` in the repo-local git config.
@@ -269,6 +488,37 @@ impl Repo {
     }
 }
 
+/// The root of the working tree containing `dir`, see [`Repo::open`].
+fn find_root(dir: &Path) -> PathBuf {
+    let prefix = std::process::Command::new("git")
+        .args(["rev-parse", "--show-prefix"])
+        .current_dir(dir)
+        .output()
+        .ok()
+        .filter(|o| o.status.success());
+    if let Some(prefix) = prefix {
+        // Pop the prefix rather than using `--show-toplevel`, which resolves
+        // symlinks and would no longer be a parent of `dir`.
+        let prefix = String::from_utf8_lossy(&prefix.stdout);
+        let mut root = dir.to_path_buf();
+        for _ in Path::new(prefix.trim_end_matches('\n')).components() {
+            root.pop();
+        }
+        return root;
+    }
+    dir.ancestors()
+        .find(|d| d.join(CONFIG_FILE_NAME).is_file())
+        .unwrap_or(dir)
+        .to_path_buf()
+}
+
+/// `path` (absolute, or relative to `root`) relative to `cwd`, see
+/// [`Repo::display_path`].
+pub(crate) fn display_path(root: &Path, cwd: &Path, path: &Path) -> PathBuf {
+    let absolute = root.join(path);
+    diff_paths(&absolute, cwd).unwrap_or(absolute)
+}
+
 #[cfg(test)]
 mod tests {
     use std::process::Command;
@@ -277,14 +527,15 @@ mod tests {
     use tempfile::TempDir;
 
     use super::*;
-    use crate::crypt::HEADER_LEN;
+    use crate::{crypt::HEADER_LEN, observer::NoopObserver};
 
     #[test]
     fn test_repo_open() -> Result<()> {
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
         let repo = Repo::open(Path::new(".").absolutize()?)?;
-        assert_eq!(repo.path().file_name().unwrap(), "git-simple-encrypt");
+        assert_eq!(repo.path(), manifest_dir);
         let repo = Repo::open(Path::new("./.git").absolutize()?)?;
-        assert_eq!(repo.path().file_name().unwrap(), "git-simple-encrypt");
+        assert_eq!(repo.path(), manifest_dir);
         Ok(())
     }
 
@@ -312,23 +563,14 @@ mod tests {
     }
 
     #[test]
-    fn test_install_hook_creates_file() -> Result<()> {
+    #[allow(deprecated)]
+    fn test_install_hook_wrapper() -> Result<()> {
         let dir = init_temp_repo();
-        let repo_path = dir.path().absolutize().unwrap().to_path_buf();
-        let repo = Repo::open(&repo_path)?;
-
+        let repo = Repo::open(dir.path().absolutize().unwrap())?;
         repo.install_hook()?;
-        let hook = repo_path.join(".git").join("hooks").join("pre-commit");
-        assert!(hook.exists());
-        let content = std::fs::read(&hook)?;
-        assert!(
-            content.starts_with(b"#!/bin/sh"),
-            "hook should be a shell script"
-        );
-
-        // Second install must fail (idempotency guard).
-        let second = repo.install_hook();
-        assert!(matches!(second, Err(Error::HookExists(_))));
+        repo.install_hook()?;
+        let hook = std::fs::read_to_string(repo.path().join(".git/hooks/pre-commit"))?;
+        assert!(hook.contains("git-se check --staged"), "{hook}");
         Ok(())
     }
 
@@ -342,9 +584,15 @@ mod tests {
         std::fs::write(repo_path.join("plain.txt"), b"hello")?;
         repo.conf.add_one_path_to_crypt_list("plain.txt")?;
 
-        // check should return FilesNotEncrypted.
-        let result = repo.check(&[], false);
-        assert!(matches!(result, Err(Error::FilesNotEncrypted(1, 1))));
+        // check should report the file, and fail with FilesNotEncrypted.
+        let report = repo.check(&[], false, &NoopObserver)?;
+        assert_eq!(report.files.len(), 1);
+        assert_eq!(report.files[0].path, Path::new("plain.txt"));
+        assert_eq!(report.files[0].outcome, FileOutcome::NotEncrypted);
+        assert!(matches!(
+            report.result(),
+            Err(Error::FilesNotEncrypted(1, 1))
+        ));
 
         // Encrypt the file (cheap path: just give it a valid 64-byte GITSE header so
         // is_file_encrypted returns true), then check passes.
@@ -352,8 +600,8 @@ mod tests {
         fake_header[..5].copy_from_slice(b"GITSE");
         fake_header[5] = 3; // version
         std::fs::write(repo_path.join("plain.txt"), fake_header).unwrap();
-        let result = repo.check(&[], false);
-        assert!(result.is_ok());
+        let report = repo.check(&[], false, &NoopObserver)?;
+        assert!(report.result().is_ok());
         Ok(())
     }
 }
@@ -0,0 +1,277 @@
+//! Reports returned by the repo-wide operations ([`encrypt_repo`],
+//! [`decrypt_repo`], [`stage`] and [`Repo::check`]) and the batch APIs
+//! ([`encrypt_files_to`], [`decrypt_files_to`]).
+//!
+//! The operations do not print anything; a [`BatchReport`] holds the outcome,
+//! typed error, byte counts and time of every file, so that the CLI, a GUI or
+//! a server can present them as they like. A failed file does not stop the
+//! batch, and [`BatchReport::result`] turns the failures into one [`Error`].
+//!
+//! [`encrypt_repo`]: crate::crypt::encrypt_repo
+//! [`decrypt_repo`]: crate::crypt::decrypt_repo
+//! [`stage`]: crate::stage::stage
+//! [`Repo::check`]: crate::repo::Repo::check
+//! [`encrypt_files_to`]: crate::crypt::encrypt_files_to
+//! [`decrypt_files_to`]: crate::crypt::decrypt_files_to
+
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+use crate::error::{Error, Result};
+
+/// What was done to a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileAction {
+    Add,
+    Encrypt,
+    Decrypt,
+    Stage,
+    Check,
+}
+
+impl FileAction {
+    /// The verb, e.g. `encrypt`.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Add => "add",
+            Self::Encrypt => "encrypt",
+            Self::Decrypt => "decrypt",
+            Self::Stage => "stage",
+            Self::Check => "check",
+        }
+    }
+}
+
+/// How a [`FileAction`] turned out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileOutcome {
+    /// The action was carried out; for `check`, the file is encrypted.
+    Ok,
+    /// There was nothing to do, e.g. the file was already encrypted.
+    Skipped,
+    /// The action failed, see [`FileReport::error`].
+    Failed,
+    /// `check`: the file is not encrypted.
+    NotEncrypted,
+    /// `check --staged`: the file is encrypted with a different key.
+    WrongKey,
+    /// `check --staged`: the ciphertext ends early.
+    Truncated,
+    /// `check --staged`: invalid header, or a chunk fails authentication.
+    Tampered,
+    /// `add --dry-run`: the file would be encrypted.
+    DryRun,
+}
+
+impl FileOutcome {
+    /// Whether the file counts as failed in a [`Summary`].
+    #[must_use]
+    pub const fn is_failure(self) -> bool {
+        matches!(
+            self,
+            Self::Failed | Self::NotEncrypted | Self::WrongKey | Self::Truncated | Self::Tampered
+        )
+    }
+}
+
+/// The result of an action on one file.
+#[derive(Debug)]
+pub struct FileReport {
+    /// The path, relative to the repo root, or as passed to the batch APIs.
+    pub path: PathBuf,
+    pub outcome: FileOutcome,
+    /// Why the action failed, for [`FileOutcome::Failed`].
+    pub error: Option<Error>,
+    /// Bytes read, 0 if the file was skipped.
+    pub bytes_in: u64,
+    /// Bytes written, 0 if the file was skipped.
+    pub bytes_out: u64,
+    /// Time spent on the file.
+    pub elapsed: Duration,
+}
+
+impl FileReport {
+    /// A report without error or byte counts, timed from `start`.
+    #[must_use]
+    pub fn new(path: impl Into<PathBuf>, outcome: FileOutcome, start: Instant) -> Self {
+        Self {
+            path: path.into(),
+            outcome,
+            error: None,
+            bytes_in: 0,
+            bytes_out: 0,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    /// A [`FileOutcome::Failed`] report.
+    #[must_use]
+    pub fn failed(path: impl Into<PathBuf>, error: Error, start: Instant) -> Self {
+        Self {
+            error: Some(error),
+            ..Self::new(path, FileOutcome::Failed, start)
+        }
+    }
+
+    /// Set the byte counts.
+    #[must_use]
+    pub const fn with_bytes(mut self, bytes_in: u64, bytes_out: u64) -> Self {
+        self.bytes_in = bytes_in;
+        self.bytes_out = bytes_out;
+        self
+    }
+}
+
+/// The result of an operation on a batch of files.
+#[derive(Debug)]
+pub struct BatchReport {
+    pub action: FileAction,
+    /// One report per file, sorted by path.
+    pub files: Vec<FileReport>,
+    /// Time spent on the whole batch.
+    pub elapsed: Duration,
+}
+
+impl BatchReport {
+    /// A report of `files`, timed from `start`.
+    #[must_use]
+    pub fn new(action: FileAction, mut files: Vec<FileReport>, start: Instant) -> Self {
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        Self {
+            action,
+            files,
+            elapsed: start.elapsed(),
+        }
+    }
+
+    /// The counts of the batch.
+    #[must_use]
+    pub fn summary(&self) -> Summary {
+        let count = |f: fn(FileOutcome) -> bool| self.files.iter().filter(|r| f(r.outcome)).count();
+        Summary {
+            total: self.files.len(),
+            succeeded: count(|o| o == FileOutcome::Ok),
+            skipped: count(|o| o == FileOutcome::Skipped),
+            failed: count(FileOutcome::is_failure),
+        }
+    }
+
+    /// The files that failed, see [`FileOutcome::is_failure`].
+    pub fn failures(&self) -> impl Iterator<Item = &FileReport> {
+        self.files.iter().filter(|r| r.outcome.is_failure())
+    }
+
+    /// Total bytes read.
+    #[must_use]
+    pub fn bytes_in(&self) -> u64 {
+        self.files.iter().map(|r| r.bytes_in).sum()
+    }
+
+    /// Total bytes written.
+    #[must_use]
+    pub fn bytes_out(&self) -> u64 {
+        self.files.iter().map(|r| r.bytes_out).sum()
+    }
+
+    /// `Ok` if no file failed. Otherwise [`Error::FilesNotEncrypted`] with the
+    /// [`FileOutcome::NotEncrypted`] files if `check` found any, and
+    /// [`Error::BatchFailed`] for everything else, e.g. files with a wrong key;
+    /// the errors of the single files stay in [`Self::files`].
+    pub fn result(&self) -> Result<()> {
+        let Summary { total, failed, .. } = self.summary();
+        let not_encrypted = self
+            .files
+            .iter()
+            .filter(|f| f.outcome == FileOutcome::NotEncrypted)
+            .count();
+        match self.action {
+            _ if failed == 0 => Ok(()),
+            FileAction::Check if not_encrypted > 0 => {
+                Err(Error::FilesNotEncrypted(not_encrypted, total))
+            }
+            action => Err(Error::BatchFailed(action.as_str(), failed, total)),
+        }
+    }
+}
+
+/// The counts of a batch of files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Summary {
+    pub total: usize,
+    pub succeeded: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+impl Summary {
+    /// Add the counts of `other`.
+    pub const fn add(&mut self, other: Self) {
+        self.total += other.total;
+        self.succeeded += other.succeeded;
+        self.skipped += other.skipped;
+        self.failed += other.failed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary_and_result() {
+        let start = Instant::now();
+        let files = vec![
+            FileReport::new("b", FileOutcome::NotEncrypted, start),
+            FileReport::new("a", FileOutcome::Ok, start).with_bytes(3, 70),
+            FileReport::new("c", FileOutcome::Skipped, start),
+            FileReport::failed("d", Error::FileTruncated, start),
+        ];
+        let report = BatchReport::new(FileAction::Check, files, start);
+        assert_eq!(report.files[0].path, PathBuf::from("a"));
+        assert_eq!(
+            report.summary(),
+            Summary {
+                total: 4,
+                succeeded: 1,
+                skipped: 1,
+                failed: 2,
+            }
+        );
+        assert_eq!((report.bytes_in(), report.bytes_out()), (3, 70));
+        assert!(matches!(
+            report.result(),
+            Err(Error::FilesNotEncrypted(1, 4))
+        ));
+
+        let report = BatchReport::new(
+            FileAction::Encrypt,
+            vec![FileReport::failed("d", Error::FileTruncated, start)],
+            start,
+        );
+        assert!(matches!(
+            report.result(),
+            Err(Error::BatchFailed("encrypt", 1, 1))
+        ));
+        let report = BatchReport::new(
+            FileAction::Check,
+            vec![FileReport::new("e", FileOutcome::WrongKey, start)],
+            start,
+        );
+        assert!(matches!(
+            report.result(),
+            Err(Error::BatchFailed("check", 1, 1))
+        ));
+        assert!(
+            BatchReport::new(FileAction::Stage, vec![], start)
+                .result()
+                .is_ok()
+        );
+    }
+}
@@ -29,10 +29,18 @@
 //!
 //! # Persistence
 //!
-//! Serialized via [`rkyv`] to `<repo>/.git/git-simple-encrypt-salt-cache`.
+//! Serialized via [`rkyv`] to `<git-dir>/git-simple-encrypt-salt-cache`.
 //! The binary format is opaque and not meant for human consumption. Writes
 //! are performed atomically to prevent corruption.
 //!
+//! `<git-dir>` is `git rev-parse --absolute-git-dir`, so every linked
+//! worktree has a cache of its own rather than sharing the one in the common
+//! dir: worktrees check out different versions of the same paths, and a
+//! decrypt in one worktree must not replace the salts another one needs to
+//! re-encrypt its files unchanged. It also keeps concurrent runs in
+//! different worktrees from racing on one file. Outside a git repo the cache
+//! lives in `<repo>/.git`.
+//!
 //! # Lifecycle
 //!
 //! - **Decrypt**: Create sender → workers send entries → saver persists
@@ -95,9 +103,19 @@ impl fmt::Debug for CacheRef<'_> {
     }
 }
 
-/// Returns the cache file path for the given repo.
+/// Returns the cache file path for the given repo, inside its (per
+/// worktree) git dir.
 fn cache_path(repo_path: &Path) -> PathBuf {
-    repo_path.join(".git").join(CACHE_FILENAME)
+    let git_dir = std::process::Command::new("git")
+        .args(["rev-parse", "--absolute-git-dir"])
+        .current_dir(repo_path)
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .map(|o| PathBuf::from(String::from_utf8_lossy(&o.stdout).trim_end()));
+    git_dir
+        .unwrap_or_else(|| repo_path.join(".git"))
+        .join(CACHE_FILENAME)
 }
 
 // ---------------------------------------------------------------------------
@@ -327,6 +345,47 @@ pub fn create_writer(repo_path: &Path) -> (SaltCacheSender, SaltCacheSaver) {
     )
 }
 
+/// Remove every cache entry whose key satisfies `remove` and return how many
+/// were removed.
+///
+/// Used when paths leave the crypt list, so that re-adding them later starts
+/// with fresh salts. Like [`SaltCacheSaver::save`] this is best-effort: a
+/// missing or unreadable cache is left alone.
+pub fn prune(repo_path: &Path, remove: impl Fn(&[u8]) -> bool) -> usize {
+    let path = cache_path(repo_path);
+    let Ok(bytes) = std::fs::read(&path) else {
+        return 0;
+    };
+    let Ok(mut entries) = rkyv::from_bytes::<HashMap<Vec<u8>, CachedEntry>, RkyvError>(&bytes)
+    else {
+        warn!(
+            "Salt cache at {} is unreadable, not pruning it",
+            path.display()
+        );
+        return 0;
+    };
+    let before = entries.len();
+    entries.retain(|key, _| !remove(key));
+    let removed = before - entries.len();
+    if removed == 0 {
+        return 0;
+    }
+    match rkyv::to_bytes::<RkyvError>(&entries) {
+        Ok(bytes) => {
+            if let Err(e) = atomic_write(&path, bytes.as_slice()) {
+                warn!("Failed to save salt cache to {}: {e}", path.display());
+                return 0;
+            }
+            debug!("Pruned {removed} entries from {}", path.display());
+            removed
+        }
+        Err(e) => {
+            warn!("Failed to serialize salt cache: {e}");
+            0
+        }
+    }
+}
+
 impl Drop for SaltCacheSaver {
     fn drop(&mut self) {
         self.save_inner();
@@ -432,6 +491,28 @@ mod tests {
         assert_eq!(reader.get(b"subdir/file.txt"), Some(entry));
     }
 
+    #[test]
+    fn test_prune() {
+        let dir = TempDir::new().unwrap();
+        let repo = dir.path();
+        std::fs::create_dir_all(repo.join(".git")).unwrap();
+
+        {
+            let (sender, saver) = create_writer(repo);
+            sender.insert(b"keep.txt", make_entry(1, 2));
+            sender.insert(b"gone/a.txt", make_entry(3, 4));
+            sender.insert(b"gone/b.txt", make_entry(5, 6));
+            drop(sender);
+            saver.save();
+        }
+
+        assert_eq!(prune(repo, |key| key.starts_with(b"gone/")), 2);
+        let reader = SaltCacheReader::load(repo);
+        assert_eq!(reader.get(b"keep.txt"), Some(make_entry(1, 2)));
+        assert_eq!(reader.get(b"gone/a.txt"), None);
+        assert_eq!(prune(repo, |_| false), 0);
+    }
+
     #[test]
     fn test_merge_with_existing() {
         let dir = TempDir::new().unwrap();
@@ -0,0 +1,66 @@
+//! Print the plaintext of a working tree file or of any blob in history,
+//! without touching the working tree.
+//!
+//! Inputs that are not encrypted are passed through unchanged, so both
+//! commands also work on files that were committed before they were added to
+//! the crypt list.
+
+use std::{
+    io::{Read, Write},
+    path::Path,
+    process::Stdio,
+};
+
+use log::debug;
+
+use crate::{
+    crypt::decrypt_or_copy_into,
+    error::{Error, Result},
+    repo::Repo,
+};
+
+/// Write the plaintext of the working tree file `path` to `writer`. Relative
+/// paths are resolved against the repo root.
+pub fn cat(repo: &Repo, path: &Path, writer: &mut impl Write) -> Result<()> {
+    let path = repo.path().join(path);
+    if !path.exists() {
+        return Err(Error::PathNotExist(path));
+    }
+    let key = repo.get_key()?;
+    let header = decrypt_or_copy_into(&mut std::fs::File::open(&path)?, writer, key.as_bytes())?;
+    debug!("cat {}: encrypted = {}", path.display(), header.is_some());
+    writer.flush()?;
+    Ok(())
+}
+
+/// Write the plaintext of the blob named by `object` (e.g. `HEAD~2:a.env`) to
+/// `writer`.
+///
+/// The blob is streamed from `git cat-file blob`, so large files are never
+/// held in memory.
+pub fn show(repo: &Repo, object: &str, writer: &mut impl Write) -> Result<()> {
+    let key = repo.get_key()?;
+    // Run from the cwd so that `<rev>:./<path>` is relative to it, as in git.
+    let mut child = repo
+        .git_command()
+        .current_dir(&repo.cwd)
+        .args(["cat-file", "blob", object])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdout = child.stdout.take().expect("stdout is piped");
+    let decrypted = decrypt_or_copy_into(&mut stdout, writer, key.as_bytes());
+    drop(stdout);
+
+    let mut stderr = String::new();
+    if let Some(mut pipe) = child.stderr.take() {
+        pipe.read_to_string(&mut stderr)?;
+    }
+    if !child.wait()?.success() {
+        return Err(Error::Git(stderr));
+    }
+    let header = decrypted?;
+    debug!("show {object}: encrypted = {}", header.is_some());
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,228 @@
+//! `git-se stage`: commit ciphertext while keeping plaintext in the working
+//! tree.
+//!
+//! Instead of encrypting files in place and `git add`-ing them, the plaintext
+//! is encrypted in memory, written to the object database with
+//! `git hash-object -w` and put in the index with `git update-index`. The
+//! working tree (and every file's mtime) is left untouched, so `git status`
+//! shows the files as modified until they are encrypted or staged again.
+//!
+//! The salt and `file_id` come from the salt cache (recorded by `git-se d`),
+//! then from the currently staged blob, so staging unchanged plaintext keeps
+//! the index entry byte-identical.
+
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use dashmap::DashMap;
+use log::debug;
+use rand::prelude::*;
+
+use crate::{
+    attributes::{CryptAttrs, check_crypt_attrs},
+    crypt::{
+        FILE_ID_LEN, FileHeader, HEADER_LEN, KeyCache, MAGIC, SALT_LEN, cache_key,
+        encrypt_into_with_progress, get_or_derive_key, is_encrypted_version,
+    },
+    error::{Error, Result},
+    git::{CatFile, hash_object, run_with_input},
+    observer::{Observer, notify},
+    repo::Repo,
+    report::{BatchReport, FileAction, FileOutcome, FileReport},
+    salt_cache::SaltCacheReader,
+    utils::resolve_target_files,
+};
+
+/// Encrypt crypt list files into the index, leaving the working tree as is.
+///
+/// With `staged`, the staged content of every staged crypt list file is
+/// encrypted instead (`paths` is ignored), so partially staged changes stay
+/// partial; this is what the `pre-commit-stage` hook runs. Otherwise the
+/// working tree content of `paths` (by default the whole crypt list) is
+/// staged, as `git add` would. Content that is already encrypted is staged
+/// unchanged.
+///
+/// Files whose index entry stays the same are [`FileOutcome::Skipped`] in the
+/// report, which is empty with `staged` if nothing in the crypt list is
+/// staged. A file that cannot be read or encrypted is [`FileOutcome::Failed`]
+/// and keeps its index entry, while the others are staged; see
+/// [`BatchReport::result`]. `observer` receives the events of the batch as it
+/// runs.
+pub fn stage(
+    repo: &Repo,
+    paths: &[PathBuf],
+    staged: bool,
+    observer: &dyn Observer,
+) -> Result<BatchReport> {
+    let start = Instant::now();
+    let key = repo.get_key()?;
+    if key.is_empty() {
+        return Err(Error::EmptyKey);
+    }
+
+    let targets: Vec<String> = if staged {
+        repo.staged_crypt_paths()?
+    } else {
+        resolve_target_files(paths, &repo.conf)?
+            .iter()
+            .map(|f| String::from_utf8_lossy(&cache_key(f, repo.path())).into_owned())
+            .collect()
+    };
+    if targets.is_empty() {
+        if staged {
+            debug!("No staged files in the crypt list");
+            return Ok(BatchReport::new(FileAction::Stage, vec![], start));
+        }
+        return Err(Error::NoFile("stage"));
+    }
+    let rule_set = repo.conf.rule_set()?;
+    let attrs = check_crypt_attrs(repo.path(), &targets, staged)?;
+    let index = index_entries(repo)?;
+    let reader = SaltCacheReader::load(repo.path());
+    let key_cache: KeyCache = DashMap::new();
+    let mut cat_file = CatFile::spawn(repo)?;
+    let mut batch_salt = [0u8; SALT_LEN];
+    rand::rng().fill_bytes(&mut batch_salt);
+
+    observer.batch_started(
+        FileAction::Stage,
+        &targets.iter().map(PathBuf::from).collect::<Vec<_>>(),
+    );
+    // The new blob of `path`, with the bytes read and written.
+    let mut stage_file = |path: &str, attrs: &CryptAttrs| -> Result<(String, u64, u64)> {
+        let staged_blob = cat_file.read(&format!(":{path}"))?;
+        let content = if staged {
+            staged_blob.clone().unwrap_or_default()
+        } else {
+            std::fs::read(repo.path().join(path))?
+        };
+        let bytes_in = content.len() as u64;
+
+        let ciphertext = if is_encrypted(&content) {
+            content
+        } else {
+            let (salt, file_id) = reader.get(path.as_bytes()).map_or_else(
+                || {
+                    staged_blob
+                        .as_deref()
+                        .and_then(header_of)
+                        .map_or((batch_salt, None), |h| (h.salt, Some(h.file_id)))
+                },
+                |entry| (entry.salt, Some(entry.file_id)),
+            );
+            let settings = attrs.apply(rule_set.settings_for(path));
+            debug!("{path}: {settings}");
+            encrypt(
+                &key_cache,
+                key.as_bytes(),
+                &content,
+                salt,
+                file_id,
+                settings.zstd(),
+                &mut |bytes| observer.bytes_processed(Path::new(path), bytes),
+            )?
+        };
+        let oid = hash_object(repo, "blob", &ciphertext)?;
+        Ok((oid, bytes_in, ciphertext.len() as u64))
+    };
+
+    let mut index_info = String::new();
+    let mut files = Vec::with_capacity(targets.len());
+    for (path, attrs) in targets.iter().zip(&attrs) {
+        let file_start = Instant::now();
+        observer.file_started(Path::new(path));
+        let report = match stage_file(path, attrs) {
+            Ok((oid, bytes_in, bytes_out)) => {
+                let (mode, old_oid) = index.get(path).map_or_else(
+                    || (worktree_mode(&repo.path().join(path)), ""),
+                    |(m, o)| (m.as_str(), o.as_str()),
+                );
+                if oid == old_oid {
+                    FileReport::new(path, FileOutcome::Skipped, file_start)
+                } else {
+                    debug!("stage {path}: {old_oid} -> {oid}");
+                    let _ = write!(index_info, "{mode} {oid}\t{path}\0");
+                    FileReport::new(path, FileOutcome::Ok, file_start)
+                        .with_bytes(bytes_in, bytes_out)
+                }
+            }
+            Err(e) => FileReport::failed(path, e, file_start),
+        };
+        files.push(notify(observer, report));
+    }
+
+    if !index_info.is_empty() {
+        let mut command = repo.git_command();
+        command.args(["update-index", "--add", "-z", "--index-info"]);
+        run_with_input(command, index_info.as_bytes())?;
+    }
+    let report = BatchReport::new(FileAction::Stage, files, start);
+    observer.batch_finished(report.action, &report.summary());
+    Ok(report)
+}
+
+fn encrypt(
+    key_cache: &KeyCache,
+    key: &[u8],
+    plaintext: &[u8],
+    salt: [u8; SALT_LEN],
+    file_id: Option<[u8; FILE_ID_LEN]>,
+    zstd: Option<u8>,
+    progress: &mut dyn FnMut(u64),
+) -> Result<Vec<u8>> {
+    let derived_key = get_or_derive_key(key_cache, key, &salt)?;
+    let mut ciphertext = Vec::with_capacity(plaintext.len() + HEADER_LEN);
+    encrypt_into_with_progress(
+        &mut &plaintext[..],
+        &mut ciphertext,
+        &derived_key,
+        salt,
+        file_id,
+        zstd,
+        progress,
+    )?;
+    Ok(ciphertext)
+}
+
+fn is_encrypted(content: &[u8]) -> bool {
+    content.len() >= HEADER_LEN && content.starts_with(MAGIC) && is_encrypted_version(content[5])
+}
+
+fn header_of(blob: &[u8]) -> Option<FileHeader> {
+    let bytes = <&[u8; HEADER_LEN]>::try_from(blob.get(..HEADER_LEN)?).ok()?;
+    FileHeader::from_bytes(bytes).ok().copied()
+}
+
+/// Stage-0 index entries as `path -> (mode, oid)`.
+fn index_entries(repo: &Repo) -> Result<HashMap<String, (String, String)>> {
+    let output = repo.run_with_bytes(&["ls-files", "-s", "-z"])?;
+    Ok(output
+        .split(|&b| b == 0)
+        .filter_map(|record| {
+            // `<mode> <oid> <stage>\t<path>`
+            let record = String::from_utf8_lossy(record);
+            let (meta, path) = record.split_once('\t')?;
+            let mut meta = meta.split(' ');
+            let (mode, oid) = (meta.next()?, meta.next()?);
+            Some((path.to_owned(), (mode.to_owned(), oid.to_owned())))
+        })
+        .collect())
+}
+
+/// Git file mode for a path that is not in the index yet.
+fn worktree_mode(path: &Path) -> &'static str {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if std::fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o111 != 0) {
+            return "100755";
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    "100644"
+}
@@ -0,0 +1,299 @@
+//! Per-file encryption state report (`git-se status`).
+//!
+//! [`Repo::check`] only answers "encrypted or not" from the magic bytes.
+//! [`status`] goes further and classifies every file in the resolved crypt
+//! list, and also reports the two ways the crypt list can drift from the
+//! working tree: encrypted files that are no longer listed, and list entries
+//! that match nothing.
+
+use std::{
+    collections::HashSet,
+    fmt::Write as _,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use rayon::prelude::*;
+use serde::Serialize;
+
+use crate::{
+    config::{CryptMatcher, is_glob, literal_path},
+    crypt::{FileHeader, HEADER_LEN, KeyCache, MAGIC, cache_key, decrypt_into_cached, probe_key},
+    error::{Error, Result},
+    git::CatFile,
+    repo::Repo,
+    utils::{
+        is_file_encrypted, list_files, progress::Progress, resolve_target_files, style::Colorize,
+    },
+};
+
+/// Encryption state of a single file in the crypt list.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileState {
+    /// Valid GITSE file that opens with the configured key.
+    Encrypted,
+    /// Plaintext that matches the committed (index) version, or has no
+    /// encrypted counterpart to compare against.
+    Plaintext,
+    /// Plaintext whose content differs from the decrypted index version, i.e.
+    /// it was edited after `git-se d`.
+    Modified,
+    /// Valid GITSE header, but the first chunk does not open with the
+    /// configured key (wrong password, or a tampered first chunk).
+    WrongKey,
+    /// Starts with the GITSE magic, but the header is invalid or the body is
+    /// truncated.
+    Corrupt,
+}
+
+impl FileState {
+    /// Single-letter code used by `--porcelain`.
+    #[must_use]
+    pub const fn code(self) -> char {
+        match self {
+            Self::Encrypted => 'E',
+            Self::Plaintext => 'P',
+            Self::Modified => 'M',
+            Self::WrongKey => 'K',
+            Self::Corrupt => 'C',
+        }
+    }
+
+    const fn label(self) -> &'static str {
+        match self {
+            Self::Encrypted => "encrypted",
+            Self::Plaintext => "plaintext",
+            Self::Modified => "modified",
+            Self::WrongKey => "wrong key",
+            Self::Corrupt => "corrupt",
+        }
+    }
+}
+
+/// State of one file, keyed by its repo-relative path (`/`-separated).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileStatus {
+    pub path: String,
+    pub state: FileState,
+}
+
+/// Result of [`status`].
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StatusReport {
+    /// Files in the crypt list (or the given paths), sorted by path.
+    pub files: Vec<FileStatus>,
+    /// Encrypted files that are not covered by the crypt list.
+    pub orphaned: Vec<String>,
+    /// `crypt_list` entries that match no file.
+    pub unmatched: Vec<String>,
+}
+
+/// Classify every file in the crypt list (or only `paths`, if given).
+///
+/// Orphaned files and unmatched entries are only computed for a full status,
+/// i.e. when `paths` is empty. Without a configured key, encrypted files are
+/// classified from their header alone and plaintext files are never reported
+/// as [`FileState::Modified`].
+pub fn status(repo: &Repo, paths: &[PathBuf]) -> Result<StatusReport> {
+    let key = repo.get_key().ok();
+    let target_files = resolve_target_files(paths, &repo.conf)?;
+
+    let key_cache: KeyCache = DashMap::new();
+    let cat_file = Mutex::new(CatFile::spawn(repo)?);
+    let pb = Progress::new(target_files.len(), "Status");
+
+    let mut files = target_files
+        .par_iter()
+        .map(|f| {
+            let path = relative_key(f, repo.path());
+            let state = classify(
+                f,
+                &path,
+                key.as_deref().map(str::as_bytes),
+                &key_cache,
+                &cat_file,
+            )?;
+            pb.inc(1);
+            Ok(FileStatus { path, state })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    pb.finish_and_clear();
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+
+    let mut report = StatusReport {
+        files,
+        ..Default::default()
+    };
+    if !paths.is_empty() {
+        return Ok(report);
+    }
+
+    let listed: HashSet<&str> = report.files.iter().map(|f| f.path.as_str()).collect();
+    let all_files = list_files([""], repo.path());
+    let mut orphaned = all_files
+        .par_iter()
+        .filter_map(|f| {
+            let path = relative_key(f, repo.path());
+            (!listed.contains(path.as_str()) && is_file_encrypted(f).unwrap_or(false))
+                .then_some(path)
+        })
+        .collect::<Vec<_>>();
+    orphaned.sort();
+    report.orphaned = orphaned;
+
+    let all_paths: Vec<String> = all_files
+        .iter()
+        .map(|f| relative_key(f, repo.path()))
+        .collect();
+    for entry in &repo.conf.crypt_list {
+        let matched = if entry.starts_with('!') {
+            continue;
+        } else if is_glob(entry) {
+            let matcher = CryptMatcher::new(repo.path(), std::slice::from_ref(entry), &[])?;
+            all_paths.iter().any(|p| matcher.matches(p))
+        } else {
+            !list_files([literal_path(entry)], repo.path()).is_empty()
+        };
+        if !matched {
+            report.unmatched.push(entry.clone());
+        }
+    }
+
+    Ok(report)
+}
+
+fn relative_key(path: &Path, repo_path: &Path) -> String {
+    String::from_utf8_lossy(&cache_key(path, repo_path)).into_owned()
+}
+
+fn classify(
+    path: &Path,
+    relative: &str,
+    key: Option<&[u8]>,
+    key_cache: &KeyCache,
+    cat_file: &Mutex<CatFile>,
+) -> Result<FileState> {
+    let mut file = fs::File::open(path)?;
+    let mut head = Vec::with_capacity(HEADER_LEN);
+    (&mut file).take(HEADER_LEN as u64).read_to_end(&mut head)?;
+
+    if head.starts_with(MAGIC) {
+        let Ok(header_bytes) = <&[u8; HEADER_LEN]>::try_from(head.as_slice()) else {
+            return Ok(FileState::Corrupt);
+        };
+        if FileHeader::from_bytes(header_bytes).is_err() {
+            return Ok(FileState::Corrupt);
+        }
+        let Some(key) = key else {
+            return Ok(FileState::Encrypted);
+        };
+        return Ok(
+            match probe_key(&mut fs::File::open(path)?, key_cache, key) {
+                Ok(_) => FileState::Encrypted,
+                Err(Error::DecryptFailed(_)) => FileState::WrongKey,
+                Err(Error::Io(e)) => return Err(Error::Io(e)),
+                Err(_) => FileState::Corrupt,
+            },
+        );
+    }
+
+    // Plaintext: compare against the decrypted index version, if any.
+    let Some(key) = key else {
+        return Ok(FileState::Plaintext);
+    };
+    let Some(blob) = cat_file.lock().read(&format!(":{relative}"))? else {
+        return Ok(FileState::Plaintext);
+    };
+    if !blob.starts_with(MAGIC) {
+        return Ok(FileState::Plaintext);
+    }
+    let mut committed = Vec::new();
+    if decrypt_into_cached(&mut blob.as_slice(), &mut committed, key_cache, key).is_err() {
+        return Ok(FileState::Plaintext);
+    }
+    if fs::read(path)? == committed {
+        Ok(FileState::Plaintext)
+    } else {
+        Ok(FileState::Modified)
+    }
+}
+
+impl StatusReport {
+    /// Stable, uncoloured, one-line-per-item format for scripts: `<code>
+    /// <path>`, where `O` marks orphaned files and `U` unmatched entries.
+    #[must_use]
+    pub fn porcelain(&self) -> String {
+        let mut out = String::new();
+        for f in &self.files {
+            let _ = writeln!(out, "{} {}", f.state.code(), f.path);
+        }
+        for f in &self.orphaned {
+            let _ = writeln!(out, "O {f}");
+        }
+        for entry in &self.unmatched {
+            let _ = writeln!(out, "U {entry}");
+        }
+        out
+    }
+
+    /// Append the report of another repo. With a `submodule` path, its
+    /// paths are prefixed with it, so they are relative to this repo.
+    pub fn merge(&mut self, submodule: Option<&str>, other: Self) {
+        let prefix = |p: String| submodule.map_or_else(|| p.clone(), |s| format!("{s}/{p}"));
+        self.files
+            .extend(other.files.into_iter().map(|f| FileStatus {
+                path: prefix(f.path),
+                state: f.state,
+            }));
+        self.orphaned.extend(other.orphaned.into_iter().map(prefix));
+        self.unmatched
+            .extend(other.unmatched.into_iter().map(prefix));
+    }
+
+    /// Print a coloured, human-readable report to stdout, with paths
+    /// relative to [`Repo::cwd`].
+    pub fn print_human(&self, repo: &Repo) {
+        println!(
+            "\n{} {} {}",
+            "Encryption status".bold(),
+            format!("({} files)", self.files.len()).cyan(),
+            ":".dimmed()
+        );
+        for f in &self.files {
+            let label = format!("{:<10}", f.state.label());
+            let label = match f.state {
+                FileState::Encrypted => label.green(),
+                FileState::Plaintext => label.yellow(),
+                FileState::Modified => label.yellow().bold(),
+                FileState::WrongKey | FileState::Corrupt => label.red(),
+            };
+            println!("  {label} {}", repo.display_path(&f.path).display());
+        }
+
+        if !self.orphaned.is_empty() {
+            println!(
+                "\n{} {}:",
+                self.orphaned.len().to_string().yellow(),
+                "encrypted files are not in the crypt list".yellow()
+            );
+            for f in &self.orphaned {
+                println!("  - {}", repo.display_path(f).display());
+            }
+        }
+        if !self.unmatched.is_empty() {
+            println!(
+                "\n{} {}:",
+                self.unmatched.len().to_string().yellow(),
+                "crypt list entries match no file".yellow()
+            );
+            for entry in &self.unmatched {
+                println!("  - {entry}");
+            }
+        }
+        println!();
+    }
+}
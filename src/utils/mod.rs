@@ -1,24 +1,35 @@
-mod progress;
+pub(crate) mod progress;
 pub(crate) mod style;
 
 use std::{
+    collections::HashSet,
     fs,
     io::{Read, Write},
     path::{Path, PathBuf},
+    process::Command,
     sync::mpsc,
 };
 
-use ignore::{WalkBuilder, WalkState};
-pub use progress::Progress;
+use fuck_backslash::FuckBackslash;
+use ignore::{WalkBuilder, WalkState, overrides::Override};
+use log::{debug, warn};
+use pathdiff::diff_paths;
 use tempfile::NamedTempFile;
 use zeroize::Zeroizing;
 
 use crate::{
+    attributes::{check_crypt_attrs, crypt_attr_used},
+    config::{Config, FileSource},
     crypt::{HEADER_LEN, MAGIC, is_encrypted_version},
     error::{Error, Result},
-    utils::style::Colorize,
 };
 
+/// Progress bar handle, see [`progress::Progress`].
+#[deprecated(
+    note = "batch progress is reported to an `observer::Observer`; use `observer::ProgressObserver`"
+)]
+pub type Progress = progress::Progress;
+
 /// Format a byte array into a hex string
 #[allow(dead_code)]
 #[cfg(any(test, debug_assertions))]
@@ -43,24 +54,35 @@ pub fn atomic_write(path: &Path, data: &[u8]) -> Result<()> {
     Ok(())
 }
 
-/// Prompt the user for a password.
+/// Prompt the user for a password on the terminal, with echo disabled.
 ///
-/// Returns an empty-password error if the user enters only whitespace. The
-/// returned string is wrapped in [`Zeroizing`] so the plaintext is scrubbed
-/// from memory on drop.
+/// Returns [`Error::NoTerminal`] instead of waiting for input that can never
+/// come when stderr is not a terminal, and an empty-password error if the
+/// user enters only whitespace. The returned string is wrapped in
+/// [`Zeroizing`] so the plaintext is scrubbed from memory on drop.
 pub fn prompt_password(prompt: &str) -> Result<Zeroizing<String>> {
-    print!("{prompt}");
-    std::io::stdout().flush()?;
-    let mut password = String::new();
-    std::io::stdin().read_line(&mut password)?;
+    let term = console::Term::stderr();
+    if !term.is_term() {
+        return Err(Error::NoTerminal);
+    }
+    term.write_str(prompt)?;
+    let password = Zeroizing::new(term.read_secure_line()?);
+    trimmed_password(&password)
+}
+
+/// Read a password from the first line of `reader`, e.g. a pipe.
+pub fn read_password(mut reader: impl std::io::BufRead) -> Result<Zeroizing<String>> {
+    let mut password = Zeroizing::new(String::new());
+    reader.read_line(&mut password)?;
+    trimmed_password(&password)
+}
+
+fn trimmed_password(password: &str) -> Result<Zeroizing<String>> {
     let trimmed = password.trim();
     if trimmed.is_empty() {
         return Err(Error::EmptyPassword);
     }
-    // Scrub the raw input buffer too.
-    let result = Zeroizing::new(trimmed.to_string());
-    zeroize::Zeroize::zeroize(&mut password);
-    Ok(result)
+    Ok(Zeroizing::new(trimmed.to_string()))
 }
 
 /// If the given path is a file, return the file name. Otherwise, return the
@@ -69,8 +91,54 @@ pub fn list_files(
     paths: impl IntoIterator<Item = impl AsRef<Path>>,
     cwd: impl AsRef<Path>,
 ) -> Vec<PathBuf> {
-    let mut paths_iter = paths.into_iter();
+    walk(paths, cwd.as_ref(), None, true)
+}
+
+/// Like [`list_files`], but only keep files that `overrides` whitelists.
+pub fn list_matching_files(
+    paths: impl IntoIterator<Item = impl AsRef<Path>>,
+    cwd: impl AsRef<Path>,
+    overrides: &Override,
+) -> Vec<PathBuf> {
+    walk(paths, cwd.as_ref(), Some(overrides), true)
+}
+
+/// Files below `paths` (whitelisted by `overrides`, if given) that
+/// [`list_files`] skips because of `.gitignore` or other ignore rules.
+///
+/// In a git working tree, git lists them (`git ls-files --cached --others
+/// --ignored --exclude-standard`), so ignored build output is not walked
+/// here. Outside of one, the directory is walked without ignore rules.
+pub fn list_ignored_files(
+    paths: &[impl AsRef<Path>],
+    cwd: impl AsRef<Path>,
+    overrides: Option<&Override>,
+) -> Vec<PathBuf> {
     let cwd = cwd.as_ref();
+    if let Some(files) = ls_files(
+        paths,
+        cwd,
+        &["--cached", "--others", "--ignored", "--exclude-standard"],
+    ) {
+        return files
+            .into_iter()
+            .filter(|f| overrides.is_none_or(|o| o.matched(f, false).is_whitelist()))
+            .collect();
+    }
+    let listed: HashSet<PathBuf> = walk(paths, cwd, overrides, true).into_iter().collect();
+    walk(paths, cwd, overrides, false)
+        .into_iter()
+        .filter(|f| !listed.contains(f))
+        .collect()
+}
+
+fn walk(
+    paths: impl IntoIterator<Item = impl AsRef<Path>>,
+    cwd: &Path,
+    overrides: Option<&Override>,
+    respect_ignore: bool,
+) -> Vec<PathBuf> {
+    let mut paths_iter = paths.into_iter();
 
     let mut builder = if let Some(first_path) = paths_iter.next() {
         debug_assert!(first_path.as_ref().is_relative());
@@ -87,11 +155,13 @@ pub fn list_files(
     builder
         .current_dir(cwd)
         .hidden(false)
-        .git_ignore(true)
-        .ignore(true)
-        .git_global(true)
-        .git_exclude(true)
+        .parents(respect_ignore)
+        .git_ignore(respect_ignore)
+        .ignore(respect_ignore)
+        .git_global(respect_ignore)
+        .git_exclude(respect_ignore)
         .follow_links(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
         .threads(0);
 
     let parallel_walker: ignore::WalkParallel = builder.build_parallel();
@@ -104,6 +174,7 @@ pub fn list_files(
             if let Ok(entry) = result
                 && let Some(file_type) = entry.file_type()
                 && file_type.is_file()
+                && overrides.is_none_or(|o| o.matched(entry.path(), false).is_whitelist())
             {
                 let _ = tx.send(entry.into_path());
             }
@@ -112,61 +183,11 @@ pub fn list_files(
     });
 
     drop(tx);
-    rx.into_iter().collect()
-}
-
-// --- Reporting & Progress Helpers ---
-
-/// Maximum number of files to display individually before collapsing.
-const REPORT_LIST_LIMIT: usize = 10;
-
-/// Print a pre-operation report listing the target files and total count.
-/// If the list exceeds `REPORT_LIST_LIMIT`, show the first few and summarize
-/// the rest as "... and N more files".
-pub fn print_pre_report(action: &str, files: &[impl AsRef<Path>], repo_path: &Path) {
-    let count = files.len();
-    println!(
-        "\n{} {} {}",
-        action.bold(),
-        format!("({count} files)").cyan(),
-        ":".dimmed()
-    );
-
-    for f in &files[..count.min(REPORT_LIST_LIMIT)] {
-        let relative =
-            pathdiff::diff_paths(f.as_ref(), repo_path).unwrap_or_else(|| f.as_ref().to_path_buf());
-        println!("  {}", relative.display());
-    }
-
-    if count > REPORT_LIST_LIMIT {
-        let remaining = count - REPORT_LIST_LIMIT;
-        println!("  {}", format!("... and {remaining} more files").dimmed());
-    }
-    println!();
-}
-
-/// Print a post-operation summary report.
-pub fn print_post_report(action: &str, total: usize, skipped: usize, failed: usize) {
-    let succeeded = total - skipped - failed;
-    let label = format!("{action} complete").bold();
-
-    if failed > 0 {
-        println!(
-            "\n{}: {} succeeded, {} skipped, {} {}",
-            label,
-            succeeded.to_string().green(),
-            skipped.to_string().yellow(),
-            failed.to_string().red(),
-            "failed".red(),
-        );
-    } else {
-        println!(
-            "\n{}: {} succeeded, {} skipped",
-            label,
-            succeeded.to_string().green(),
-            skipped.to_string().yellow(),
-        );
-    }
+    let mut files: Vec<PathBuf> = rx.into_iter().collect();
+    // Overlapping roots (e.g. `a` and `a/b`) yield the same file twice.
+    files.sort_unstable();
+    files.dedup();
+    files
 }
 
 /// Check whether a single file has a valid GITSE encrypted header.
@@ -182,19 +203,126 @@ pub fn is_file_encrypted(path: &Path) -> Result<bool> {
     Ok(&header_bytes[0..5] == MAGIC && is_encrypted_version(header_bytes[5]))
 }
 
-/// Resolve the target file list for the repo. If `paths` is empty, use the
-/// crypt list from the config; otherwise, use the given paths.
-#[must_use]
-pub fn resolve_target_files(
-    paths: &[PathBuf],
-    crypt_list: &[String],
-    repo_path: &Path,
-) -> Vec<PathBuf> {
-    if paths.is_empty() {
-        list_files(crypt_list.iter(), repo_path)
+/// The files below `paths` that git tracks (`git ls-files`), plus untracked
+/// files that are not ignored if `untracked` is set. Paths that no longer
+/// exist in the working tree are left out.
+///
+/// Returns `None` if `cwd` is not inside a git working tree.
+pub fn list_index_files(
+    paths: &[impl AsRef<Path>],
+    cwd: impl AsRef<Path>,
+    untracked: bool,
+) -> Option<Vec<PathBuf>> {
+    let args: &[&str] = if untracked {
+        &["--cached", "--others", "--exclude-standard"]
     } else {
-        list_files(paths, repo_path)
+        &["--cached"]
+    };
+    ls_files(paths, cwd.as_ref(), args)
+}
+
+/// The existing files below `paths` that `git ls-files <args>` lists, or
+/// `None` if `cwd` is not inside a git working tree.
+fn ls_files(paths: &[impl AsRef<Path>], cwd: &Path, args: &[&str]) -> Option<Vec<PathBuf>> {
+    if paths.is_empty() {
+        return Some(Vec::new());
+    }
+    let mut command = Command::new("git");
+    command.current_dir(cwd).args(["ls-files", "-z"]).args(args);
+    command.arg("--");
+    for path in paths {
+        let path = path.as_ref();
+        let path = path.strip_prefix(cwd).unwrap_or(path).to_string_lossy();
+        let path = path.trim_start_matches("./").trim_end_matches('/');
+        if path.is_empty() || path == "." {
+            command.arg(":/");
+        } else {
+            command.arg(format!(":(top,literal){path}"));
+        }
+    }
+    let output = command.output().ok()?;
+    if !output.status.success() {
+        debug!(
+            "git ls-files failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return None;
+    }
+    let mut files = output
+        .stdout
+        .split(|&b| b == 0)
+        .filter(|p| !p.is_empty())
+        .map(|p| cwd.join(String::from_utf8_lossy(p).as_ref()))
+        .filter(|p| p.is_file())
+        .collect::<Vec<_>>();
+    files.sort_unstable();
+    files.dedup();
+    Some(files)
+}
+
+/// Resolve the target file list for the repo. If `paths` is empty, use the
+/// files that the crypt list (and excludes) or the `crypt` attribute select,
+/// see [`CryptAttrs::selects`](crate::attributes::CryptAttrs::selects);
+/// otherwise, use the given paths.
+///
+/// Files are enumerated as configured by [`Config::file_source`], below the
+/// crypt list roots only. The rest of the tree is listed with `git ls-files`
+/// when an attributes file uses `crypt`, see
+/// [`crypt_attr_used`](crate::attributes::crypt_attr_used).
+pub fn resolve_target_files(paths: &[PathBuf], conf: &Config) -> Result<Vec<PathBuf>> {
+    if !paths.is_empty() {
+        return Ok(enumerate_files(paths, conf));
+    }
+    let matcher = conf.matcher()?;
+    let roots = matcher
+        .roots()
+        .iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>();
+    let mut files = enumerate_files(&roots, conf);
+    let relative = |f: &Path| {
+        diff_paths(f, &conf.repo_path)
+            .unwrap_or_else(|| f.to_path_buf())
+            .fuck_backslash()
+            .to_string_lossy()
+            .into_owned()
+    };
+    if !crypt_attr_used(&conf.repo_path) {
+        files.retain(|f| matcher.matches(&relative(f)));
+        return Ok(files);
+    }
+
+    // The `crypt` attribute may select files anywhere, so ask git for the
+    // rest of the tree and check the attributes of all of them.
+    let untracked = conf.file_source != FileSource::Index;
+    if let Some(candidates) = list_index_files(&[""], &conf.repo_path, untracked) {
+        files.extend(candidates);
+        files.sort_unstable();
+        files.dedup();
+    }
+    let relative = files.iter().map(|f| relative(f)).collect::<Vec<_>>();
+    let attrs = check_crypt_attrs(&conf.repo_path, &relative, false)?;
+    Ok(files
+        .into_iter()
+        .zip(relative.iter().zip(attrs))
+        .filter(|(_, (rel, attrs))| attrs.selects(rel, matcher.matches(rel)))
+        .map(|(f, _)| f)
+        .collect())
+}
+
+/// The files below `paths`, from the git index or by walking the directory.
+fn enumerate_files(paths: &[PathBuf], conf: &Config) -> Vec<PathBuf> {
+    if conf.file_source != FileSource::Walk {
+        let untracked = conf.file_source == FileSource::IndexAndUntracked;
+        if let Some(files) = list_index_files(paths, &conf.repo_path, untracked) {
+            return files;
+        }
+        warn!(
+            "{} is not a git working tree, walking the directory instead",
+            conf.repo_path.display()
+        );
     }
+    list_files(paths, &conf.repo_path)
 }
 
 #[cfg(test)]
@@ -204,6 +332,41 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_read_password() {
+        let password = read_password(&b"  secret pass \nignored\n"[..]).unwrap();
+        assert!(password.as_str() == "secret pass");
+        assert!(matches!(
+            read_password(&b" \n"[..]),
+            Err(Error::EmptyPassword)
+        ));
+        assert!(matches!(read_password(&b""[..]), Err(Error::EmptyPassword)));
+    }
+
+    #[test]
+    fn test_list_ignored_files() -> Result<()> {
+        let dir = tempfile::TempDir::new()?;
+        let root = dir.path();
+        Command::new("git").arg("init").current_dir(root).output()?;
+        fs::create_dir(root.join("target"))?;
+        fs::write(root.join(".gitignore"), "target/\n*.log\n")?;
+        fs::write(root.join("a.env"), "a")?;
+        fs::write(root.join("c.log"), "c")?;
+        fs::write(root.join("target/b.env"), "b")?;
+        let overrides = ignore::overrides::OverrideBuilder::new(root)
+            .add("*.env")
+            .and_then(|b| b.build())
+            .unwrap();
+
+        assert!(
+            list_ignored_files(&[""], root, None)
+                == [root.join("c.log"), root.join("target/b.env")]
+        );
+        assert!(list_ignored_files(&[""], root, Some(&overrides)) == [root.join("target/b.env")]);
+        assert!(list_ignored_files(&["target"], root, None) == [root.join("target/b.env")]);
+        Ok(())
+    }
+
     #[test]
     fn test_list_files() {
         let paths = vec!["docs", ".gitignore", "src", "some_thing_not_exist"]
@@ -1,15 +1,17 @@
-//! Progress reporting abstraction.
+//! Progress bar of the internal scans.
 //!
 //! When the `progress` feature is enabled, [`Progress`] wraps an
 //! `indicatif::ProgressBar`. When disabled, it is a zero-sized type and
 //! progress is reported only via `log::info!` at start/finish. Either way
-//! the call-site API is identical, so `crypt::encrypt_repo` and friends do
-//! not need any feature gates.
+//! the call-site API is identical, so `status` and the history commands do
+//! not need any feature gates. The batch operations report to an
+//! [`Observer`](crate::observer::Observer) instead, and
+//! [`ProgressObserver`](crate::observer::ProgressObserver) draws this bar.
 
 #[cfg(feature = "progress")]
 use indicatif::{ProgressBar, ProgressStyle};
 
-/// Monomorphic progress handle used by repo-wide operations.
+/// Monomorphic progress handle used by the scans.
 ///
 /// `Send + Sync` so it can be shared (by reference) across rayon workers.
 pub struct Progress {
@@ -31,6 +33,10 @@ impl Progress {
 
     /// Advance the progress by `n` finished items.
     #[inline]
+    #[cfg_attr(
+        not(feature = "progress"),
+        allow(clippy::unused_self, clippy::missing_const_for_fn)
+    )]
     pub fn inc(&self, n: u64) {
         #[cfg(feature = "progress")]
         self.inner.inc(n);
@@ -41,6 +47,10 @@ impl Progress {
 
     /// Finalize and clear the progress bar (if any).
     #[inline]
+    #[cfg_attr(
+        not(feature = "progress"),
+        allow(clippy::unused_self, clippy::missing_const_for_fn)
+    )]
     pub fn finish_and_clear(&self) {
         #[cfg(feature = "progress")]
         self.inner.finish_and_clear();
@@ -6,7 +6,18 @@ use std::{
 
 use anyhow::{Context as _, Ok};
 use colored::Colorize;
-use git_simple_encrypt::{Cli, FileHeader, SetField, SubCommand};
+use git_simple_encrypt::{
+    AttributesAction, Cli, Error, FileHeader, SetField, SubCommand,
+    config::FileSource,
+    crypt::{HEADER_LEN, MAGIC, NONCE_LEN, decrypt_repo, encrypt_repo},
+    history::{self, BlobState},
+    observer::{NoopObserver, Observer},
+    output::OutputFormat,
+    repo::Repo,
+    report::{FileAction, FileOutcome, FileReport, Summary},
+    show, stage,
+    status::{FileState, status},
+};
 use rand::prelude::*;
 use tap::Tap;
 use tempfile::TempDir;
@@ -46,10 +57,32 @@ fn run(cmd: SubCommand, pwd: impl Into<PathBuf>) -> anyhow::Result<()> {
     git_simple_encrypt::run(Cli {
         command: cmd,
         repo: pwd,
+        format: OutputFormat::Human,
+        verbose: false,
     })?;
     Ok(())
 }
 
+/// Run `cmd` with `--recurse-submodules`.
+fn run_recursive(mut cmd: SubCommand, pwd: impl Into<PathBuf>) -> anyhow::Result<()> {
+    match &mut cmd {
+        SubCommand::Encrypt {
+            recurse_submodules, ..
+        }
+        | SubCommand::Decrypt {
+            recurse_submodules, ..
+        }
+        | SubCommand::Check {
+            recurse_submodules, ..
+        }
+        | SubCommand::Status {
+            recurse_submodules, ..
+        } => *recurse_submodules = true,
+        _ => unreachable!("{cmd:?} does not take --recurse-submodules"),
+    }
+    run(cmd, pwd)
+}
+
 trait PathExt {
     fn is_encrypted(&self) -> bool;
     fn is_compressed(&self) -> bool;
@@ -94,12 +127,19 @@ fn test_basic() -> anyhow::Result<()> {
     run(
         SubCommand::Add {
             paths: ["t1.txt", "t2.txt", "dir"].map(PathBuf::from).to_vec(),
+            dry_run: false,
         },
         temp_dir,
     )?;
 
     // Encrypt (added files)
-    run(SubCommand::Encrypt { paths: vec![] }, temp_dir)?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
 
     // Test
     temp_dir.read_dir()?.for_each(|x| println!("{:?}", x));
@@ -110,7 +150,13 @@ fn test_basic() -> anyhow::Result<()> {
     assert!(temp_dir.join("dir/t4.txt").is_encrypted());
 
     // Decrypt
-    run(SubCommand::Decrypt { paths: vec![] }, temp_dir)?;
+    run(
+        SubCommand::Decrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
     println!("{}", "After Decrypt".green());
 
     // Test decrypt result
@@ -139,433 +185,2312 @@ fn test_basic() -> anyhow::Result<()> {
 }
 
 #[test]
-fn test_encrypt_multiple_times() -> anyhow::Result<()> {
+fn test_glob_crypt_list() -> anyhow::Result<()> {
     let pwd = test_init();
     let temp_dir = pwd.path();
-
-    std::fs::create_dir(temp_dir.join("dir"))?;
-    std::fs::write(temp_dir.join("t1.txt"), "Hello, world!")?;
-    std::fs::write(temp_dir.join("dir/t4.txt"), "dir test")?;
-
-    // Add file
+    for (path, content) in [
+        ("a.env", "a"),
+        ("deep/dir/b.env", "b"),
+        ("secrets/key.pem", "key"),
+        ("secrets/README.md", "readme"),
+        ("example.env", "example"),
+        ("plain.txt", "plain"),
+    ] {
+        let path = temp_dir.join(path);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, content)?;
+    }
     run(
         SubCommand::Add {
-            paths: ["t1.txt", "dir"].map(PathBuf::from).to_vec(),
+            paths: vec![
+                "*.env".into(),
+                "secrets".into(),
+                "!secrets/README.md".into(),
+            ],
+            dry_run: false,
         },
         temp_dir,
     )?;
+    // Excludes live in the config file only.
+    let config_path = temp_dir.join("git_simple_encrypt.toml");
+    let config = fs::read_to_string(&config_path)?;
+    fs::write(
+        &config_path,
+        format!("exclude = [\"example.env\"]\n{config}"),
+    )?;
 
-    // Encrypt multiple times
-    run(SubCommand::Encrypt { paths: vec![] }, temp_dir)?;
-    run(SubCommand::Encrypt { paths: vec![] }, temp_dir)?;
-    run(SubCommand::Encrypt { paths: vec![] }, temp_dir)?;
-
-    // Test
-    temp_dir.read_dir()?.for_each(|x| println!("{:?}", x));
-    temp_dir
-        .join("dir")
-        .read_dir()?
-        .for_each(|x| println!("{:?}", x));
-    assert!(temp_dir.join("t1.txt").is_encrypted());
-    assert!(temp_dir.join("dir/t4.txt").is_encrypted());
-
-    // Decrypt
-    run(SubCommand::Decrypt { paths: vec![] }, temp_dir)?;
-    println!("{}", "After Decrypt".green());
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    assert!(temp_dir.join("a.env").is_encrypted());
+    assert!(temp_dir.join("deep/dir/b.env").is_encrypted());
+    assert!(temp_dir.join("secrets/key.pem").is_encrypted());
+    assert!(temp_dir.join("secrets/README.md").is_not_encrypted());
+    assert!(temp_dir.join("example.env").is_not_encrypted());
+    assert!(temp_dir.join("plain.txt").is_not_encrypted());
+
+    // New files matching a glob are picked up without `git-se add`.
+    fs::write(temp_dir.join("deep/c.env"), "c")?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    assert!(temp_dir.join("deep/c.env").is_encrypted());
 
-    // Test
+    run(
+        SubCommand::Decrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    assert_eq!(fs::read_to_string(temp_dir.join("deep/dir/b.env"))?, "b");
+    Ok(())
+}
 
-    for entry in temp_dir.read_dir()? {
-        println!("{:?}", entry?);
-    }
-    assert!(temp_dir.join("t1.txt").is_not_encrypted());
-    assert!(temp_dir.join("dir/t4.txt").is_not_encrypted());
-    assert_eq!(
-        std::fs::read_to_string(temp_dir.join("t1.txt"))?,
-        "Hello, world!"
-    );
+#[test]
+fn test_old_literal_entry_with_brackets() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+    fs::write(temp_dir.join("config[prod].toml"), "prod")?;
+    fs::write(temp_dir.join("configp.toml"), "p")?;
+    // Lists written before globs were supported hold such paths unescaped.
+    let config_path = temp_dir.join("git_simple_encrypt.toml");
+    let config = fs::read_to_string(&config_path)?;
+    fs::write(
+        &config_path,
+        config.replace("crypt_list = []", "crypt_list = [\"config[prod].toml\"]"),
+    )?;
     assert_eq!(
-        std::fs::read_to_string(temp_dir.join("dir/t4.txt"))?,
-        "dir test"
+        Repo::open(temp_dir)?.conf.crypt_list,
+        ["config\\[prod].toml"]
     );
 
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    assert!(temp_dir.join("config[prod].toml").is_encrypted());
+    assert!(temp_dir.join("configp.toml").is_not_encrypted());
     Ok(())
 }
 
 #[test]
-#[ignore = "This test takes too long to run, and it's not necessary to run it every time. You can run it manually if you want."]
-fn test_many_files() -> anyhow::Result<()> {
+fn test_rules() -> anyhow::Result<()> {
     let pwd = test_init();
     let temp_dir = pwd.path();
-
-    let dir = temp_dir.join("dir");
-    std::fs::create_dir(&dir)?;
-    let files = (1..2000)
-        .map(|i| {
-            dir.join(format!("file{}.txt", i))
-                .tap(|f| std::fs::write(f, "Hello").unwrap())
-        })
-        .collect::<Vec<PathBuf>>();
-
-    // Add file
+    fs::create_dir_all(temp_dir.join("media"))?;
+    let text = "compressible ".repeat(1000);
+    fs::write(temp_dir.join("media/a.png"), &text)?;
+    fs::write(temp_dir.join("b.txt"), &text)?;
     run(
         SubCommand::Add {
-            paths: vec!["dir".into()],
+            paths: vec!["media".into(), "b.txt".into()],
+            dry_run: false,
         },
         temp_dir,
     )?;
+    let config_path = temp_dir.join("git_simple_encrypt.toml");
+    let config = fs::read_to_string(&config_path)?;
+    fs::write(
+        &config_path,
+        format!("{config}\n[[rules]]\npaths = [\"media/\"]\nuse_zstd = false\n"),
+    )?;
 
-    // Encrypt
-    run(SubCommand::Encrypt { paths: vec![] }, temp_dir)?;
-    // Decrypt
-    run(SubCommand::Decrypt { paths: vec![] }, temp_dir)?;
-
-    // Test
-    for _ in 1..10 {
-        let file_name = files.choose(&mut rand::rng()).unwrap();
-        println!("Testing file: {}", file_name.display());
-        assert_eq!(std::fs::read_to_string(file_name)?, "Hello");
-    }
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    assert!(temp_dir.join("media/a.png").is_encrypted());
+    assert!(!temp_dir.join("media/a.png").is_compressed());
+    assert!(temp_dir.join("b.txt").is_compressed());
 
+    run(
+        SubCommand::Decrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    assert_eq!(fs::read_to_string(temp_dir.join("media/a.png"))?, text);
     Ok(())
 }
 
 #[test]
-fn test_large_file_encrypt_decrypt() -> anyhow::Result<()> {
-    const FILE_SIZE: usize = 5 * 1024 * 1024; // 5 MB
+fn test_rm() -> anyhow::Result<()> {
     let pwd = test_init();
     let temp_dir = pwd.path();
-
-    let mut rng = rand::rngs::SmallRng::from_seed([42; 32]);
-    let original_data: Vec<u8> = (0..FILE_SIZE).map(|_| rng.random::<u8>()).collect();
-
-    let file_path = temp_dir.join("large.bin");
-    std::fs::write(&file_path, &original_data)?;
-
+    fs::create_dir_all(temp_dir.join("secrets"))?;
+    fs::write(temp_dir.join("secrets/key.pem"), "key")?;
+    fs::write(temp_dir.join("a.txt"), "a")?;
     run(
         SubCommand::Add {
-            paths: vec![file_path.clone()],
+            paths: vec!["secrets/".into(), "./a.txt".into()],
+            dry_run: false,
         },
         temp_dir,
     )?;
-    run(SubCommand::Encrypt { paths: vec![] }, temp_dir)?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    let rm = |paths: Vec<PathBuf>, decrypt: bool| run(SubCommand::Rm { paths, decrypt }, temp_dir);
+
+    // A path inside a directory entry names that entry.
+    let err = rm(vec!["secrets/key.pem".into()], false).unwrap_err();
+    assert!(err.to_string().contains("`secrets`"), "{err}");
+    assert!(rm(vec!["b.txt".into()], false).is_err());
+
+    // Any path form finds the entry; `--decrypt` restores the plaintext.
+    rm(vec![temp_dir.join("secrets")], true)?;
+    assert_eq!(fs::read_to_string(temp_dir.join("secrets/key.pem"))?, "key");
+    assert_eq!(Repo::open(temp_dir)?.conf.crypt_list, ["a.txt"]);
+
+    // Without `--decrypt` the file stays encrypted.
+    rm(vec!["a.txt".into()], false)?;
+    assert!(temp_dir.join("a.txt").is_encrypted());
+    assert!(Repo::open(temp_dir)?.conf.crypt_list.is_empty());
+    Ok(())
+}
 
-    assert!(file_path.is_encrypted());
-    run(SubCommand::Decrypt { paths: vec![] }, temp_dir)?;
+#[test]
+fn test_add_dedupe_and_dry_run() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+    fs::create_dir_all(temp_dir.join("secrets/sub"))?;
+    fs::write(temp_dir.join("secrets/sub/key.pem"), "key")?;
+    fs::write(temp_dir.join("secrets/local.env"), "ignored")?;
+    fs::write(temp_dir.join(".gitignore"), "*.env\n")?;
+    let add =
+        |paths: Vec<PathBuf>, dry_run: bool| run(SubCommand::Add { paths, dry_run }, temp_dir);
+
+    // A dry run leaves the list alone.
+    add(vec!["secrets".into()], true)?;
+    assert!(Repo::open(temp_dir)?.conf.crypt_list.is_empty());
+    let conf = Repo::open(temp_dir)?.conf;
+    assert_eq!(
+        conf.resolve_added_files(&["secrets"])?,
+        [PathBuf::from("secrets/sub/key.pem")]
+    );
 
-    let decrypted_data = std::fs::read(&file_path)?;
-    assert_eq!(decrypted_data, original_data);
-    assert!(file_path.is_not_encrypted());
+    // The child is replaced by its parent, and covered paths are skipped.
+    add(vec!["secrets/sub/key.pem".into()], false)?;
+    add(vec!["secrets/".into(), "./secrets/sub".into()], false)?;
+    assert_eq!(Repo::open(temp_dir)?.conf.crypt_list, ["secrets"]);
 
+    // The ignored file is skipped by encryption.
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    assert!(temp_dir.join("secrets/sub/key.pem").is_encrypted());
+    assert_eq!(
+        fs::read_to_string(temp_dir.join("secrets/local.env"))?,
+        "ignored"
+    );
     Ok(())
 }
 
 #[test]
-fn test_partial_decrypt() -> anyhow::Result<()> {
+fn test_file_source_index() -> anyhow::Result<()> {
     let pwd = test_init();
     let temp_dir = pwd.path();
-
-    std::fs::create_dir(temp_dir.join("dir"))?;
-    std::fs::write(temp_dir.join("t1.txt"), "Hello, world!")?;
-    std::fs::write(temp_dir.join("dir/t4.txt"), "dir test")?;
-
-    // Add file
+    fs::create_dir_all(temp_dir.join("secrets"))?;
+    fs::write(temp_dir.join("secrets/tracked.env"), "tracked")?;
+    fs::write(temp_dir.join("secrets/untracked.txt"), "untracked")?;
+    exec("git add secrets/tracked.env", temp_dir)?;
+    // Tracked even though it matches `.gitignore` from now on.
+    fs::write(temp_dir.join(".gitignore"), "*.env\n")?;
     run(
         SubCommand::Add {
-            paths: ["t1.txt", "dir"].map(PathBuf::from).to_vec(),
+            paths: vec!["secrets".into()],
+            dry_run: false,
         },
         temp_dir,
     )?;
+    let set = |value| {
+        run(
+            SubCommand::Set {
+                field: SetField::FileSource { value },
+            },
+            temp_dir,
+        )
+    };
 
-    // Encrypt
-    run(SubCommand::Encrypt { paths: vec![] }, temp_dir)?;
+    set(FileSource::Index)?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    assert!(temp_dir.join("secrets/tracked.env").is_encrypted());
+    assert!(!temp_dir.join("secrets/untracked.txt").is_encrypted());
 
-    // Partial decrypt
+    set(FileSource::IndexAndUntracked)?;
     run(
-        SubCommand::Decrypt {
-            paths: vec!["dir".into()],
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
         },
         temp_dir,
     )?;
+    assert!(temp_dir.join("secrets/untracked.txt").is_encrypted());
+    Ok(())
+}
 
-    // Test
-    for entry in temp_dir.read_dir()? {
-        println!("{:?}", entry?);
-    }
-    assert!(temp_dir.join("t1.txt").is_encrypted());
-    assert!(temp_dir.join("dir/t4.txt").exists());
+#[test]
+fn test_crypt_attribute() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+    fs::create_dir_all(temp_dir.join("secrets/nested"))?;
+    fs::write(temp_dir.join("secrets/a.txt"), "a")?;
+    fs::write(temp_dir.join("secrets/public.txt"), "public")?;
+    fs::write(temp_dir.join("secrets/nested/raw.bin"), "raw")?;
+    fs::write(temp_dir.join("listed.txt"), "listed")?;
+    fs::write(temp_dir.join(".gitattributes"), "secrets/** crypt\n")?;
+    // Per-directory attributes files work as in git.
+    fs::write(
+        temp_dir.join("secrets/.gitattributes"),
+        "public.txt -crypt\nnested/** -crypt-zstd\n",
+    )?;
+    run(
+        SubCommand::Add {
+            paths: vec!["listed.txt".into()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
 
-    // Reencrypt
-    run(SubCommand::Encrypt { paths: vec![] }, temp_dir)?;
+    assert!(temp_dir.join("listed.txt").is_encrypted());
+    assert!(temp_dir.join("secrets/a.txt").is_compressed());
+    assert!(!temp_dir.join("secrets/public.txt").is_encrypted());
+    let raw = temp_dir.join("secrets/nested/raw.bin");
+    assert!(raw.is_encrypted());
+    assert!(!raw.is_compressed());
 
-    // Partial decrypt
     run(
         SubCommand::Decrypt {
-            paths: vec!["t1.txt".into()],
+            paths: vec![],
+            recurse_submodules: false,
         },
         temp_dir,
     )?;
-
-    // Test
-    for entry in temp_dir.read_dir()? {
-        println!("{:?}", entry?);
-    }
-    assert!(temp_dir.join("t1.txt").exists());
-    assert!(temp_dir.join("dir/t4.txt").is_encrypted());
-
+    assert_eq!(fs::read_to_string(temp_dir.join("secrets/a.txt"))?, "a");
+    assert_eq!(fs::read_to_string(&raw)?, "raw");
     Ok(())
 }
 
 #[test]
-fn test_tampered_encrypted_file_fails_aad() -> anyhow::Result<()> {
+fn test_attributes_block() -> anyhow::Result<()> {
     let pwd = test_init();
     let temp_dir = pwd.path();
-
-    let file_path = temp_dir.join("secret.txt");
-    let original_content = b"Hello, this is a secret message that must be authenticated!";
-    std::fs::write(&file_path, original_content)?;
-
+    let attributes_path = temp_dir.join(".gitattributes");
+    fs::write(&attributes_path, "* text=auto eol=crlf\n")?;
+    fs::write(temp_dir.join("a.txt"), "line 1\nline 2\n".repeat(100))?;
+    fs::write(temp_dir.join("b.txt"), "b")?;
     run(
         SubCommand::Add {
-            paths: vec![file_path.clone()],
+            paths: vec!["a.txt".into(), "b.txt".into()],
+            dry_run: false,
         },
         temp_dir,
     )?;
-    run(SubCommand::Encrypt { paths: vec![] }, temp_dir)?;
-
-    assert!(file_path.is_encrypted());
-    let mut encrypted_data = std::fs::read(&file_path)?;
-    assert!(!encrypted_data.is_empty());
-
-    // 篡改：翻转中间的一个字节
-    let mid = encrypted_data.len() / 2;
-    encrypted_data[mid] ^= 0xFF;
-
-    // 写回篡改后的数据
-    std::fs::write(&file_path, &encrypted_data)?;
-
-    // 尝试解密，应该失败（AAD 校验不通过）
-    let decrypt_result = run(SubCommand::Decrypt { paths: vec![] }, temp_dir);
-    dbg!(&decrypt_result);
-    assert!(decrypt_result.is_err());
-    // 可选：验证文件仍然处于加密状态（因为解密失败，文件未被修改）
-    assert!(file_path.is_encrypted());
+    let attributes = fs::read_to_string(&attributes_path)?;
+    assert!(attributes.starts_with("* text=auto eol=crlf\n"));
+    assert!(
+        attributes.contains("/a.txt -text -diff -merge\n"),
+        "{attributes}"
+    );
 
-    // 另一种篡改方式：截断文件末尾 10 个字节
-    let mut encrypted_data2 = std::fs::read(&file_path)?;
-    encrypted_data2.truncate(encrypted_data2.len().saturating_sub(10));
-    std::fs::write(&file_path, &encrypted_data2)?;
+    // The ciphertext is stored byte for byte despite `eol=crlf`.
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    exec("git add -A", temp_dir)?;
+    let blob = exec("git cat-file blob :a.txt", temp_dir)?.stdout;
+    assert_eq!(blob, fs::read(temp_dir.join("a.txt"))?);
 
-    let decrypt_result2 = run(SubCommand::Decrypt { paths: vec![] }, temp_dir);
-    dbg!(&decrypt_result);
-    assert!(decrypt_result2.is_err());
+    run(
+        SubCommand::Rm {
+            paths: vec!["b.txt".into()],
+            decrypt: true,
+        },
+        temp_dir,
+    )?;
+    let attributes = fs::read_to_string(&attributes_path)?;
+    assert!(!attributes.contains("/b.txt"), "{attributes}");
 
+    fs::write(&attributes_path, "* text=auto eol=crlf\n")?;
+    run(
+        SubCommand::Attributes {
+            action: AttributesAction::Sync,
+        },
+        temp_dir,
+    )?;
+    let attributes = fs::read_to_string(&attributes_path)?;
+    assert!(
+        attributes.contains("/a.txt -text -diff -merge\n"),
+        "{attributes}"
+    );
     Ok(())
 }
 
 #[test]
-fn test_deterministic_reencryption() -> anyhow::Result<()> {
+fn test_subdirectory() -> anyhow::Result<()> {
     let pwd = test_init();
     let temp_dir = pwd.path();
+    let api = temp_dir.join("services/api");
+    fs::create_dir_all(&api)?;
+    fs::write(api.join("a.env"), "a")?;
+    fs::write(temp_dir.join("root.env"), "root")?;
 
-    std::fs::create_dir(temp_dir.join("dir"))?;
-    std::fs::write(temp_dir.join("t1.txt"), "Hello, world!")?;
-    std::fs::write(temp_dir.join("t2.txt"), "6".repeat(100))?;
-    std::fs::write(temp_dir.join("dir/t3.txt"), "nested file")?;
-
-    // Add files
+    // Paths are relative to the directory git-se runs in.
     run(
         SubCommand::Add {
-            paths: ["t1.txt", "t2.txt", "dir"].map(PathBuf::from).to_vec(),
+            paths: vec!["a.env".into(), "../../root.env".into()],
+            dry_run: false,
         },
-        temp_dir,
+        &api,
     )?;
+    let repo = Repo::open(&api)?;
+    assert_eq!(repo.path(), temp_dir);
+    assert_eq!(repo.conf.crypt_list, ["services/api/a.env", "root.env"]);
+    assert_eq!(repo.display_path("root.env"), Path::new("../../root.env"));
 
-    // ---- First encrypt ----
-    run(SubCommand::Encrypt { paths: vec![] }, temp_dir)?;
-    assert!(temp_dir.join("t1.txt").is_encrypted());
-    assert!(temp_dir.join("t2.txt").is_compressed());
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        &api,
+    )?;
+    assert!(api.join("a.env").is_encrypted());
+    assert!(temp_dir.join("root.env").is_encrypted());
+    run(
+        SubCommand::Decrypt {
+            paths: vec!["a.env".into()],
+            recurse_submodules: false,
+        },
+        &api,
+    )?;
+    assert_eq!(fs::read_to_string(api.join("a.env"))?, "a");
+    assert!(temp_dir.join("root.env").is_encrypted());
+    Ok(())
+}
+
+#[test]
+fn test_worktree() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+    fs::write(temp_dir.join("a.txt"), "a")?;
+    run(
+        SubCommand::Add {
+            paths: vec!["a.txt".into()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    exec("git config user.name t", temp_dir)?;
+    exec("git config user.email t@t", temp_dir)?;
+    exec("git add -A", temp_dir)?;
+    exec("git commit -m init", temp_dir)?;
+
+    let wt_parent = TempDir::new()?;
+    let wt = wt_parent.path().join("wt");
+    exec(&format!("git worktree add {}", wt.display()), temp_dir)?;
+    assert!(wt.join(".git").is_file());
+
+    // Opened through the `.git` file; the salt cache goes to the worktree's
+    // own git dir.
+    run(
+        SubCommand::Decrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        wt.join(".git"),
+    )?;
+    assert_eq!(fs::read_to_string(wt.join("a.txt"))?, "a");
+    let git_dir = exec("git rev-parse --absolute-git-dir", &wt)?.stdout;
+    let git_dir = PathBuf::from(String::from_utf8(git_dir)?.trim());
+    assert!(git_dir.join("git-simple-encrypt-salt-cache").is_file());
+    assert!(!temp_dir.join(".git/git-simple-encrypt-salt-cache").exists());
+
+    // The cached salt makes re-encryption reproduce the committed blob.
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        &wt,
+    )?;
+    let status = exec("git status --porcelain", &wt)?.stdout;
+    assert!(status.is_empty(), "{}", String::from_utf8_lossy(&status));
+
+    // Hooks live in the common dir.
+    run(
+        SubCommand::Install {
+            hooks: vec![],
+            all_hooks: false,
+            diff: false,
+            merge: false,
+        },
+        &wt,
+    )?;
+    assert!(temp_dir.join(".git/hooks/pre-commit").is_file());
+    Ok(())
+}
+
+#[test]
+fn test_recurse_submodules() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+    // A config repo with its own crypt list, but no key.
+    let upstream = TempDir::new()?;
+    let upstream = upstream.path();
+    exec("git init", upstream)?;
+    exec("git config user.name t", upstream)?;
+    exec("git config user.email t@t", upstream)?;
+    fs::write(upstream.join("secret.txt"), "secret")?;
+    fs::write(
+        upstream.join("git_simple_encrypt.toml"),
+        "use_zstd = true\nzstd_level = 3\ncrypt_list = [\"secret.txt\"]\n",
+    )?;
+    exec("git add -A", upstream)?;
+    exec("git commit -m init", upstream)?;
+    exec(
+        &format!(
+            "git -c protocol.file.allow=always submodule add {} vendor",
+            upstream.display()
+        ),
+        temp_dir,
+    )?;
+    fs::write(temp_dir.join("a.txt"), "a")?;
+    run(
+        SubCommand::Add {
+            paths: vec!["a.txt".into()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+
+    let submodule = temp_dir.join("vendor/secret.txt");
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    assert!(!submodule.is_encrypted());
+
+    // The submodule uses its own list and the parent's key.
+    run_recursive(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    assert!(temp_dir.join("a.txt").is_encrypted());
+    assert!(submodule.is_encrypted());
+    run_recursive(
+        SubCommand::Check {
+            paths: vec![],
+            staged: false,
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+
+    // Given paths only go to the repo they are in.
+    run_recursive(
+        SubCommand::Decrypt {
+            paths: vec!["vendor/secret.txt".into()],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    assert_eq!(fs::read_to_string(&submodule)?, "secret");
+    assert!(temp_dir.join("a.txt").is_encrypted());
+    assert!(
+        run_recursive(
+            SubCommand::Check {
+                paths: vec![],
+                staged: false,
+                recurse_submodules: false,
+            },
+            temp_dir,
+        )
+        .is_err()
+    );
+    Ok(())
+}
+
+#[test]
+fn test_encrypt_multiple_times() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+
+    std::fs::create_dir(temp_dir.join("dir"))?;
+    std::fs::write(temp_dir.join("t1.txt"), "Hello, world!")?;
+    std::fs::write(temp_dir.join("dir/t4.txt"), "dir test")?;
+
+    // Add file
+    run(
+        SubCommand::Add {
+            paths: ["t1.txt", "dir"].map(PathBuf::from).to_vec(),
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+
+    // Encrypt multiple times
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+
+    // Test
+    temp_dir.read_dir()?.for_each(|x| println!("{:?}", x));
+    temp_dir
+        .join("dir")
+        .read_dir()?
+        .for_each(|x| println!("{:?}", x));
+    assert!(temp_dir.join("t1.txt").is_encrypted());
+    assert!(temp_dir.join("dir/t4.txt").is_encrypted());
+
+    // Decrypt
+    run(
+        SubCommand::Decrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    println!("{}", "After Decrypt".green());
+
+    // Test
+
+    for entry in temp_dir.read_dir()? {
+        println!("{:?}", entry?);
+    }
+    assert!(temp_dir.join("t1.txt").is_not_encrypted());
+    assert!(temp_dir.join("dir/t4.txt").is_not_encrypted());
+    assert_eq!(
+        std::fs::read_to_string(temp_dir.join("t1.txt"))?,
+        "Hello, world!"
+    );
+    assert_eq!(
+        std::fs::read_to_string(temp_dir.join("dir/t4.txt"))?,
+        "dir test"
+    );
+
+    Ok(())
+}
+
+#[test]
+#[ignore = "This test takes too long to run, and it's not necessary to run it every time. You can run it manually if you want."]
+fn test_many_files() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+
+    let dir = temp_dir.join("dir");
+    std::fs::create_dir(&dir)?;
+    let files = (1..2000)
+        .map(|i| {
+            dir.join(format!("file{}.txt", i))
+                .tap(|f| std::fs::write(f, "Hello").unwrap())
+        })
+        .collect::<Vec<PathBuf>>();
+
+    // Add file
+    run(
+        SubCommand::Add {
+            paths: vec!["dir".into()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+
+    // Encrypt
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    // Decrypt
+    run(
+        SubCommand::Decrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+
+    // Test
+    for _ in 1..10 {
+        let file_name = files.choose(&mut rand::rng()).unwrap();
+        println!("Testing file: {}", file_name.display());
+        assert_eq!(std::fs::read_to_string(file_name)?, "Hello");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_large_file_encrypt_decrypt() -> anyhow::Result<()> {
+    const FILE_SIZE: usize = 5 * 1024 * 1024; // 5 MB
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+
+    let mut rng = rand::rngs::SmallRng::from_seed([42; 32]);
+    let original_data: Vec<u8> = (0..FILE_SIZE).map(|_| rng.random::<u8>()).collect();
+
+    let file_path = temp_dir.join("large.bin");
+    std::fs::write(&file_path, &original_data)?;
+
+    run(
+        SubCommand::Add {
+            paths: vec![file_path.clone()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+
+    assert!(file_path.is_encrypted());
+    run(
+        SubCommand::Decrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+
+    let decrypted_data = std::fs::read(&file_path)?;
+    assert_eq!(decrypted_data, original_data);
+    assert!(file_path.is_not_encrypted());
+
+    Ok(())
+}
+
+#[test]
+fn test_partial_decrypt() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+
+    std::fs::create_dir(temp_dir.join("dir"))?;
+    std::fs::write(temp_dir.join("t1.txt"), "Hello, world!")?;
+    std::fs::write(temp_dir.join("dir/t4.txt"), "dir test")?;
+
+    // Add file
+    run(
+        SubCommand::Add {
+            paths: ["t1.txt", "dir"].map(PathBuf::from).to_vec(),
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+
+    // Encrypt
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+
+    // Partial decrypt
+    run(
+        SubCommand::Decrypt {
+            paths: vec!["dir".into()],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+
+    // Test
+    for entry in temp_dir.read_dir()? {
+        println!("{:?}", entry?);
+    }
+    assert!(temp_dir.join("t1.txt").is_encrypted());
+    assert!(temp_dir.join("dir/t4.txt").exists());
+
+    // Reencrypt
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+
+    // Partial decrypt
+    run(
+        SubCommand::Decrypt {
+            paths: vec!["t1.txt".into()],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+
+    // Test
+    for entry in temp_dir.read_dir()? {
+        println!("{:?}", entry?);
+    }
+    assert!(temp_dir.join("t1.txt").exists());
+    assert!(temp_dir.join("dir/t4.txt").is_encrypted());
+
+    Ok(())
+}
+
+#[test]
+fn test_tampered_encrypted_file_fails_aad() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+
+    let file_path = temp_dir.join("secret.txt");
+    let original_content = b"Hello, this is a secret message that must be authenticated!";
+    std::fs::write(&file_path, original_content)?;
+
+    run(
+        SubCommand::Add {
+            paths: vec![file_path.clone()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+
+    assert!(file_path.is_encrypted());
+    let mut encrypted_data = std::fs::read(&file_path)?;
+    assert!(!encrypted_data.is_empty());
+
+    // 篡改：翻转中间的一个字节
+    let mid = encrypted_data.len() / 2;
+    encrypted_data[mid] ^= 0xFF;
+
+    // 写回篡改后的数据
+    std::fs::write(&file_path, &encrypted_data)?;
+
+    // 尝试解密，应该失败（AAD 校验不通过）
+    let decrypt_result = run(
+        SubCommand::Decrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    );
+    dbg!(&decrypt_result);
+    assert!(decrypt_result.is_err());
+    // 可选：验证文件仍然处于加密状态（因为解密失败，文件未被修改）
+    assert!(file_path.is_encrypted());
+
+    // 另一种篡改方式：截断文件末尾 10 个字节
+    let mut encrypted_data2 = std::fs::read(&file_path)?;
+    encrypted_data2.truncate(encrypted_data2.len().saturating_sub(10));
+    std::fs::write(&file_path, &encrypted_data2)?;
+
+    let decrypt_result2 = run(
+        SubCommand::Decrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    );
+    dbg!(&decrypt_result);
+    assert!(decrypt_result2.is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_deterministic_reencryption() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+
+    std::fs::create_dir(temp_dir.join("dir"))?;
+    std::fs::write(temp_dir.join("t1.txt"), "Hello, world!")?;
+    std::fs::write(temp_dir.join("t2.txt"), "6".repeat(100))?;
+    std::fs::write(temp_dir.join("dir/t3.txt"), "nested file")?;
+
+    // Add files
+    run(
+        SubCommand::Add {
+            paths: ["t1.txt", "t2.txt", "dir"].map(PathBuf::from).to_vec(),
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+
+    // ---- First encrypt ----
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    assert!(temp_dir.join("t1.txt").is_encrypted());
+    assert!(temp_dir.join("t2.txt").is_compressed());
     assert!(temp_dir.join("dir/t3.txt").is_encrypted());
 
-    let enc1_t1 = std::fs::read(temp_dir.join("t1.txt"))?;
-    let enc1_t2 = std::fs::read(temp_dir.join("t2.txt"))?;
-    let enc1_t3 = std::fs::read(temp_dir.join("dir/t3.txt"))?;
+    let enc1_t1 = std::fs::read(temp_dir.join("t1.txt"))?;
+    let enc1_t2 = std::fs::read(temp_dir.join("t2.txt"))?;
+    let enc1_t3 = std::fs::read(temp_dir.join("dir/t3.txt"))?;
+
+    // ---- Decrypt ----
+    run(
+        SubCommand::Decrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    assert_eq!(
+        std::fs::read_to_string(temp_dir.join("t1.txt"))?,
+        "Hello, world!"
+    );
+    assert_eq!(
+        std::fs::read_to_string(temp_dir.join("t2.txt"))?,
+        "6".repeat(100)
+    );
+    assert_eq!(
+        std::fs::read_to_string(temp_dir.join("dir/t3.txt"))?,
+        "nested file"
+    );
+
+    // ---- Re-encrypt (should produce identical ciphertext) ----
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+
+    let enc2_t1 = std::fs::read(temp_dir.join("t1.txt"))?;
+    let enc2_t2 = std::fs::read(temp_dir.join("t2.txt"))?;
+    let enc2_t3 = std::fs::read(temp_dir.join("dir/t3.txt"))?;
+
+    assert_eq!(
+        enc1_t1, enc2_t1,
+        "t1.txt: decrypt→encrypt must produce identical ciphertext"
+    );
+    assert_eq!(
+        enc1_t2, enc2_t2,
+        "t2.txt: decrypt→encrypt must produce identical ciphertext"
+    );
+    assert_eq!(
+        enc1_t3, enc2_t3,
+        "dir/t3.txt: decrypt→encrypt must produce identical ciphertext"
+    );
+
+    // Verify the files still decrypt correctly
+    run(
+        SubCommand::Decrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    assert_eq!(
+        std::fs::read_to_string(temp_dir.join("t1.txt"))?,
+        "Hello, world!"
+    );
+    assert_eq!(
+        std::fs::read_to_string(temp_dir.join("t2.txt"))?,
+        "6".repeat(100)
+    );
+    assert_eq!(
+        std::fs::read_to_string(temp_dir.join("dir/t3.txt"))?,
+        "nested file"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_deterministic_reencryption_multiple_cycles() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+
+    std::fs::write(temp_dir.join("data.txt"), "persistent data")?;
+
+    run(
+        SubCommand::Add {
+            paths: vec!["data.txt".into()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+
+    // Encrypt and capture ciphertext from 3 decrypt→encrypt cycles
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    let reference = std::fs::read(temp_dir.join("data.txt"))?;
+
+    for cycle in 1..=3 {
+        run(
+            SubCommand::Decrypt {
+                paths: vec![],
+                recurse_submodules: false,
+            },
+            temp_dir,
+        )?;
+        assert_eq!(
+            std::fs::read_to_string(temp_dir.join("data.txt"))?,
+            "persistent data",
+            "Data corrupted at cycle {cycle}"
+        );
+
+        run(
+            SubCommand::Encrypt {
+                paths: vec![],
+                recurse_submodules: false,
+            },
+            temp_dir,
+        )?;
+        let ciphertext = std::fs::read(temp_dir.join("data.txt"))?;
+        assert_eq!(ciphertext, reference, "Ciphertext changed at cycle {cycle}");
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_check_staged() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+
+    std::fs::write(temp_dir.join("encrypted.txt"), "already encrypted")?;
+    std::fs::write(temp_dir.join("unencrypted.txt"), "not yet encrypted")?;
+    std::fs::write(temp_dir.join("plain.txt"), "not in crypt list")?;
+
+    run(
+        SubCommand::Add {
+            paths: ["encrypted.txt", "unencrypted.txt"]
+                .map(PathBuf::from)
+                .to_vec(),
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+
+    // Encrypt only encrypted.txt, leave unencrypted.txt as-is
+    run(
+        SubCommand::Encrypt {
+            paths: vec!["encrypted.txt".into()],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    assert!(temp_dir.join("encrypted.txt").is_encrypted());
+    assert!(temp_dir.join("unencrypted.txt").is_not_encrypted());
+
+    // Case 1: stage only the encrypted file → should pass
+    exec("git add encrypted.txt", temp_dir).context("git add encrypted.txt")?;
+    assert!(
+        run(
+            SubCommand::Check {
+                paths: vec![],
+                staged: true,
+                recurse_submodules: false
+            },
+            temp_dir
+        )
+        .is_ok(),
+        "encrypted staged file should pass check"
+    );
+
+    // Case 2: also stage the unencrypted file (in crypt list) → should fail
+    exec("git add unencrypted.txt", temp_dir).context("git add unencrypted.txt")?;
+    assert!(
+        run(
+            SubCommand::Check {
+                paths: vec![],
+                staged: true,
+                recurse_submodules: false
+            },
+            temp_dir
+        )
+        .is_err(),
+        "unencrypted staged file (in crypt list) should fail check"
+    );
+
+    // Clear index
+    exec("git rm --cached encrypted.txt unencrypted.txt", temp_dir).context("git rm --cached")?;
+
+    // Case 3: stage a file not in crypt list → should pass (nothing to check)
+    exec("git add plain.txt", temp_dir).context("git add plain.txt")?;
+    assert!(
+        run(
+            SubCommand::Check {
+                paths: vec![],
+                staged: true,
+                recurse_submodules: false
+            },
+            temp_dir
+        )
+        .is_ok(),
+        "staged file not in crypt list should pass check"
+    );
+
+    // Clear index
+    exec("git rm --cached plain.txt", temp_dir).context("git rm --cached")?;
+
+    // Case 4: nothing staged → should pass
+    assert!(
+        run(
+            SubCommand::Check {
+                paths: vec![],
+                staged: true,
+                recurse_submodules: false
+            },
+            temp_dir
+        )
+        .is_ok(),
+        "nothing staged should pass check"
+    );
+
+    // Case 5: damaged ciphertext is reported as such, not as a wrong key
+    let encrypted = fs::read(temp_dir.join("encrypted.txt"))?;
+    let mut tampered = encrypted.clone();
+    tampered[MAGIC.len()] = 0xff;
+    for (content, outcome) in [
+        (&encrypted[..HEADER_LEN + NONCE_LEN], FileOutcome::Truncated),
+        (&tampered[..], FileOutcome::Tampered),
+    ] {
+        fs::write(temp_dir.join("encrypted.txt"), content)?;
+        exec("git add encrypted.txt", temp_dir).context("git add encrypted.txt")?;
+        let report = Repo::open(temp_dir)?.check(&[], true, &NoopObserver)?;
+        assert_eq!(report.files[0].outcome, outcome);
+        assert!(report.result().is_err());
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_stage() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+    let index_blob = |path: &str| -> anyhow::Result<Vec<u8>> {
+        Ok(exec(&format!("git cat-file blob :{path}"), temp_dir)?.stdout)
+    };
+
+    std::fs::write(temp_dir.join("a.txt"), "secret a")?;
+    std::fs::write(temp_dir.join("b.txt"), "secret b")?;
+    run(
+        SubCommand::Add {
+            paths: vec!["a.txt".into(), "b.txt".into()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+
+    // The working tree keeps its plaintext, the index gets ciphertext.
+    run(
+        SubCommand::Stage {
+            paths: vec!["a.txt".into()],
+            staged: false,
+        },
+        temp_dir,
+    )?;
+    assert!(temp_dir.join("a.txt").is_not_encrypted());
+    let staged = index_blob("a.txt")?;
+    assert!(staged.starts_with(b"GITSE"));
+    assert!(index_blob("b.txt")?.is_empty(), "b.txt is not staged");
+
+    // Staging unchanged plaintext again keeps the index entry.
+    run(
+        SubCommand::Stage {
+            paths: vec![],
+            staged: false,
+        },
+        temp_dir,
+    )?;
+    assert_eq!(index_blob("a.txt")?, staged);
+    assert!(index_blob("b.txt")?.starts_with(b"GITSE"));
+
+    // Pre-commit mode encrypts plaintext staged with `git add`.
+    std::fs::write(temp_dir.join("b.txt"), "secret b2")?;
+    exec("git add b.txt", temp_dir)?;
+    assert_eq!(index_blob("b.txt")?, b"secret b2");
+    run(
+        SubCommand::Stage {
+            paths: vec![],
+            staged: true,
+        },
+        temp_dir,
+    )?;
+    run(
+        SubCommand::Check {
+            paths: vec![],
+            staged: true,
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    let mut plaintext = Vec::new();
+    show::show(&Repo::open(temp_dir)?, ":b.txt", &mut plaintext)?;
+    assert_eq!(plaintext, b"secret b2");
+    assert!(temp_dir.join("b.txt").is_not_encrypted());
+    Ok(())
+}
+
+#[cfg(unix)]
+#[test]
+fn test_stage_unreadable_file() -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+    fs::write(temp_dir.join("a.txt"), "secret a")?;
+    fs::write(temp_dir.join("b.txt"), "secret b")?;
+    run(
+        SubCommand::Add {
+            paths: vec!["a.txt".into(), "b.txt".into()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+    let locked = temp_dir.join("b.txt");
+    fs::set_permissions(&locked, fs::Permissions::from_mode(0o000))?;
+    if fs::read(&locked).is_ok() {
+        // Running as root, permissions are not enforced.
+        return Ok(());
+    }
+
+    // The unreadable file fails on its own, the other one is still staged.
+    let repo = Repo::open(temp_dir)?;
+    let report = stage::stage(&repo, &[], false, &NoopObserver)?;
+    let outcomes = report
+        .files
+        .iter()
+        .map(|f| (f.path.clone(), f.outcome))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        outcomes,
+        [
+            (PathBuf::from("a.txt"), FileOutcome::Ok),
+            (PathBuf::from("b.txt"), FileOutcome::Failed),
+        ]
+    );
+    assert!(matches!(report.files[1].error, Some(Error::Io(_))));
+    assert!(matches!(
+        report.result(),
+        Err(Error::BatchFailed("stage", 1, 2))
+    ));
+    let staged = exec("git ls-files --cached", temp_dir)?.stdout;
+    assert_eq!(String::from_utf8(staged)?, "a.txt\n");
+    Ok(())
+}
+
+#[test]
+fn test_check_staged_reads_index() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+    let check_staged = || {
+        run(
+            SubCommand::Check {
+                paths: vec![],
+                staged: true,
+                recurse_submodules: false,
+            },
+            temp_dir,
+        )
+    };
+
+    std::fs::write(temp_dir.join("a.txt"), "secret")?;
+    run(
+        SubCommand::Add {
+            paths: vec!["a.txt".into()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+
+    // Plaintext staged, working tree encrypted afterwards → still rejected.
+    exec("git add a.txt", temp_dir)?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    assert!(check_staged().is_err(), "staged plaintext must be rejected");
+
+    // Ciphertext staged, working tree decrypted afterwards → accepted.
+    exec("git add a.txt", temp_dir)?;
+    run(
+        SubCommand::Decrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    assert!(check_staged().is_ok(), "staged ciphertext must pass");
+
+    // Ciphertext staged under another key → rejected.
+    run(
+        SubCommand::Set {
+            field: SetField::Key {
+                value: "another key".to_owned(),
+            },
+        },
+        temp_dir,
+    )?;
+    assert!(check_staged().is_err(), "wrong key must be rejected");
+    Ok(())
+}
+
+#[test]
+fn test_status() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+
+    std::fs::create_dir(temp_dir.join("dir"))?;
+    std::fs::write(temp_dir.join("enc.txt"), "encrypted")?;
+    std::fs::write(temp_dir.join("plain.txt"), "never encrypted")?;
+    std::fs::write(temp_dir.join("edited.txt"), "before edit")?;
+    std::fs::write(temp_dir.join("dir/other_key.txt"), "other key")?;
+    std::fs::write(temp_dir.join("broken.txt"), "GITSE but not a header")?;
+    std::fs::write(temp_dir.join("orphan.txt"), "orphan")?;
+    std::fs::create_dir(temp_dir.join("gone"))?;
+
+    run(
+        SubCommand::Add {
+            paths: [
+                "enc.txt",
+                "plain.txt",
+                "edited.txt",
+                "dir",
+                "broken.txt",
+                "orphan.txt",
+                "gone",
+            ]
+            .map(PathBuf::from)
+            .to_vec(),
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+    // `dir/other_key.txt` stays encrypted under the old key.
+    run(
+        SubCommand::Encrypt {
+            paths: ["dir", "orphan.txt"].map(PathBuf::from).to_vec(),
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    run(
+        SubCommand::Set {
+            field: SetField::Key {
+                value: "a different password".to_owned(),
+            },
+        },
+        temp_dir,
+    )?;
+    run(
+        SubCommand::Encrypt {
+            paths: ["enc.txt", "edited.txt"].map(PathBuf::from).to_vec(),
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    exec("git add edited.txt", temp_dir)?;
+    run(
+        SubCommand::Decrypt {
+            paths: vec!["edited.txt".into()],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    std::fs::write(temp_dir.join("edited.txt"), "after edit")?;
+
+    // Drop `orphan.txt` and the empty `gone` dir from the list by hand.
+    let mut repo = Repo::open(temp_dir)?;
+    repo.conf.crypt_list.retain(|x| x != "orphan.txt");
+    std::fs::remove_dir(temp_dir.join("gone"))?;
+
+    let report = status(&repo, &[])?;
+    dbg!(&report);
+    let state_of = |path: &str| {
+        report
+            .files
+            .iter()
+            .find(|f| f.path == path)
+            .map(|f| f.state)
+    };
+    assert_eq!(state_of("enc.txt"), Some(FileState::Encrypted));
+    assert_eq!(state_of("plain.txt"), Some(FileState::Plaintext));
+    assert_eq!(state_of("edited.txt"), Some(FileState::Modified));
+    assert_eq!(state_of("dir/other_key.txt"), Some(FileState::WrongKey));
+    assert_eq!(state_of("broken.txt"), Some(FileState::Corrupt));
+    assert_eq!(report.orphaned, vec!["orphan.txt".to_owned()]);
+    assert_eq!(report.unmatched, vec!["gone".to_owned()]);
+
+    assert!(report.porcelain().contains("M edited.txt\n"));
+    assert!(report.porcelain().contains("O orphan.txt\n"));
+
+    Ok(())
+}
+
+#[test]
+fn test_textconv_diff_driver() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+
+    std::fs::create_dir(temp_dir.join("dir"))?;
+    std::fs::write(temp_dir.join("dir/secret.txt"), "old secret\n")?;
+    run(
+        SubCommand::Add {
+            paths: vec!["dir".into()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+    run(
+        SubCommand::Install {
+            hooks: vec![],
+            all_hooks: false,
+            diff: true,
+            merge: false,
+        },
+        temp_dir,
+    )?;
+
+    let attributes = std::fs::read_to_string(temp_dir.join(".gitattributes"))?;
+    assert!(
+        attributes.contains("/dir/** -text diff=git-se -merge"),
+        "{attributes}"
+    );
+    let textconv = exec("git config diff.git-se.textconv", temp_dir)?;
+    assert_eq!(
+        String::from_utf8(textconv.stdout)?.trim(),
+        "git-se textconv"
+    );
+
+    // `cachetextconv` stores its cache as notes commits, which need an identity.
+    exec("git config user.name t", temp_dir)?;
+    exec("git config user.email t@t", temp_dir)?;
+    // The binary under test is not on PATH; point the driver at it directly.
+    Command::new("git")
+        .args([
+            "config",
+            "diff.git-se.textconv",
+            concat!(env!("CARGO_BIN_EXE_git-se"), " textconv"),
+        ])
+        .current_dir(temp_dir)
+        .output()?;
+    exec("git commit -q --allow-empty -m init", temp_dir)?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    exec("git add -A", temp_dir)?;
+    exec("git commit -q -m secret", temp_dir)?;
+
+    run(
+        SubCommand::Decrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    std::fs::write(temp_dir.join("dir/secret.txt"), "new secret\n")?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+
+    let diff = String::from_utf8(exec("git diff", temp_dir)?.stdout)?;
+    println!("{diff}");
+    assert!(diff.contains("-old secret"));
+    assert!(diff.contains("+new secret"));
+    Ok(())
+}
+
+#[test]
+fn test_merge_driver() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+    let file = temp_dir.join("secret.txt");
+
+    std::fs::write(&file, "a\nb\nc\nd\ne\n")?;
+    run(
+        SubCommand::Add {
+            paths: vec!["secret.txt".into()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+    run(
+        SubCommand::Install {
+            hooks: vec![],
+            all_hooks: false,
+            diff: false,
+            merge: true,
+        },
+        temp_dir,
+    )?;
+    let attributes = std::fs::read_to_string(temp_dir.join(".gitattributes"))?;
+    assert!(
+        attributes.contains("/secret.txt -text -diff merge=git-se"),
+        "{attributes}"
+    );
+
+    exec("git config user.name t", temp_dir)?;
+    exec("git config user.email t@t", temp_dir)?;
+    // The binary under test is not on PATH; point the driver at it directly.
+    Command::new("git")
+        .args([
+            "config",
+            "merge.git-se.driver",
+            concat!(env!("CARGO_BIN_EXE_git-se"), " merge %O %A %B %P"),
+        ])
+        .current_dir(temp_dir)
+        .output()?;
+    let commit_edit = |content: &str, msg: &str| -> anyhow::Result<()> {
+        run(
+            SubCommand::Decrypt {
+                paths: vec![],
+                recurse_submodules: false,
+            },
+            temp_dir,
+        )?;
+        std::fs::write(&file, content)?;
+        run(
+            SubCommand::Encrypt {
+                paths: vec![],
+                recurse_submodules: false,
+            },
+            temp_dir,
+        )?;
+        exec("git add -A", temp_dir)?;
+        exec(&format!("git commit -q -m {msg}"), temp_dir)?;
+        Ok(())
+    };
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    exec("git add -A", temp_dir)?;
+    exec("git commit -q -m init", temp_dir)?;
+    exec("git branch base", temp_dir)?;
+
+    // Edits on different lines merge cleanly.
+    exec("git checkout -q -b feature", temp_dir)?;
+    commit_edit("a\nb\nc\nd\nE\n", "feature")?;
+    exec("git checkout -q -", temp_dir)?;
+    exec("git checkout -q -b main2", temp_dir)?;
+    commit_edit("A\nb\nc\nd\ne\n", "main")?;
+    let output = exec("git merge -q --no-edit feature", temp_dir)?;
+    assert!(output.status.success(), "{output:?}");
+    assert!(file.is_encrypted());
+    run(
+        SubCommand::Decrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    assert_eq!(std::fs::read_to_string(&file)?, "A\nb\nc\nd\nE\n");
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+
+    // Edits on the same line conflict, leaving encrypted conflict markers.
+    exec("git checkout -q -b conflict base", temp_dir)?;
+    commit_edit("X\nb\nc\nd\ne\n", "conflict")?;
+    let output = exec("git merge -q --no-edit main2", temp_dir)?;
+    assert!(!output.status.success());
+    assert!(file.is_encrypted());
+    run(
+        SubCommand::Decrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    let merged = std::fs::read_to_string(&file)?;
+    assert!(merged.contains("<<<<<<<"), "{merged}");
+    assert!(merged.contains("X\n") && merged.contains("A\n"), "{merged}");
+    Ok(())
+}
+
+#[test]
+fn test_cat_and_show() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+    let file = temp_dir.join("secret.txt");
+
+    exec("git config user.name t", temp_dir)?;
+    exec("git config user.email t@t", temp_dir)?;
+    std::fs::write(&file, "v1\n")?;
+    std::fs::write(temp_dir.join("plain.txt"), "plain\n")?;
+    run(
+        SubCommand::Add {
+            paths: vec!["secret.txt".into()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    exec("git add -A", temp_dir)?;
+    exec("git commit -q -m v1", temp_dir)?;
+
+    run(
+        SubCommand::Decrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    std::fs::write(&file, "v2\n")?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    exec("git add -A", temp_dir)?;
+    exec("git commit -q -m v2", temp_dir)?;
+
+    let repo = Repo::open(temp_dir)?;
+    let mut out = Vec::new();
+    show::cat(&repo, Path::new("secret.txt"), &mut out)?;
+    assert_eq!(out, b"v2\n");
+    assert!(file.is_encrypted(), "cat must not modify the file");
+
+    out.clear();
+    show::show(&repo, "HEAD~1:secret.txt", &mut out)?;
+    assert_eq!(out, b"v1\n");
+
+    // Non-encrypted inputs are passed through.
+    out.clear();
+    show::show(&repo, "HEAD:plain.txt", &mut out)?;
+    assert_eq!(out, b"plain\n");
+    out.clear();
+    show::cat(&repo, Path::new("plain.txt"), &mut out)?;
+    assert_eq!(out, b"plain\n");
+
+    assert!(show::show(&repo, "HEAD:missing.txt", &mut Vec::new()).is_err());
+    Ok(())
+}
+
+#[test]
+fn test_verify_history() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+    let commit = |msg: &str| -> anyhow::Result<()> {
+        exec("git add -A", temp_dir)?;
+        exec(&format!("git commit -q -m {msg}"), temp_dir)?;
+        Ok(())
+    };
+    exec("git config user.name t", temp_dir)?;
+    exec("git config user.email t@t", temp_dir)?;
+
+    // A plaintext commit before encryption was set up.
+    std::fs::create_dir(temp_dir.join("dir"))?;
+    std::fs::write(temp_dir.join("dir/a.txt"), "a\n")?;
+    commit("plain")?;
+    run(
+        SubCommand::Add {
+            paths: vec!["dir".into()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    commit("old-key")?;
 
-    // ---- Decrypt ----
-    run(SubCommand::Decrypt { paths: vec![] }, temp_dir)?;
-    assert_eq!(
-        std::fs::read_to_string(temp_dir.join("t1.txt"))?,
-        "Hello, world!"
-    );
-    assert_eq!(
-        std::fs::read_to_string(temp_dir.join("t2.txt"))?,
-        "6".repeat(100)
-    );
-    assert_eq!(
-        std::fs::read_to_string(temp_dir.join("dir/t3.txt"))?,
-        "nested file"
-    );
+    // Re-encrypt under a new password.
+    run(
+        SubCommand::Decrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    run(
+        SubCommand::Set {
+            field: SetField::Key {
+                value: "new password".to_owned(),
+            },
+        },
+        temp_dir,
+    )?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    commit("new-key")?;
 
-    // ---- Re-encrypt (should produce identical ciphertext) ----
-    run(SubCommand::Encrypt { paths: vec![] }, temp_dir)?;
+    // A truncated blob: header and nonce, but no ciphertext.
+    let encrypted = std::fs::read(temp_dir.join("dir/a.txt"))?;
+    std::fs::write(
+        temp_dir.join("dir/b.txt"),
+        &encrypted[..HEADER_LEN + NONCE_LEN],
+    )?;
+    commit("truncated")?;
+
+    let repo = Repo::open(temp_dir)?;
+    let report = history::verify_history(&repo, &[])?;
+    assert_eq!(report.commits, 4);
+    let state_at = |rev: &str, path: &str| {
+        let oid = String::from_utf8(
+            exec(&format!("git rev-parse {rev}"), temp_dir)
+                .unwrap()
+                .stdout,
+        )
+        .unwrap();
+        report
+            .entries
+            .iter()
+            .find(|e| e.commit == oid.trim() && e.path == path)
+            .map(|e| e.state)
+    };
+    assert_eq!(state_at("HEAD~3", "dir/a.txt"), Some(BlobState::Plaintext));
+    assert_eq!(state_at("HEAD~2", "dir/a.txt"), Some(BlobState::WrongKey));
+    assert_eq!(state_at("HEAD~1", "dir/a.txt"), Some(BlobState::Ok));
+    assert_eq!(state_at("HEAD", "dir/b.txt"), Some(BlobState::Truncated));
+    assert_eq!(report.failures().count(), 3);
+
+    // A rev range only walks the given commits.
+    let report = history::verify_history(&repo, &["HEAD~2..HEAD~1".to_owned()])?;
+    assert_eq!(report.commits, 1);
+    assert_eq!(report.failures().count(), 0);
+    assert!(run(SubCommand::VerifyHistory { revs: vec![] }, temp_dir).is_err());
+    Ok(())
+}
 
-    let enc2_t1 = std::fs::read(temp_dir.join("t1.txt"))?;
-    let enc2_t2 = std::fs::read(temp_dir.join("t2.txt"))?;
-    let enc2_t3 = std::fs::read(temp_dir.join("dir/t3.txt"))?;
+#[test]
+fn test_leaks() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+    let commit = |msg: &str| -> anyhow::Result<()> {
+        exec("git add -A", temp_dir)?;
+        exec(&format!("git commit -q -m {msg}"), temp_dir)?;
+        Ok(())
+    };
+    exec("git config user.name leaker", temp_dir)?;
+    exec("git config user.email leaker@example.com", temp_dir)?;
+
+    // Committed in plaintext under another name, then renamed into the list.
+    std::fs::write(temp_dir.join("old.env"), "TOKEN=1\n")?;
+    std::fs::write(temp_dir.join("public.txt"), "hello\n")?;
+    commit("leak")?;
+    std::fs::create_dir(temp_dir.join("secrets"))?;
+    exec("git mv old.env secrets/app.env", temp_dir)?;
+    commit("move")?;
+    run(
+        SubCommand::Add {
+            paths: vec!["secrets".into()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    commit("encrypt")?;
 
-    assert_eq!(
-        enc1_t1, enc2_t1,
-        "t1.txt: decrypt→encrypt must produce identical ciphertext"
-    );
-    assert_eq!(
-        enc1_t2, enc2_t2,
-        "t2.txt: decrypt→encrypt must produce identical ciphertext"
+    let repo = Repo::open(temp_dir)?;
+    let found = history::leaks(&repo)?;
+    let paths: Vec<_> = found.iter().map(|l| l.path.as_str()).collect();
+    assert_eq!(paths, ["old.env", "secrets/app.env"], "{found:?}");
+    assert!(
+        found
+            .iter()
+            .all(|l| l.author == "leaker <leaker@example.com>")
     );
-    assert_eq!(
-        enc1_t3, enc2_t3,
-        "dir/t3.txt: decrypt→encrypt must produce identical ciphertext"
+    assert!(
+        run(SubCommand::Leaks, temp_dir).is_err(),
+        "leaks must fail when plaintext is found"
     );
+    Ok(())
+}
 
-    // Verify the files still decrypt correctly
-    run(SubCommand::Decrypt { paths: vec![] }, temp_dir)?;
-    assert_eq!(
-        std::fs::read_to_string(temp_dir.join("t1.txt"))?,
-        "Hello, world!"
-    );
+#[test]
+fn test_purge_history() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+    let commit = |msg: &str| -> anyhow::Result<()> {
+        exec("git add -A", temp_dir)?;
+        exec(&format!("git commit -q -m {msg}"), temp_dir)?;
+        Ok(())
+    };
+    let rev_parse = |rev: &str| -> anyhow::Result<String> {
+        let out = exec(&format!("git rev-parse {rev}"), temp_dir)?;
+        Ok(String::from_utf8(out.stdout)?.trim().to_owned())
+    };
+    exec("git config user.name t", temp_dir)?;
+    exec("git config user.email t@t", temp_dir)?;
+
+    std::fs::create_dir(temp_dir.join("secrets"))?;
+    std::fs::write(temp_dir.join("secrets/a.env"), "TOKEN=1\n")?;
+    std::fs::write(temp_dir.join("secrets/b.env"), "TOKEN=1\n")?;
+    std::fs::write(temp_dir.join("public.txt"), "hello\n")?;
+    commit("leak")?;
+    exec("git tag light", temp_dir)?;
+    exec("git tag -a annotated -m release", temp_dir)?;
+    std::fs::write(temp_dir.join("public.txt"), "hello again\n")?;
+    commit("public")?;
+    run(
+        SubCommand::Add {
+            paths: vec!["secrets".into()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+    commit("config")?;
+    let old_head = rev_parse("HEAD")?;
+
+    let repo = Repo::open(temp_dir)?;
+    std::fs::write(temp_dir.join("public.txt"), "dirty\n")?;
+    assert!(matches!(
+        history::purge_history(&repo, &[]),
+        Err(git_simple_encrypt::Error::DirtyWorktree)
+    ));
+    exec("git checkout -- public.txt", temp_dir)?;
+
+    let report = history::purge_history(&repo, &[])?;
+    assert_eq!(report.blobs, 1, "identical blobs are encrypted once");
+    assert_eq!(report.commits.len(), 3);
+    assert_eq!(report.refs.len(), 3);
+    assert_eq!(report.commits[2].old, old_head);
+    assert_eq!(rev_parse("HEAD")?, report.commits[2].new);
+    assert_eq!(rev_parse("refs/git-se-original/heads/master")?, old_head);
+
+    // Identical plaintext maps to identical ciphertext; metadata is kept.
     assert_eq!(
-        std::fs::read_to_string(temp_dir.join("t2.txt"))?,
-        "6".repeat(100)
+        rev_parse("HEAD:secrets/a.env")?,
+        rev_parse("HEAD:secrets/b.env")?
     );
     assert_eq!(
-        std::fs::read_to_string(temp_dir.join("dir/t3.txt"))?,
-        "nested file"
+        rev_parse("HEAD:public.txt")?,
+        rev_parse(&format!("{old_head}:public.txt"))?
     );
-
+    let message = exec("git log -1 --format=%an%n%s annotated", temp_dir)?.stdout;
+    assert_eq!(String::from_utf8(message)?, "t\nleak\n");
+    assert_eq!(rev_parse("annotated^{commit}")?, rev_parse("light")?);
+    assert_eq!(rev_parse("light")?, report.commits[0].new);
+
+    let mut out = Vec::new();
+    show::show(&repo, "light:secrets/a.env", &mut out)?;
+    assert_eq!(out, b"TOKEN=1\n");
+    assert!(temp_dir.join("secrets/a.env").is_encrypted());
+    assert!(history::leaks(&repo)?.is_empty());
+
+    // Nothing left to do on a second run.
+    assert!(history::purge_history(&repo, &[])?.refs.is_empty());
     Ok(())
 }
 
+#[cfg(unix)]
 #[test]
-fn test_deterministic_reencryption_multiple_cycles() -> anyhow::Result<()> {
+fn test_pre_receive_hook() -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let server = TempDir::new()?;
+    let server_dir = server.path();
+    exec("git init -q --bare", server_dir)?;
+    exec("git config receive.advertisePushOptions true", server_dir)?;
+    let hook = server_dir.join("hooks/pre-receive");
+    std::fs::write(
+        &hook,
+        concat!(
+            "#!/bin/sh\nexec ",
+            env!("CARGO_BIN_EXE_git-se"),
+            " pre-receive\n"
+        ),
+    )?;
+    std::fs::set_permissions(&hook, fs::Permissions::from_mode(0o755))?;
+
     let pwd = test_init();
     let temp_dir = pwd.path();
-
-    std::fs::write(temp_dir.join("data.txt"), "persistent data")?;
-
+    exec("git config user.name t", temp_dir)?;
+    exec("git config user.email t@t", temp_dir)?;
+    let push = |options: &str| -> anyhow::Result<Output> {
+        Ok(exec(
+            &format!(
+                "git push -q {options} {} HEAD:refs/heads/main",
+                server_dir.display()
+            ),
+            temp_dir,
+        )?)
+    };
+
+    std::fs::create_dir(temp_dir.join("secrets"))?;
+    std::fs::write(temp_dir.join("secrets/a.env"), "TOKEN=1\n")?;
     run(
         SubCommand::Add {
-            paths: vec!["data.txt".into()],
+            paths: vec!["secrets".into()],
+            dry_run: false,
         },
         temp_dir,
     )?;
+    exec("git add -A", temp_dir)?;
+    exec("git commit -q -m plaintext", temp_dir)?;
 
-    // Encrypt and capture ciphertext from 3 decrypt→encrypt cycles
-    run(SubCommand::Encrypt { paths: vec![] }, temp_dir)?;
-    let reference = std::fs::read(temp_dir.join("data.txt"))?;
-
-    for cycle in 1..=3 {
-        run(SubCommand::Decrypt { paths: vec![] }, temp_dir)?;
-        assert_eq!(
-            std::fs::read_to_string(temp_dir.join("data.txt"))?,
-            "persistent data",
-            "Data corrupted at cycle {cycle}"
-        );
+    let output = push("")?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "{stderr}");
+    assert!(
+        stderr.contains("secrets/a.env is not encrypted"),
+        "{stderr}"
+    );
 
-        run(SubCommand::Encrypt { paths: vec![] }, temp_dir)?;
-        let ciphertext = std::fs::read(temp_dir.join("data.txt"))?;
-        assert_eq!(ciphertext, reference, "Ciphertext changed at cycle {cycle}");
-    }
+    // An allow-listed path is let through.
+    let output = push("-o git-se.allow=secrets/a.env")?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
 
+    // Encrypted files pass without any option.
+    std::fs::write(temp_dir.join("secrets/b.env"), "TOKEN=2\n")?;
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    exec("git add -A", temp_dir)?;
+    exec("git commit -q -m encrypted", temp_dir)?;
+    let output = push("")?;
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
     Ok(())
 }
 
+#[cfg(unix)]
 #[test]
-fn test_check_staged() -> anyhow::Result<()> {
+fn test_client_hooks() -> anyhow::Result<()> {
+    let server = TempDir::new()?;
+    exec("git init -q --bare", server.path())?;
+
     let pwd = test_init();
     let temp_dir = pwd.path();
+    exec("git config user.name t", temp_dir)?;
+    exec("git config user.email t@t", temp_dir)?;
+    exec(
+        &format!("git remote add origin {}", server.path().display()),
+        temp_dir,
+    )?;
+    run(
+        SubCommand::Install {
+            hooks: vec![],
+            all_hooks: true,
+            diff: false,
+            merge: false,
+        },
+        temp_dir,
+    )?;
+    // The hooks call `git-se` from PATH.
+    let bin_dir = Path::new(env!("CARGO_BIN_EXE_git-se")).parent().unwrap();
+    let path = format!("{}:{}", bin_dir.display(), std::env::var("PATH")?);
+    let git = |args: &str| -> anyhow::Result<Output> {
+        Ok(Command::new("git")
+            .args(args.split_whitespace())
+            .env("PATH", &path)
+            .current_dir(temp_dir)
+            .output()?)
+    };
+
+    std::fs::write(temp_dir.join("a.env"), "TOKEN=1\n")?;
+    run(
+        SubCommand::Add {
+            paths: vec!["a.env".into()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+    git("add -A")?;
+    assert!(!git("commit -q -m plaintext")?.status.success());
+    run(
+        SubCommand::Encrypt {
+            paths: vec![],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    git("add -A")?;
+    assert!(git("commit -q -m encrypted")?.status.success());
+    assert!(git("push -q origin HEAD:main")?.status.success());
+
+    // post-checkout decrypts after switching branches.
+    assert!(git("checkout -q -b feature")?.status.success());
+    assert!(temp_dir.join("a.env").is_not_encrypted());
+
+    // pre-push catches what `--no-verify` let through.
+    std::fs::write(temp_dir.join("a.env"), "TOKEN=2\n")?;
+    git("commit -q -a --no-verify -m leak")?;
+    let output = git("push -q origin HEAD:main")?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(!output.status.success(), "{stderr}");
+    assert!(stderr.contains("a.env is not encrypted"), "{stderr}");
+
+    run(SubCommand::Uninstall, temp_dir)?;
+    assert!(!temp_dir.join(".git/hooks/pre-push").exists());
+    Ok(())
+}
 
-    std::fs::write(temp_dir.join("encrypted.txt"), "already encrypted")?;
-    std::fs::write(temp_dir.join("unencrypted.txt"), "not yet encrypted")?;
-    std::fs::write(temp_dir.join("plain.txt"), "not in crypt list")?;
+#[test]
+fn test_output_format() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+    let git_se = |args: &str| {
+        exec(
+            &format!("{} {args}", env!("CARGO_BIN_EXE_git-se")),
+            temp_dir,
+        )
+    };
 
+    std::fs::create_dir(temp_dir.join("secrets"))?;
+    std::fs::write(temp_dir.join("secrets/a.env"), "TOKEN=1\n")?;
+    std::fs::write(temp_dir.join("secrets/b.env"), "TOKEN=2\n")?;
     run(
         SubCommand::Add {
-            paths: ["encrypted.txt", "unencrypted.txt"]
-                .map(PathBuf::from)
-                .to_vec(),
+            paths: vec!["secrets".into()],
+            dry_run: false,
         },
         temp_dir,
     )?;
-
-    // Encrypt only encrypted.txt, leave unencrypted.txt as-is
     run(
         SubCommand::Encrypt {
-            paths: vec!["encrypted.txt".into()],
+            paths: vec!["secrets/a.env".into()],
+            recurse_submodules: false,
         },
         temp_dir,
     )?;
-    assert!(temp_dir.join("encrypted.txt").is_encrypted());
-    assert!(temp_dir.join("unencrypted.txt").is_not_encrypted());
 
-    // Case 1: stage only the encrypted file → should pass
-    exec("git add encrypted.txt", temp_dir).context("git add encrypted.txt")?;
-    assert!(
-        run(
-            SubCommand::Check {
-                paths: vec![],
-                staged: true
-            },
-            temp_dir
-        )
-        .is_ok(),
-        "encrypted staged file should pass check"
+    // One document, with the progress on stderr.
+    let output = git_se("--format json e")?;
+    assert!(output.status.success());
+    let doc: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(doc["schema_version"], 1);
+    assert_eq!(doc["command"], "encrypt");
+    assert_eq!(doc["ok"], true);
+    assert!(doc["error"].is_null());
+    let files = doc["files"].as_array().unwrap();
+    let fields =
+        |f: &serde_json::Value| (f["path"].clone(), f["action"].clone(), f["outcome"].clone());
+    assert_eq!(
+        files.iter().map(fields).collect::<Vec<_>>(),
+        [
+            ("secrets/a.env".into(), "encrypt".into(), "skipped".into()),
+            ("secrets/b.env".into(), "encrypt".into(), "ok".into()),
+        ]
     );
-
-    // Case 2: also stage the unencrypted file (in crypt list) → should fail
-    exec("git add unencrypted.txt", temp_dir).context("git add unencrypted.txt")?;
-    assert!(
-        run(
-            SubCommand::Check {
-                paths: vec![],
-                staged: true
-            },
-            temp_dir
-        )
-        .is_err(),
-        "unencrypted staged file (in crypt list) should fail check"
+    assert_eq!(files[1]["bytes_in"], 8);
+    assert!(files[1]["bytes_out"].as_u64() > Some(8));
+    assert_eq!(
+        doc["summary"],
+        serde_json::json!({ "total": 2, "succeeded": 1, "skipped": 1, "failed": 0 })
     );
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Encrypting"));
 
-    // Clear index
-    exec("git rm --cached encrypted.txt unencrypted.txt", temp_dir).context("git rm --cached")?;
+    // One object per line, ending with the result; failures carry the kind.
+    run(
+        SubCommand::Decrypt {
+            paths: vec!["secrets/b.env".into()],
+            recurse_submodules: false,
+        },
+        temp_dir,
+    )?;
+    let output = git_se("--format ndjson check")?;
+    assert!(!output.status.success());
+    let lines = String::from_utf8(output.stdout)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<serde_json::Value>, _>>()?;
+    assert!(lines.iter().all(|l| l["schema_version"] == 1));
+    assert!(lines.iter().any(|l| l["type"] == "file"
+        && l["path"] == "secrets/b.env"
+        && l["action"] == "check"
+        && l["outcome"] == "not_encrypted"));
+    let result = lines.last().unwrap();
+    assert_eq!(result["type"], "result");
+    assert_eq!(result["ok"], false);
+    assert_eq!(result["error"]["kind"], "files_not_encrypted");
+
+    // Reports go in `report`.
+    let output = git_se("--format json st")?;
+    let doc: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    assert_eq!(doc["command"], "status");
+    assert_eq!(doc["report"]["files"].as_array().map(Vec::len), Some(2));
+    Ok(())
+}
 
-    // Case 3: stage a file not in crypt list → should pass (nothing to check)
-    exec("git add plain.txt", temp_dir).context("git add plain.txt")?;
+#[test]
+fn test_batch_report() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+    for name in ["a.env", "b.env", "c.env"] {
+        std::fs::write(temp_dir.join(name), format!("{name} content"))?;
+    }
+    run(
+        SubCommand::Add {
+            paths: vec!["*.env".into()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+    let repo = Repo::open(temp_dir)?;
+
+    let report = encrypt_repo(&repo, &[], &NoopObserver)?;
+    assert_eq!(report.action, FileAction::Encrypt);
+    let paths = report
+        .files
+        .iter()
+        .map(|f| f.path.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, ["a.env", "b.env", "c.env"].map(PathBuf::from));
+    assert!(report.files.iter().all(|f| f.outcome == FileOutcome::Ok));
+    assert_eq!(report.bytes_in(), 3 * 13);
+    assert!(report.bytes_out() > report.bytes_in());
+    assert!(report.result().is_ok());
+
+    // Every failure is reported with its own error, not just the first one.
+    for name in ["a.env", "b.env"] {
+        let path = temp_dir.join(name);
+        let data = fs::read(&path)?;
+        fs::write(&path, &data[..data.len() - 1])?;
+    }
+    let report = decrypt_repo(&repo, &[], &NoopObserver)?;
+    let summary = report.summary();
+    assert_eq!((summary.succeeded, summary.failed), (1, 2));
     assert!(
-        run(
-            SubCommand::Check {
-                paths: vec![],
-                staged: true
-            },
-            temp_dir
-        )
-        .is_ok(),
-        "staged file not in crypt list should pass check"
+        report.failures().all(|f| matches!(
+            f.error,
+            Some(Error::FileTruncated | Error::DecryptFailed(_))
+        )),
+        "{report:?}"
     );
+    assert!(matches!(
+        report.result(),
+        Err(Error::BatchFailed("decrypt", 2, 3))
+    ));
+    assert!(temp_dir.join("c.env").is_not_encrypted());
+    Ok(())
+}
 
-    // Clear index
-    exec("git rm --cached plain.txt", temp_dir).context("git rm --cached")?;
+/// Records the events it receives, in order.
+#[derive(Default)]
+struct Recorder(std::sync::Mutex<Vec<String>>);
 
-    // Case 4: nothing staged → should pass
-    assert!(
-        run(
-            SubCommand::Check {
-                paths: vec![],
-                staged: true
-            },
-            temp_dir
-        )
-        .is_ok(),
-        "nothing staged should pass check"
+impl Recorder {
+    fn push(&self, event: String) {
+        self.0.lock().unwrap().push(event);
+    }
+
+    fn events(&self) -> Vec<String> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+impl Observer for Recorder {
+    fn batch_started(&self, action: FileAction, files: &[PathBuf]) {
+        self.push(format!("start {} {}", action.as_str(), files.len()));
+    }
+
+    fn file_started(&self, path: &Path) {
+        self.push(format!("file {}", path.display()));
+    }
+
+    fn bytes_processed(&self, path: &Path, bytes: u64) {
+        self.push(format!("bytes {} {bytes}", path.display()));
+    }
+
+    fn file_finished(&self, report: &FileReport) {
+        self.push(format!("done {}", report.path.display()));
+    }
+
+    fn file_skipped(&self, report: &FileReport) {
+        self.push(format!("skip {}", report.path.display()));
+    }
+
+    fn file_failed(&self, report: &FileReport) {
+        self.push(format!("fail {}", report.path.display()));
+    }
+
+    fn batch_finished(&self, action: FileAction, summary: &Summary) {
+        self.push(format!(
+            "finish {} {}/{}/{}",
+            action.as_str(),
+            summary.succeeded,
+            summary.skipped,
+            summary.failed
+        ));
+    }
+}
+
+#[test]
+fn test_observer() -> anyhow::Result<()> {
+    let pwd = test_init();
+    let temp_dir = pwd.path();
+    std::fs::write(temp_dir.join("a.env"), "a.env content")?;
+    run(
+        SubCommand::Add {
+            paths: vec!["a.env".into()],
+            dry_run: false,
+        },
+        temp_dir,
+    )?;
+    let repo = Repo::open(temp_dir)?;
+    let recorder = Recorder::default();
+
+    encrypt_repo(&repo, &[], &recorder)?;
+    assert_eq!(
+        recorder.events(),
+        [
+            "start encrypt 1",
+            "file a.env",
+            "bytes a.env 13",
+            "done a.env",
+            "finish encrypt 1/0/0"
+        ]
+    );
+
+    encrypt_repo(&repo, &[], &recorder)?;
+    assert_eq!(
+        recorder.events(),
+        [
+            "start encrypt 1",
+            "file a.env",
+            "skip a.env",
+            "finish encrypt 0/1/0"
+        ]
     );
 
+    decrypt_repo(&repo, &[], &recorder)?;
+    repo.check(&[], false, &recorder)?;
+    assert_eq!(
+        recorder.events()[5..],
+        [
+            "start check 1",
+            "file a.env",
+            "fail a.env",
+            "finish check 0/0/1"
+        ]
+    );
     Ok(())
 }